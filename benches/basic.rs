@@ -3,10 +3,11 @@ use criterion::{
     criterion_main, 
     Criterion, 
     Throughput, 
-    BenchmarkId
+    BenchmarkId,
+    BatchSize
 };
 
-use nimix::Heap;
+use nimix::{Heap, HeapConfig};
 use std::alloc::Layout;
 use std::num::NonZero;
 
@@ -27,5 +28,156 @@ fn alloc_sizes(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, alloc_sizes);
+fn mark_one_block(c: &mut Criterion) {
+    let heap = Heap::new();
+    let layout = Layout::new::<[u64; 2]>();
+    let mark = NonZero::new(1u8).unwrap();
+
+    // enough objects to fill most of a single block
+    let objects: Vec<*mut u8> = (0..900)
+        .map(|_| unsafe { heap.alloc(layout).unwrap() })
+        .collect();
+
+    c.bench_function("mark one block", |b| {
+        b.iter(|| {
+            for obj in objects.iter() {
+                unsafe { Heap::mark(*obj, layout, mark).unwrap() };
+            }
+        })
+    });
+}
+
+// Leaves the heap with as many recycled blocks, each with every eighth line
+// marked, as free blocks.
+fn fragmented_heap(small_burst_blocks: usize) -> Heap {
+    let heap = Heap::with_config(HeapConfig {
+        small_burst_blocks,
+        ..HeapConfig::default()
+    });
+    let layout = Layout::new::<[u64; 16]>();
+    let mark = NonZero::new(1u8).unwrap();
+    let filler = heap.clone();
+
+    for i in 0..(64 * 126) {
+        let obj = unsafe { filler.alloc(layout).unwrap() };
+
+        if i % 8 == 0 && i < 32 * 126 {
+            unsafe { Heap::mark(obj, layout, mark).unwrap() };
+        }
+    }
+
+    drop(filler);
+    unsafe { heap.sweep(mark, || {}) };
+    heap
+}
+
+fn recycle_vs_free_first(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small burst");
+    let layout = Layout::new::<[u64; 2]>();
+    let default = HeapConfig::default().small_burst_blocks;
+
+    for (name, small_burst_blocks) in [("recycle first", usize::MAX), ("free first", 0), ("default", default)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || fragmented_heap(small_burst_blocks),
+                |heap| {
+                    for _ in 0..10_000 {
+                        unsafe { heap.alloc(layout).unwrap() };
+                    }
+
+                    heap
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+// Sweeps a heap of many blocks that each keep some objects alive, so the
+// blocks survive and are walked again by the next sweep.
+fn sweep_order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sweep order");
+    let layout = Layout::new::<[u64; 16]>();
+
+    for ordered in [false, true] {
+        let name = if ordered { "ordered" } else { "unordered" };
+        let heap = Heap::new().with_ordered_sweep(ordered);
+        let filler = heap.clone();
+        let mut live = vec![];
+
+        for i in 0..(2000 * 126) {
+            let obj = unsafe { filler.alloc(layout).unwrap() };
+
+            if i % 7 == 0 {
+                live.push(obj);
+            }
+        }
+
+        drop(filler);
+
+        let mut mark = 1u8;
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                mark = mark % 254 + 1;
+
+                let mark = NonZero::new(mark).unwrap();
+
+                for obj in live.iter() {
+                    unsafe { Heap::mark(*obj, layout, mark).unwrap() };
+                }
+
+                unsafe { heap.sweep(mark, || {}) };
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// Allocates through many blocks, with and without transparent huge pages
+// backing them.
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+fn huge_pages(c: &mut Criterion) {
+    use nimix::{HugePageBacking, MmapBacking};
+
+    let mut group = c.benchmark_group("huge pages");
+    let layout = Layout::new::<[u64; 4]>();
+    let count = 128 * 126 * 4;
+
+    group.throughput(Throughput::Elements(count as u64));
+
+    for huge in [false, true] {
+        let name = if huge { "huge pages" } else { "mmap" };
+
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    if huge {
+                        Heap::with_backing(HugePageBacking::new())
+                    } else {
+                        Heap::with_backing(MmapBacking)
+                    }
+                },
+                |heap| {
+                    for _ in 0..count {
+                        unsafe { heap.alloc(layout).unwrap().write_bytes(1, layout.size()) };
+                    }
+
+                    heap
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+#[cfg(not(all(feature = "mmap", target_os = "linux")))]
+fn huge_pages(_: &mut Criterion) {}
+
+criterion_group!(benches, alloc_sizes, mark_one_block, recycle_vs_free_first, sweep_order, huge_pages);
 criterion_main!(benches);
@@ -1,89 +1,318 @@
-use super::block_store::BlockStore;
+use super::block::BlockId;
+use super::block_store::{self, BlockStore, SweepStats};
+use super::constants;
 use super::bump_block::BumpBlock;
 use super::error::AllocError;
+use super::geometry::Geometry;
 use super::size_class::SizeClass;
 use std::alloc::Layout;
 use std::cell::Cell;
 use std::sync::Arc;
 use std::num::NonZero;
 
-pub struct AllocHead {
-    head: Cell<Option<BumpBlock>>,
-    overflow: Cell<Option<BumpBlock>>,
-    store: Arc<BlockStore>,
+/// Counts of how a heap handle's small and medium allocations were served.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FastPathStats {
+    /// Allocations that fit in the block the handle already held.
+    pub hits: usize,
+    /// Times the handle had to fetch another block to allocate into.
+    pub refreshes: usize,
 }
 
-impl Drop for AllocHead {
+pub struct AllocHead<const BLOCK_SIZE: usize = { constants::BLOCK_SIZE }> {
+    head: Cell<Option<BumpBlock<BLOCK_SIZE>>>,
+    overflow: Cell<Option<BumpBlock<BLOCK_SIZE>>>,
+    store: Arc<BlockStore<BLOCK_SIZE>>,
+    // the store's geometry, copied when the handle is made so allocations
+    // don't have to reach the store to classify an object
+    geometry: Cell<Geometry>,
+    // bytes handed out since they were last reported to the store
+    allocated: Cell<usize>,
+    stats: Cell<FastPathStats>,
+    // mark given to every object as soon as it is allocated
+    birth_color: Cell<Option<NonZero<u8>>>,
+    // blocks taken for small objects since the last medium allocation
+    small_burst: Cell<usize>,
+}
+
+impl<const BLOCK_SIZE: usize> Drop for AllocHead<BLOCK_SIZE> {
     fn drop(&mut self) {
-        self.flush()
+        self.flush();
+        self.store.remove_handle();
     }
 }
 
-impl Clone for AllocHead {
+impl<const BLOCK_SIZE: usize> Clone for AllocHead<BLOCK_SIZE> {
     fn clone(&self) -> Self {
+        self.store.add_handle();
+
         Self {
             head: Cell::new(None),
             overflow: Cell::new(None),
-            store: self.store.clone()
+            store: self.store.clone(),
+            geometry: Cell::new(self.store.geometry()),
+            allocated: Cell::new(0),
+            stats: Cell::new(FastPathStats::default()),
+            birth_color: Cell::new(None),
+            small_burst: Cell::new(0),
         }
     }
 }
 
-impl AllocHead {
-    pub const fn new(store: Arc<BlockStore>) -> Self {
+impl<const BLOCK_SIZE: usize> AllocHead<BLOCK_SIZE> {
+    pub fn new(store: Arc<BlockStore<BLOCK_SIZE>>) -> Self {
+        store.add_handle();
+
         Self {
             head: Cell::new(None),
             overflow: Cell::new(None),
+            geometry: Cell::new(store.geometry()),
             store,
+            allocated: Cell::new(0),
+            stats: Cell::new(FastPathStats { hits: 0, refreshes: 0 }),
+            birth_color: Cell::new(None),
+            small_burst: Cell::new(0),
         }
     }
 
     pub fn alloc(&self, layout: Layout) -> Result<*const u8, AllocError> {
-        let size_class = SizeClass::get_for_size(layout.size())?;
+        let size_class = self.geometry.get().size_class(layout)?;
+
+        let result = match size_class {
+            SizeClass::Small => self.small_alloc(layout.size(), |block| block.inner_alloc(layout)),
+            SizeClass::Medium => self.medium_alloc(layout.size(), |block| block.inner_alloc(layout)),
+            SizeClass::Large => self.store.create_large(layout),
+        };
+
+        self.report_alloc(result, layout)
+    }
+
+    pub fn alloc_zeroed(&self, layout: Layout) -> Result<*const u8, AllocError> {
+        let ptr = self.alloc(layout)?;
 
-        match size_class {
-            SizeClass::Small => self.small_alloc(layout),
-            SizeClass::Medium => self.medium_alloc(layout),
+        // holes in recycled blocks still hold whatever was swept out of them
+        unsafe { std::ptr::write_bytes(ptr as *mut u8, 0, layout.size()) };
+
+        Ok(ptr)
+    }
+
+    // Allocates without any alignment, each object starts right where the
+    // previous one ended.
+    pub fn alloc_packed(&self, size: usize) -> Result<*const u8, AllocError> {
+        let layout = Layout::from_size_align(size, 1)?;
+
+        let result = match self.geometry.get().size_class_of(size)? {
+            SizeClass::Small => self.small_alloc(size, |block| block.inner_alloc_packed(size)),
+            SizeClass::Medium => self.medium_alloc(size, |block| block.inner_alloc_packed(size)),
             SizeClass::Large => self.store.create_large(layout),
+        };
+
+        self.report_alloc(result, layout)
+    }
+
+    // Moves the object to a new allocation of `new_size` bytes, copying over as
+    // much of it as fits, unless it shrinks within its size class. The old
+    // object is freed once copied, see BlockStore::free_moved.
+    pub unsafe fn realloc(&self, ptr: *const u8, old_layout: Layout, new_size: usize) -> Result<*const u8, AllocError> {
+        let new_layout = Layout::from_size_align(new_size, old_layout.align())?;
+        let geometry = self.geometry.get();
+        let size_class = geometry.size_class(new_layout)?;
+
+        // a shrunk object can stay where it is, so long as it is still marked
+        // as the same kind of object
+        if new_size <= old_layout.size() && size_class == geometry.size_class(old_layout)? {
+            return Ok(ptr);
+        }
+
+        // an unknown pointer fails before anything is allocated
+        let record = self.store.find_moved(ptr)?;
+
+        if size_class == SizeClass::Medium {
+            self.prefer_recycled_overflow(new_size);
+        }
+
+        let new_ptr = self.alloc(new_layout)?;
+
+        std::ptr::copy_nonoverlapping(ptr, new_ptr as *mut u8, old_layout.size().min(new_size));
+        self.store.free_moved(ptr, old_layout, record)?;
+
+        Ok(new_ptr)
+    }
+
+    // When the overflow block can't hold `size` bytes, switches to a recycled
+    // block that can, so the reallocation fills an existing hole rather than
+    // taking a fresh block.
+    fn prefer_recycled_overflow(&self, size: usize) {
+        let fits = Self::hole_fits(&self.overflow, size);
+
+        if fits || self.store.is_deterministic() {
+            return;
+        }
+
+        if let Some(block) = self.store.take_recycled_fitting(size) {
+            self.store.add_used(self.allocated.take());
+
+            if let Some(old) = self.overflow.replace(Some(block)) {
+                self.store.recycle(old);
+            }
+        }
+    }
+
+    fn hole_fits(slot: &Cell<Option<BumpBlock<BLOCK_SIZE>>>, size: usize) -> bool {
+        let block = slot.take();
+        let fits = block.as_ref().is_some_and(|block| block.current_hole_size() >= size);
+
+        slot.set(block);
+        fits
+    }
+
+    fn report_alloc(&self, result: Result<*const u8, AllocError>, layout: Layout) -> Result<*const u8, AllocError> {
+        if let Err(AllocError::OOM) = result {
+            if self.store.panics_on_oom() {
+                panic!(
+                    "out of memory allocating {} bytes (heap size {} bytes, {} bytes used)",
+                    layout.size(),
+                    self.get_size(),
+                    self.get_used(),
+                );
+            }
         }
+
+        let ptr = result?;
+
+        if let Some(color) = self.birth_color.get() {
+            unsafe { block_store::mark_object(ptr as *mut u8, layout, color)? };
+        }
+
+        #[cfg(feature = "track-allocations")]
+        self.store.record_allocation(ptr, layout.size());
+
+        if let Some(observer) = self.store.observer() {
+            observer.on_alloc(ptr, layout);
+        }
+
+        Ok(ptr)
     }
 
-    pub unsafe fn sweep(&self, mark: NonZero<u8>, cb: impl FnOnce()) {
-        self.store.sweep(mark.into(), cb);
+    pub unsafe fn sweep(&self, mark: NonZero<u8>, cb: impl FnOnce()) -> (SweepStats, Vec<BlockId>) {
+        self.store.sweep(mark, cb)
+    }
+
+    pub unsafe fn sweep_retaining(
+        &self,
+        mark: NonZero<u8>,
+        cb: impl FnOnce(),
+        keep: impl Fn(*const u8) -> bool,
+    ) -> (SweepStats, Vec<BlockId>) {
+        self.store.sweep_retaining(mark, cb, keep)
     }
 
     pub fn get_size(&self) -> usize {
         self.store.get_size()
     }
 
-    fn small_alloc(&self, layout: Layout) -> Result<*const u8, AllocError> {
+    pub fn get_used(&self) -> usize {
+        self.store.get_used() + self.allocated.get()
+    }
+
+    // how many handles, including this one, allocate from the store
+    pub fn handle_count(&self) -> usize {
+        self.store.handle_count()
+    }
+
+    pub fn get_store_arc(&self) -> Arc<BlockStore<BLOCK_SIZE>> {
+        self.store.clone()
+    }
+
+    pub fn get_store(&self) -> &BlockStore<BLOCK_SIZE> {
+        &self.store
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry.get()
+    }
+
+    // Only this handle may be allocating from the store, see
+    // BlockStore::set_line_size, so no other copy of the geometry goes stale.
+    pub fn set_line_size(&self, line_size: usize) -> Result<(), AllocError> {
+        self.store.set_line_size(line_size)?;
+        self.geometry.set(self.store.geometry());
+
+        Ok(())
+    }
+
+    pub fn set_birth_color(&self, color: Option<NonZero<u8>>) {
+        self.birth_color.set(color);
+    }
+
+    pub fn get_stats(&self) -> FastPathStats {
+        self.stats.get()
+    }
+
+    fn small_alloc(
+        &self,
+        size: usize,
+        alloc: impl Fn(&mut BumpBlock<BLOCK_SIZE>) -> Option<*const u8>,
+    ) -> Result<*const u8, AllocError> {
+        let mut refreshed = false;
+
         loop {
-            if let Some(ptr) = self.head_alloc(layout) {
+            if let Some(ptr) = Self::block_alloc(&self.head, &alloc) {
+                self.allocated.set(self.allocated.get() + size);
+                self.record_alloc(refreshed);
+                self.store.count_alloc(SizeClass::Small);
                 return Ok(ptr);
             }
 
             self.get_new_head()?;
+            refreshed = true;
         }
     }
 
-    fn medium_alloc(&self, layout: Layout) -> Result<*const u8, AllocError> {
+    fn medium_alloc(
+        &self,
+        size: usize,
+        alloc: impl Fn(&mut BumpBlock<BLOCK_SIZE>) -> Option<*const u8>,
+    ) -> Result<*const u8, AllocError> {
+        let mut refreshed = false;
+
         loop {
-            if let Some(space) = self.overflow_alloc(layout) {
+            if let Some(space) = Self::block_alloc(&self.overflow, &alloc) {
+                self.allocated.set(self.allocated.get() + size);
+                self.record_alloc(refreshed);
+                self.store.count_alloc(SizeClass::Medium);
+                self.small_burst.set(0);
                 return Ok(space);
             }
 
             self.get_new_overflow()?;
+            refreshed = true;
+        }
+    }
+
+    fn record_alloc(&self, refreshed: bool) {
+        let mut stats = self.stats.get();
+
+        if refreshed {
+            stats.refreshes += 1;
+        } else {
+            stats.hits += 1;
         }
+
+        self.stats.set(stats);
     }
 
     fn get_new_head(&self) -> Result<(), AllocError> {
         let new_head = match self.overflow.take() {
             Some(block) => block,
-            None => self.store.get_head()?,
+            None => self.store.get_burst_head(self.small_burst.get())?,
         };
 
+        self.small_burst.set(self.small_burst.get() + 1);
+
         let rest_block = self.head.take();
         self.head.set(Some(new_head));
+        self.store.add_used(self.allocated.take());
 
         if let Some(block) = rest_block {
             self.store.rest(block);
@@ -97,6 +326,7 @@ impl AllocHead {
         let recycle_block = self.overflow.take();
 
         self.overflow.set(Some(new_overflow));
+        self.store.add_used(self.allocated.take());
 
         if let Some(block) = recycle_block {
             self.store.recycle(block);
@@ -105,29 +335,23 @@ impl AllocHead {
         Ok(())
     }
 
-    fn head_alloc(&self, layout: Layout) -> Option<*const u8> {
-        match self.head.take() {
-            Some(mut head) => {
-                let result = head.inner_alloc(layout);
-                self.head.set(Some(head));
+    fn block_alloc(
+        slot: &Cell<Option<BumpBlock<BLOCK_SIZE>>>,
+        alloc: impl Fn(&mut BumpBlock<BLOCK_SIZE>) -> Option<*const u8>,
+    ) -> Option<*const u8> {
+        match slot.take() {
+            Some(mut block) => {
+                let result = alloc(&mut block);
+                slot.set(Some(block));
                 result
             }
             None => None,
         }
     }
 
-    fn overflow_alloc(&self, layout: Layout) -> Option<*const u8> {
-        match self.overflow.take() {
-            Some(mut overflow) => {
-                let result = overflow.inner_alloc(layout);
-                self.overflow.set(Some(overflow));
-                result
-            }
-            None => None,
-        }
-    }
+    pub fn flush(&self)  {
+        self.store.add_used(self.allocated.take());
 
-    fn flush(&self)  {
         if let Some(head) = self.head.take() {
             self.store.recycle(head);
         }
@@ -0,0 +1,54 @@
+use super::Heap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+thread_local! {
+    // this thread's handle for each heap it has allocated from, keyed by the
+    // address of the heap's block store
+    static HANDLES: RefCell<HashMap<usize, Rc<Heap>>> = RefCell::new(HashMap::new());
+}
+
+/// A per thread cache of heap handles, for threads that allocate from several
+/// heaps. Each heap gets one handle per thread, created the first time the
+/// thread uses it, so the thread holds at most one partially used block per
+/// heap. The handles, and with them any blocks they hold, are handed back when
+/// the thread exits or [`AllocatorCache::flush`] is called.
+///
+/// A cached handle keeps its heap alive until then, and the blocks it holds
+/// are not swept, as with any other clone of the heap.
+pub struct AllocatorCache;
+
+impl AllocatorCache {
+    /// Calls `f` with this thread's handle for `heap`.
+    pub fn with<R>(heap: &Heap, f: impl FnOnce(&Heap) -> R) -> R {
+        let handle = HANDLES.with(|handles| {
+            handles
+                .borrow_mut()
+                .entry(heap.store_id())
+                .or_insert_with(|| Rc::new(heap.clone()))
+                .clone()
+        });
+
+        f(&handle)
+    }
+
+    // Drops this thread's handle for the heap with the store id given, if it
+    // has one.
+    pub(crate) fn forget(store_id: usize) {
+        let handle = HANDLES
+            .try_with(|handles| handles.borrow_mut().remove(&store_id))
+            .ok()
+            .flatten();
+
+        drop(handle);
+    }
+
+    /// Drops every handle cached by this thread, handing their blocks back
+    /// to their heaps.
+    pub fn flush() {
+        let handles = HANDLES.with(|handles| handles.take());
+
+        drop(handles);
+    }
+}
@@ -0,0 +1,71 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ptr;
+use std::sync::{Arc, OnceLock};
+
+/// Provides the memory blocks and large objects are carved out of, see
+/// [`Heap::with_backing`].
+///
+/// [`Heap::with_backing`]: crate::Heap::with_backing
+pub trait Backing: Send + Sync {
+    /// Allocates memory for `layout`, returning null on failure. `layout` is
+    /// never zero sized.
+    fn alloc(&self, layout: Layout) -> *mut u8;
+
+    /// Frees memory returned by [`Backing::alloc`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by a call to `alloc` on this backing with
+    /// the same `layout`, and must not have been freed yet.
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout);
+
+    /// Like [`Backing::alloc`], but the memory reads back as zeroes.
+    fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+
+        if !ptr.is_null() {
+            unsafe { ptr::write_bytes(ptr, 0, layout.size()) };
+        }
+
+        ptr
+    }
+}
+
+/// The backing heaps use by default, the system allocator.
+///
+/// The system allocator is called directly rather than through the global
+/// allocator, so when the global allocator is a [`NimixGlobal`] the blocks of
+/// other heaps don't become objects of its heap, and are handed back to the
+/// system as soon as they are released.
+///
+/// [`NimixGlobal`]: crate::NimixGlobal
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemBacking;
+
+impl Backing for SystemBacking {
+    fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { System.alloc_zeroed(layout) }
+    }
+}
+
+// The system backing shared by every heap that doesn't have one of its own.
+pub fn system() -> Arc<dyn Backing> {
+    static SYSTEM: OnceLock<Arc<dyn Backing>> = OnceLock::new();
+
+    SYSTEM.get_or_init(|| Arc::new(SystemBacking)).clone()
+}
+
+// Whether `backing` is the system backing shared by heaps without one of
+// their own.
+#[cfg(feature = "block-pool")]
+pub fn is_system(backing: &Arc<dyn Backing>) -> bool {
+    Arc::ptr_eq(backing, &system())
+}
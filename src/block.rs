@@ -1,25 +1,106 @@
-use super::constants::BLOCK_SIZE;
+use super::backing::Backing;
+use super::constants;
 use super::error::AllocError;
-use std::alloc::{alloc, dealloc, Layout};
+use std::alloc::Layout;
+#[cfg(feature = "block-pool")]
+use std::mem::ManuallyDrop;
+#[cfg(feature = "block-pool")]
+use std::ptr;
 use std::ptr::NonNull;
+use std::sync::Arc;
 
-pub struct Block {
+/// Identifies a block by the address it starts at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(usize);
+
+impl BlockId {
+    pub(crate) fn new(ptr: *const u8) -> Self {
+        Self(ptr as usize)
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0 as *const u8
+    }
+}
+
+// Blocks a heap bump allocates into are BLOCK_SIZE bytes, aligned to at least
+// their size. Large objects get a block sized to fit them instead, which is
+// never rounded down to.
+pub struct Block<const BLOCK_SIZE: usize = { constants::BLOCK_SIZE }> {
     ptr: NonNull<u8>,
     layout: Layout,
+    // where the block came from, blocks carved out of a caller provided region
+    // have none as they are not ours to free
+    backing: Option<Arc<dyn Backing>>,
 }
 
+// SAFETY: a block owns the memory behind `ptr`, nothing else frees it or hands
+// it out while the block is alive, so moving the block to another thread moves
+// that ownership with it. The backing is Send + Sync, and dropping the block on
+// any thread only clears its slot in the directories, which are atomic. Block
+// is not Sync, anything shared between threads through a block is reached
+// through the atomic mark bytes of its metadata.
+unsafe impl<const BLOCK_SIZE: usize> Send for Block<BLOCK_SIZE> {}
+
 impl Block {
+    #[cfg(test)]
     pub fn default() -> Result<Block, AllocError> {
-        let layout = Layout::from_size_align(BLOCK_SIZE, BLOCK_SIZE).unwrap();
+        Self::aligned(constants::BLOCK_SIZE, &super::backing::system())
+    }
+
+    pub fn new(layout: Layout, backing: &Arc<dyn Backing>) -> Result<Block, AllocError> {
+        Self::from_raw(backing.alloc(layout), layout, backing)
+    }
+}
+
+impl<const BLOCK_SIZE: usize> Block<BLOCK_SIZE> {
+    // Blocks must stay aligned to at least their size for BlockMeta::from_ptr
+    // to find them, so only larger alignments are accepted.
+    pub fn layout_aligned_to(align: usize) -> Result<Layout, AllocError> {
+        if align < BLOCK_SIZE {
+            return Err(AllocError::LayoutError);
+        }
+
+        Ok(Layout::from_size_align(BLOCK_SIZE, align)?)
+    }
+
+    pub fn aligned(align: usize, backing: &Arc<dyn Backing>) -> Result<Self, AllocError> {
+        let layout = Self::layout_aligned_to(align)?;
 
-        Self::new(layout)
+        Self::from_raw(backing.alloc(layout), layout, backing)
     }
 
-    pub fn new(layout: Layout) -> Result<Block, AllocError> {
-        Ok(Block {
-            ptr: Self::alloc_block(layout)?,
+    pub fn zeroed(align: usize, backing: &Arc<dyn Backing>) -> Result<Self, AllocError> {
+        let layout = Self::layout_aligned_to(align)?;
+
+        Self::from_raw(backing.alloc_zeroed(layout), layout, backing)
+    }
+
+    fn from_raw(ptr: *mut u8, layout: Layout, backing: &Arc<dyn Backing>) -> Result<Self, AllocError> {
+        match NonNull::new(ptr) {
+            Some(ptr) => Ok(Block {
+                ptr,
+                layout,
+                backing: Some(backing.clone()),
+            }),
+            None => Err(AllocError::OOM),
+        }
+    }
+
+    // SAFETY: ptr must be valid for BLOCK_SIZE bytes, aligned to BLOCK_SIZE, and
+    // must outlive the block
+    pub unsafe fn borrowed(ptr: NonNull<u8>) -> Self {
+        let layout = Layout::from_size_align(BLOCK_SIZE, BLOCK_SIZE).unwrap();
+
+        Block {
+            ptr,
             layout,
-        })
+            backing: None,
+        }
+    }
+
+    pub fn is_owned(&self) -> bool {
+        self.backing.is_some()
     }
 
     pub fn as_ptr(&self) -> *const u8 {
@@ -30,18 +111,32 @@ impl Block {
         self.layout.size()
     }
 
-    fn alloc_block(layout: Layout) -> Result<NonNull<u8>, AllocError> {
-        let ptr = unsafe { alloc(layout) };
+    // Blocks of every size wait in the block pool together, as blocks of the
+    // default size. The layout goes along with the memory, so a block is only
+    // handed out again at the size it was made with.
+    #[cfg(feature = "block-pool")]
+    pub fn resized<const SIZE: usize>(self) -> Block<SIZE> {
+        let block = ManuallyDrop::new(self);
 
-        match NonNull::new(ptr) {
-            Some(ptr) => Ok(ptr),
-            None => Err(AllocError::OOM),
+        Block {
+            ptr: block.ptr,
+            layout: block.layout,
+            // SAFETY: the block is never dropped, so the backing is only moved
+            backing: unsafe { ptr::read(&block.backing) },
         }
     }
 }
 
-impl Drop for Block {
+impl<const BLOCK_SIZE: usize> Drop for Block<BLOCK_SIZE> {
     fn drop(&mut self) {
-        unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+        super::block_directory::remove(self.as_ptr());
+
+        // blocks carved out of a region have metadata of their own as well
+        #[cfg(feature = "side-meta")]
+        super::side_meta::remove(self.as_ptr());
+
+        if let Some(backing) = self.backing.as_ref() {
+            unsafe { backing.dealloc(self.ptr.as_ptr(), self.layout) }
+        }
     }
 }
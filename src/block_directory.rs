@@ -0,0 +1,463 @@
+use super::constants::MIN_BLOCK_SIZE;
+use super::directory::Directory;
+use super::error::AllocError;
+use super::geometry::Geometry;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ops::RangeInclusive;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Records which stretches of the address space hold a block, and what can be
+// told about each block without taking a lock. Every stretch a block spans
+// points at the record of the block, so the block can be found from any
+// address in it. Records are kept for good once made, so one can be read for
+// any address without racing the release of its block, and is taken over by
+// whichever block of a like number of lines is placed in a record next.
+static DIRECTORY: Directory<AtomicPtr<Record>> = Directory::new();
+
+// Records of released blocks, by the log of the words in each of their
+// bitmaps. Like the directory's chunks, records come from the system
+// allocator, and the lists are linked through the records themselves, so
+// nothing here allocates through a global allocator that may be a heap.
+static POOL: Mutex<[FreeList; WORD_CLASSES]> = Mutex::new([const { FreeList(ptr::null_mut()) }; WORD_CLASSES]);
+
+const WORD_CLASSES: usize = usize::BITS as usize;
+
+// the shape of a block is the log of its size, and above it that of its lines
+const LINE_SHIFT: u32 = 8;
+
+// the state of a record with a block in it, whose owner lies above the flags
+const PRESENT: usize = 1;
+// set while the block sits on its owner's free list
+const FREE: usize = 2;
+const OWNER_SHIFT: u32 = 2;
+
+struct FreeList(*mut Record);
+
+// SAFETY: the records on the lists are reached through the lock alone
+unsafe impl Send for FreeList {}
+
+// A record is followed by two bitmaps of `words` words each, those of the
+// lines in which a medium object starts, and those in which one ends. Medium
+// objects take up more than a line, so no two start or end in the same line,
+// and an object ends in a later line than it starts in.
+struct Record {
+    // the block in the record and its shape, zero while it holds none
+    base: AtomicUsize,
+    shape: AtomicUsize,
+    state: AtomicUsize,
+    words: usize,
+    next: *mut Record,
+}
+
+impl Record {
+    fn layout(words: usize) -> (Layout, usize) {
+        let bits = Layout::array::<AtomicU64>(2 * words).unwrap();
+        let (layout, offset) = Layout::new::<Record>().extend(bits).unwrap();
+
+        (layout.pad_to_align(), offset)
+    }
+
+    // Takes a released record with room for `words` words in each bitmap, or
+    // makes a new one.
+    fn take(words: usize) -> Result<&'static Record, AllocError> {
+        let class = words.trailing_zeros() as usize;
+        let mut pool = POOL.lock().unwrap();
+        let record = pool[class].0;
+
+        if !record.is_null() {
+            // SAFETY: records are never freed
+            pool[class].0 = unsafe { (*record).next };
+
+            return Ok(unsafe { &*record });
+        }
+
+        drop(pool);
+
+        let (layout, _) = Self::layout(words);
+        let record = unsafe { System.alloc_zeroed(layout) } as *mut Record;
+
+        if record.is_null() {
+            return Err(AllocError::OOM);
+        }
+
+        unsafe {
+            record.write(Record {
+                base: AtomicUsize::new(0),
+                shape: AtomicUsize::new(0),
+                state: AtomicUsize::new(0),
+                words,
+                next: ptr::null_mut(),
+            })
+        };
+
+        Ok(unsafe { &*record })
+    }
+
+    fn give(record: &'static Record) {
+        let record = record as *const Record as *mut Record;
+        let mut pool = POOL.lock().unwrap();
+        // SAFETY: the record is no longer in the directory, and `next` is
+        // only touched with the pool locked
+        let class = unsafe { (*record).words.trailing_zeros() as usize };
+
+        unsafe { (*record).next = pool[class].0 };
+        pool[class].0 = record;
+    }
+
+    fn bits(&self, ends: bool) -> &[AtomicU64] {
+        let (_, offset) = Self::layout(self.words);
+        let start = ends as usize * self.words;
+
+        // SAFETY: the bitmaps were allocated along with the record
+        unsafe {
+            let bits = (self as *const Record as *const u8).add(offset) as *const AtomicU64;
+
+            std::slice::from_raw_parts(bits.add(start), self.words)
+        }
+    }
+}
+
+// The record of a block.
+#[derive(Clone, Copy)]
+pub struct BlockRecord {
+    record: &'static Record,
+    base: usize,
+    geometry: Geometry,
+}
+
+impl BlockRecord {
+    pub fn base(&self) -> *const u8 {
+        self.base as *const u8
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    // Records that the block belongs to the store with the id given, or to
+    // none, as is the case for blocks waiting in the block pool.
+    pub fn set_owner(&self, owner: Option<usize>) {
+        let owner = owner.map_or(0, |id| id << OWNER_SHIFT);
+
+        self.record.state.store(PRESENT | owner, Ordering::Release);
+    }
+
+    pub fn is_owned_by(&self, owner: usize) -> bool {
+        self.record.state.load(Ordering::Acquire) & !FREE == PRESENT | owner << OWNER_SHIFT
+    }
+
+    pub fn set_free(&self, free: bool) {
+        if free {
+            self.record.state.fetch_or(FREE, Ordering::Release);
+        } else {
+            self.record.state.fetch_and(!FREE, Ordering::Release);
+        }
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.record.state.load(Ordering::Acquire) & FREE != 0
+    }
+
+    // Records a medium object taking up the lines from `first` to `last`.
+    pub fn record_medium(&self, first: usize, last: usize) {
+        debug_assert!(first < last && last < self.geometry.line_count());
+
+        set_bit(self.record.bits(false), first);
+        set_bit(self.record.bits(true), last);
+    }
+
+    // Forgets the medium object taking up the lines from `first` to `last`,
+    // once it has been moved elsewhere.
+    pub fn unrecord_medium(&self, first: usize, last: usize) {
+        debug_assert!(first < last && last < self.geometry.line_count());
+
+        clear_bit(self.record.bits(false), first);
+        clear_bit(self.record.bits(true), last);
+    }
+
+    // Forgets the medium objects starting or ending in a line `is_free` holds
+    // for. A live medium object has every one of its lines marked, so only
+    // dead ones are forgotten this way.
+    pub fn forget_medium(&self, is_free: impl Fn(usize) -> bool) {
+        let line_count = self.geometry.line_count();
+        let (starts, ends) = (self.record.bits(false), self.record.bits(true));
+
+        for word in 0..line_count.div_ceil(64) {
+            let free = (0..64)
+                .filter(|bit| word * 64 + bit < line_count && is_free(word * 64 + bit))
+                .fold(0u64, |free, bit| free | 1 << bit);
+
+            starts[word].fetch_and(!free, Ordering::Relaxed);
+            ends[word].fetch_and(!free, Ordering::Relaxed);
+        }
+    }
+
+    // Calls `f` with the lines of each recorded medium object that takes up
+    // `line`. The object starting latest at or before the line is paired with
+    // the first end past its start, an object ending in the line it starts in
+    // can only be the one before it.
+    pub fn for_each_medium(&self, line: usize, mut f: impl FnMut(RangeInclusive<usize>)) {
+        let (starts, ends) = (self.record.bits(false), self.record.bits(true));
+        let mut next = Some(line);
+
+        while let Some(start) = next.and_then(|line| last_bit_at_or_before(starts, line)) {
+            let Some(end) = first_bit_after(ends, start) else {
+                return;
+            };
+
+            if end < line {
+                return;
+            }
+
+            f(start..=end);
+            next = start.checked_sub(1);
+        }
+    }
+}
+
+fn set_bit(words: &[AtomicU64], bit: usize) {
+    words[bit / 64].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+}
+
+fn clear_bit(words: &[AtomicU64], bit: usize) {
+    words[bit / 64].fetch_and(!(1 << (bit % 64)), Ordering::Relaxed);
+}
+
+fn last_bit_at_or_before(words: &[AtomicU64], bit: usize) -> Option<usize> {
+    let mut word = bit / 64;
+    let mut bits = words[word].load(Ordering::Relaxed) & (u64::MAX >> (63 - bit % 64));
+
+    loop {
+        if bits != 0 {
+            return Some(word * 64 + 63 - bits.leading_zeros() as usize);
+        }
+
+        word = word.checked_sub(1)?;
+        bits = words[word].load(Ordering::Relaxed);
+    }
+}
+
+fn first_bit_after(words: &[AtomicU64], bit: usize) -> Option<usize> {
+    let mut word = (bit + 1) / 64;
+    let mut bits = words.get(word)?.load(Ordering::Relaxed) & (u64::MAX << ((bit + 1) % 64));
+
+    loop {
+        if bits != 0 {
+            return Some(word * 64 + bits.trailing_zeros() as usize);
+        }
+
+        word += 1;
+        bits = words.get(word)?.load(Ordering::Relaxed);
+    }
+}
+
+fn shape(geometry: Geometry) -> usize {
+    geometry.block_size().trailing_zeros() as usize | (geometry.line_size().trailing_zeros() as usize) << LINE_SHIFT
+}
+
+fn geometry_of(shape: usize) -> Geometry {
+    let block_size = 1 << (shape & ((1 << LINE_SHIFT) - 1));
+    let line_size = 1 << (shape >> LINE_SHIFT);
+
+    Geometry::new(block_size, line_size).expect("recorded block geometry is valid")
+}
+
+// Places a block in the directory, pointing every stretch it spans at a
+// record of the block, which starts out owned by no store and with nothing
+// recorded.
+pub fn insert(block: *const u8, geometry: Geometry) -> Result<BlockRecord, AllocError> {
+    let stretches = geometry.block_size() / MIN_BLOCK_SIZE;
+
+    debug_assert!(block as usize % geometry.block_size() == 0);
+
+    // the chunks of every stretch are made before anything is taken, so a
+    // failure leaves nothing behind
+    for index in 0..stretches {
+        DIRECTORY.get_or_insert(block.wrapping_add(index * MIN_BLOCK_SIZE))?;
+    }
+
+    let record = Record::take(geometry.line_count().div_ceil(64).next_power_of_two())?;
+
+    for word in record.bits(false).iter().chain(record.bits(true)) {
+        word.store(0, Ordering::Relaxed);
+    }
+
+    record.base.store(block as usize, Ordering::Relaxed);
+    record.shape.store(shape(geometry), Ordering::Relaxed);
+
+    let block_record = BlockRecord { record, base: block as usize, geometry };
+
+    block_record.set_owner(None);
+
+    // only the owner of the block inserts it, so nothing races this
+    for index in 0..stretches {
+        let slot = DIRECTORY.get(block.wrapping_add(index * MIN_BLOCK_SIZE)).expect("chunk was made above");
+
+        slot.store(record as *const Record as *mut Record, Ordering::Release);
+    }
+
+    Ok(block_record)
+}
+
+pub fn remove(block: *const u8) {
+    let Some(record) = get(block).filter(|record| record.base() == block) else {
+        return;
+    };
+
+    for index in 0..record.geometry.block_size() / MIN_BLOCK_SIZE {
+        if let Some(slot) = DIRECTORY.get(block.wrapping_add(index * MIN_BLOCK_SIZE)) {
+            slot.store(ptr::null_mut(), Ordering::Release);
+        }
+    }
+
+    record.record.state.store(0, Ordering::Release);
+    record.record.base.store(0, Ordering::Release);
+    record.record.shape.store(0, Ordering::Release);
+    Record::give(record.record);
+}
+
+// The record of the block holding `addr`, its metadata included, if a block
+// is placed there.
+pub fn get(addr: *const u8) -> Option<BlockRecord> {
+    let record = DIRECTORY.get(addr)?.load(Ordering::Acquire);
+    // SAFETY: records are never freed
+    let record = unsafe { record.as_ref() }?;
+    let base = record.base.load(Ordering::Acquire);
+    let shape = record.shape.load(Ordering::Acquire);
+
+    if shape == 0 {
+        return None;
+    }
+
+    let geometry = geometry_of(shape);
+
+    // the record may have been taken over by a block elsewhere since
+    ((addr as usize).wrapping_sub(base) < geometry.block_size()).then_some(BlockRecord { record, base, geometry })
+}
+
+// Whether `addr` lies anywhere within a recorded block, its metadata included.
+pub fn contains(addr: *const u8) -> bool {
+    get(addr).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::constants::BLOCK_SIZE;
+    use std::alloc::Layout;
+    use std::ptr::NonNull;
+
+    #[test]
+    fn blocks_are_recorded_until_dropped() {
+        let layout = Layout::from_size_align(BLOCK_SIZE, BLOCK_SIZE).unwrap();
+        let memory = NonNull::new(unsafe { std::alloc::alloc(layout) }).unwrap();
+        let last = unsafe { memory.as_ptr().add(BLOCK_SIZE - 1) };
+        let block = unsafe { Block::<BLOCK_SIZE>::borrowed(memory) };
+
+        assert!(!contains(memory.as_ptr()));
+
+        insert(block.as_ptr(), Geometry::DEFAULT).unwrap();
+        assert!(contains(memory.as_ptr()));
+        assert!(contains(last));
+
+        // the memory is still ours, so no other block can have taken its place
+        drop(block);
+        assert!(!contains(memory.as_ptr()));
+
+        unsafe { std::alloc::dealloc(memory.as_ptr(), layout) };
+    }
+
+    #[test]
+    fn ownership_ignores_the_free_flag() {
+        let block = Block::default().unwrap();
+        let record = insert(block.as_ptr(), Geometry::DEFAULT).unwrap();
+
+        assert!(!record.is_owned_by(7));
+
+        record.set_owner(Some(7));
+        record.set_free(true);
+
+        assert!(record.is_owned_by(7));
+        assert!(!record.is_owned_by(8));
+        assert!(record.is_free());
+
+        record.set_free(false);
+
+        assert!(!record.is_free());
+    }
+
+    #[test]
+    fn medium_objects_are_found_from_any_of_their_lines() {
+        let block = Block::default().unwrap();
+        let record = insert(block.as_ptr(), Geometry::DEFAULT).unwrap();
+        let mut found = vec![];
+
+        // one object ends in the line the next one starts in
+        record.record_medium(2, 4);
+        record.record_medium(4, 6);
+        record.record_medium(8, 9);
+
+        for line in 0..11 {
+            record.for_each_medium(line, |lines| found.push((line, lines)));
+        }
+
+        assert_eq!(
+            found,
+            [(2, 2..=4), (3, 2..=4), (4, 4..=6), (4, 2..=4), (5, 4..=6), (6, 4..=6), (8, 8..=9), (9, 8..=9)]
+        );
+
+        // the first object's lines are freed, but for the one it shares
+        record.forget_medium(|line| line < 4);
+        found.clear();
+        record.for_each_medium(4, |lines| found.push((4, lines)));
+
+        assert_eq!(found, [(4, 4..=6)]);
+
+        // a moved object is forgotten on its own
+        record.unrecord_medium(8, 9);
+        found.clear();
+        record.for_each_medium(9, |lines| found.push((9, lines)));
+
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn larger_blocks_are_found_from_any_stretch() {
+        let geometry = Geometry::new(256 * 1024, 64).unwrap();
+        let layout = Layout::from_size_align(geometry.block_size(), geometry.block_size()).unwrap();
+        let block = Block::new(layout, &crate::backing::system()).unwrap();
+        let record = insert(block.as_ptr(), geometry).unwrap();
+        let last = geometry.line_count() - 1;
+        let mut found = vec![];
+
+        // an object spanning several stretches, and one in the last lines
+        record.record_medium(60, 200);
+        record.record_medium(last - 1, last);
+
+        for line in [60, 130, 200, last] {
+            let addr = unsafe { block.as_ptr().add(line * 64) };
+            let record = get(addr).unwrap();
+
+            assert_eq!(record.base(), block.as_ptr());
+            assert_eq!(record.geometry(), geometry);
+
+            record.for_each_medium(line, |lines| found.push((line, lines)));
+        }
+
+        assert_eq!(found, [(60, 60..=200), (130, 60..=200), (200, 60..=200), (last, last - 1..=last)]);
+
+        drop(block);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn addresses_past_the_directory_are_rejected() {
+        let block = (usize::MAX & !(BLOCK_SIZE - 1)) as *const u8;
+
+        assert!(matches!(insert(block, Geometry::DEFAULT), Err(AllocError::OOM)));
+        assert!(!contains(block));
+    }
+}
@@ -0,0 +1,84 @@
+use super::block::BlockId;
+use super::block_meta::BlockMeta;
+use super::constants::FREE_MARK;
+use super::error::AllocError;
+
+/// The state of a block's metadata, as returned by [`Heap::block_for`].
+///
+/// The handle is a copy taken when it was created, it does not change as
+/// objects are allocated in or marked in the block, and remains valid to use
+/// after the block has been swept or freed.
+///
+/// [`Heap::block_for`]: crate::Heap::block_for
+#[derive(Debug, Clone)]
+pub struct BlockHandle {
+    id: BlockId,
+    block_mark: u8,
+    age: u8,
+    lines: Box<[u8]>,
+    line_size: usize,
+}
+
+impl BlockHandle {
+    // SAFETY: ptr must be the start of a block owned by a heap
+    pub(crate) unsafe fn new(ptr: *const u8) -> Result<Self, AllocError> {
+        let meta = BlockMeta::from_ptr(ptr)?;
+        let geometry = meta.geometry();
+        let lines = (0..geometry.line_count()).map(|index| meta.get_line(index)).collect();
+
+        Ok(Self {
+            id: BlockId::new(ptr),
+            block_mark: meta.get_block_mark(),
+            age: meta.get_age(),
+            lines,
+            line_size: geometry.line_size(),
+        })
+    }
+
+    pub fn id(&self) -> BlockId {
+        self.id
+    }
+
+    /// The mark the block was last marked with, `0` if it hasn't been marked
+    /// since it was last freed.
+    pub fn mark(&self) -> u8 {
+        self.block_mark
+    }
+
+    /// How many sweeps the block has survived, see [`Heap::block_age_of`].
+    ///
+    /// [`Heap::block_age_of`]: crate::Heap::block_age_of
+    pub fn age(&self) -> u8 {
+        self.age
+    }
+
+    /// The mark of each line, from the bottom of the block up. Free lines hold
+    /// `0`.
+    pub fn line_marks(&self) -> &[u8] {
+        &self.lines
+    }
+
+    /// The number of lines holding a mark, i.e. not free. Lines are only
+    /// marked by marking objects, so objects allocated since the last sweep
+    /// don't count until they are marked.
+    pub fn occupied_lines(&self) -> usize {
+        self.lines.iter().filter(|mark| **mark != FREE_MARK).count()
+    }
+
+    /// The size in bytes of the largest run of free lines.
+    pub fn largest_hole(&self) -> usize {
+        let mut largest = 0;
+        let mut run = 0;
+
+        for mark in self.lines.iter() {
+            if *mark == FREE_MARK {
+                run += 1;
+                largest = largest.max(run);
+            } else {
+                run = 0;
+            }
+        }
+
+        largest * self.line_size
+    }
+}
@@ -0,0 +1,81 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LockResult, Mutex, MutexGuard, PoisonError};
+
+// One of the block store's lists, along with its length, which is kept up to
+// date each time the list is unlocked so it can be read without locking.
+pub struct BlockList<T> {
+    items: Mutex<Vec<T>>,
+    len: AtomicUsize,
+}
+
+pub struct BlockListGuard<'a, T> {
+    items: MutexGuard<'a, Vec<T>>,
+    len: &'a AtomicUsize,
+}
+
+impl<T> BlockList<T> {
+    pub fn new() -> Self {
+        Self {
+            items: Mutex::new(vec![]),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<BlockListGuard<'_, T>> {
+        let len = &self.len;
+
+        match self.items.lock() {
+            Ok(items) => Ok(BlockListGuard { items, len }),
+            Err(err) => Err(PoisonError::new(BlockListGuard { items: err.into_inner(), len })),
+        }
+    }
+
+    // The length as of the last time the list was unlocked.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Deref for BlockListGuard<'_, T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.items
+    }
+}
+
+impl<T> DerefMut for BlockListGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.items
+    }
+}
+
+impl<T> Drop for BlockListGuard<'_, T> {
+    fn drop(&mut self) {
+        self.len.store(self.items.len(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn len_follows_the_list_once_unlocked() {
+        let list = BlockList::new();
+
+        {
+            let mut items = list.lock().unwrap();
+
+            items.extend([1, 2, 3]);
+        }
+
+        assert_eq!(list.len(), 3);
+
+        let _ = list.lock().unwrap().pop();
+        list.lock().unwrap().retain(|&item| item != 1);
+
+        assert_eq!(list.len(), 1);
+    }
+}
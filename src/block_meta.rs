@@ -1,75 +1,225 @@
-use super::constants::{
-    BLOCK_SIZE, FREE_MARK, LINE_COUNT, LINE_MARK_START, LINE_SIZE, BLOCK_MARK_OFFSET
-};
+use super::constants::FREE_MARK;
+#[cfg(feature = "card-marks")]
+use super::constants::{CLEAN_CARD, DIRTY_CARD};
 use super::size_class::SizeClass;
 use super::block::Block;
+use super::block_directory;
+use super::error::AllocError;
+use super::geometry::Geometry;
 use std::sync::atomic::{AtomicU8, Ordering};
 use std::num::NonZero;
+use std::ptr;
 
 pub struct BlockMeta {
-    lines: *const [AtomicU8; LINE_COUNT],
+    geometry: Geometry,
+    lines: *const [AtomicU8],
     block_mark: *const AtomicU8,
+    #[cfg(feature = "card-marks")]
+    card: *const AtomicU8,
+    // how many sweeps the block has survived
+    age: *const AtomicU8,
+    // set while an object in the block is pinned, so it isn't evacuated
+    pinned: *const AtomicU8,
+    // the last mark an object in the block was given without being traced,
+    // nothing records where such an object is so the block can't be evacuated
+    untraced: *const AtomicU8,
+    // the marks of the second color
+    #[cfg(feature = "dual-mark")]
+    secondary_lines: *const [AtomicU8],
+    #[cfg(feature = "dual-mark")]
+    secondary_block_mark: *const AtomicU8,
 }
 
 impl BlockMeta {
-    pub fn new(block: &Block) -> BlockMeta {
-        let meta = unsafe { Self::from_block_ptr(block.as_ptr()) };
+    pub fn new<const BLOCK_SIZE: usize>(
+        block: &Block<BLOCK_SIZE>,
+        geometry: Geometry,
+    ) -> Result<BlockMeta, AllocError> {
+        debug_assert!(block.get_size() == geometry.block_size());
+
+        #[cfg(feature = "side-meta")]
+        super::side_meta::insert(block.as_ptr(), geometry.meta_size())?;
+
+        let meta = unsafe { Self::from_block_ptr(block.as_ptr(), geometry)? };
 
         meta.reset();
-        meta
+        Ok(meta)
     }
 
-    pub unsafe fn from_block_ptr(ptr: *const u8) -> Self {
-        let lines = ptr.add(LINE_MARK_START) as *const [AtomicU8; LINE_COUNT];
-        let block_mark =  ptr.add(BLOCK_MARK_OFFSET) as *const AtomicU8;
-
-        Self {
+    // Fails only with side-meta, for a block whose metadata can't be found.
+    // SAFETY: ptr must be the start of a block of the geometry given
+    pub unsafe fn from_block_ptr(ptr: *const u8, geometry: Geometry) -> Result<Self, AllocError> {
+        #[cfg(feature = "side-meta")]
+        let ptr = super::side_meta::get(ptr)?;
+
+        let line_count = geometry.line_count();
+        let lines = ptr::slice_from_raw_parts(ptr.add(geometry.line_mark_start()) as *const AtomicU8, line_count);
+        let block_mark = ptr.add(geometry.block_mark_offset()) as *const AtomicU8;
+        let age = ptr.add(geometry.age_offset()) as *const AtomicU8;
+        let pinned = ptr.add(geometry.pinned_offset()) as *const AtomicU8;
+        let untraced = ptr.add(geometry.untraced_offset()) as *const AtomicU8;
+
+        Ok(Self {
+            geometry,
             lines,
             block_mark,
-        }
+            #[cfg(feature = "card-marks")]
+            card: ptr.add(geometry.card_offset()) as *const AtomicU8,
+            age,
+            pinned,
+            untraced,
+            #[cfg(feature = "dual-mark")]
+            secondary_lines: ptr::slice_from_raw_parts(
+                ptr.add(geometry.secondary_line_mark_start()) as *const AtomicU8,
+                line_count,
+            ),
+            #[cfg(feature = "dual-mark")]
+            secondary_block_mark: ptr.add(geometry.secondary_block_mark_offset()) as *const AtomicU8,
+        })
     }
 
-    pub unsafe fn from_ptr(ptr: *const u8) -> Self {
-        let offset = (ptr as usize) % BLOCK_SIZE;
-        let block_ptr = ptr.byte_sub(offset);
+    // The metadata of the block holding `ptr`, whose geometry is taken from
+    // the block directory. Fails for pointers outside of any block.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, AllocError> {
+        let record = block_directory::get(ptr).ok_or(AllocError::UnknownObject)?;
 
-        Self::from_block_ptr(block_ptr)
+        Self::from_block_ptr(record.base(), record.geometry())
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    // the line of the block `ptr` lies in
+    pub fn line_of(&self, ptr: *const u8) -> usize {
+        (ptr as usize & (self.geometry.block_size() - 1)) / self.geometry.line_size()
     }
 
     // SAFETY: ptr must be a point to an object allocated within a bump block
-    pub unsafe fn mark(&self, ptr: *mut u8, size: u32, size_class: SizeClass, mark: NonZero<u8>) {
-        let addr = ptr as usize;
-        let relative_ptr = addr % BLOCK_SIZE;
-        let line = relative_ptr / LINE_SIZE;
+    pub unsafe fn mark(&self, ptr: *mut u8, size: u32, size_class: SizeClass, mark: NonZero<u8>) -> Result<(), AllocError> {
+        for i in self.marked_lines(ptr, size, size_class)? {
+            self.set_line(i, mark.into());
+        }
 
-        debug_assert!(size_class != SizeClass::Large);
+        self.mark_block_once(mark);
+
+        Ok(())
+    }
+
+    // Marks the lines given, along with the block.
+    pub fn mark_lines(&self, lines: std::ops::RangeInclusive<usize>, mark: NonZero<u8>) {
+        for i in lines {
+            self.set_line(i, mark.into());
+        }
+
+        self.mark_block_once(mark);
+    }
+
+    // Clears the lines given, in every color, leaving the block mark alone.
+    pub fn free_lines(&self, lines: std::ops::Range<usize>) {
+        for i in lines {
+            self.set_line(i, FREE_MARK);
 
+            #[cfg(feature = "dual-mark")]
+            self.secondary_line(i).store(FREE_MARK, Ordering::Relaxed);
+        }
+    }
+
+    // The lines marking an object marks.
+    pub fn marked_lines(
+        &self,
+        ptr: *mut u8,
+        size: u32,
+        size_class: SizeClass,
+    ) -> Result<std::ops::Range<usize>, AllocError> {
+        let line_size = self.geometry.line_size();
+        let relative_ptr = ptr as usize & (self.geometry.block_size() - 1);
+        let relative_end = relative_ptr + size as usize;
+        let line = relative_ptr / line_size;
+
+        // the object must lie entirely within the data region of the block,
+        // otherwise the marks would be written over the block's own metadata
+        if size_class == SizeClass::Large
+            || line >= self.geometry.line_count()
+            || relative_end > self.geometry.capacity()
+        {
+            return Err(AllocError::AllocOverflow);
+        }
+
+        // medium objects mark every line they touch, including a last line
+        // they only partly take up
         if size_class == SizeClass::Small {
-            self.set_line(line, mark.into());
+            Ok(line..line + 1)
         } else {
-            let relative_end = relative_ptr + size as usize;
-            let end_line = relative_end / LINE_SIZE;
+            Ok(line..relative_end.div_ceil(line_size))
+        }
+    }
 
-            for i in line..end_line {
-                self.set_line(i, mark.into());
-            }
+    // Marks the object with the second color, leaving the primary marks alone.
+    // SAFETY: ptr must be a point to an object allocated within a bump block
+    #[cfg(feature = "dual-mark")]
+    pub unsafe fn mark_secondary(&self, ptr: *mut u8, size: u32, size_class: SizeClass, mark: NonZero<u8>) -> Result<(), AllocError> {
+        for i in self.marked_lines(ptr, size, size_class)? {
+            self.secondary_line(i).store(mark.into(), Ordering::Relaxed);
+        }
+
+        (&*self.secondary_block_mark).store(mark.into(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    // Replaces the primary marks with the secondary ones, keeping `mark` only
+    // where the second color carries it, and clears the second color.
+    #[cfg(feature = "dual-mark")]
+    pub fn fold_secondary(&self, mark: NonZero<u8>) {
+        for i in 0..self.geometry.line_count() {
+            let line = self.secondary_line(i).swap(FREE_MARK, Ordering::Relaxed);
+
+            self.set_line(i, if line == mark.get() { line } else { FREE_MARK });
         }
 
-        self.mark_block(mark);
+        let block = unsafe { (&*self.secondary_block_mark).swap(FREE_MARK, Ordering::Relaxed) };
+
+        if block == mark.get() {
+            self.mark_block(mark);
+        } else {
+            self.free_block();
+        }
+    }
+
+    #[cfg(feature = "dual-mark")]
+    pub fn clear_secondary(&self) {
+        for i in 0..self.geometry.line_count() {
+            self.secondary_line(i).store(FREE_MARK, Ordering::Relaxed);
+        }
+
+        unsafe { (&*self.secondary_block_mark).store(FREE_MARK, Ordering::Relaxed) }
+    }
+
+    #[cfg(feature = "dual-mark")]
+    fn secondary_line(&self, line: usize) -> &AtomicU8 {
+        unsafe { &(&*self.secondary_lines)[line] }
     }
 
     pub fn free_unmarked(&self, mark: NonZero<u8>) {
         if self.get_block_mark() != mark.into() {
             self.free_block();
+            self.set_age(0);
         }
 
-        for i in 0..LINE_COUNT {
+        for i in 0..self.geometry.line_count() {
             if self.get_line(i) != mark.into() {
                 self.set_line(i, FREE_MARK);
             }
         }
     }
 
+    pub fn marked_line_count(&self, mark: NonZero<u8>) -> usize {
+        (0..self.geometry.line_count())
+            .filter(|i| self.get_line(*i) == mark.get())
+            .count()
+    }
+
     pub fn get_block_mark(&self) -> u8 {
         unsafe { (&*self.block_mark).load(Ordering::Relaxed) }
     }
@@ -78,22 +228,91 @@ impl BlockMeta {
         unsafe { (&*self.block_mark).store(mark.into(), Ordering::Relaxed) }
     }
 
+    // The block mark doubles as a per cycle dirty bit: once it holds the
+    // current mark every later object in the block can skip the store, which
+    // keeps the metadata cache line shared while a block is being marked.
+    fn mark_block_once(&self, mark: NonZero<u8>) {
+        if self.get_block_mark() != mark.get() {
+            self.mark_block(mark);
+        }
+    }
+
+    pub fn get_age(&self) -> u8 {
+        unsafe { (&*self.age).load(Ordering::Relaxed) }
+    }
+
+    // records that the block survived another sweep, saturating at u8::MAX
+    pub fn increment_age(&self) {
+        self.set_age(self.get_age().saturating_add(1));
+    }
+
+    fn set_age(&self, age: u8) {
+        unsafe { (&*self.age).store(age, Ordering::Relaxed) }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        unsafe { (&*self.pinned).load(Ordering::Relaxed) != 0 }
+    }
+
+    pub fn set_pinned(&self, pinned: bool) {
+        unsafe { (&*self.pinned).store(pinned as u8, Ordering::Relaxed) }
+    }
+
+    // Records that an object in the block was marked with `mark` without being
+    // traced. Stores only when the mark changes, as mark_block_once does.
+    pub fn mark_untraced(&self, mark: NonZero<u8>) {
+        if !self.has_untraced(mark) {
+            unsafe { (&*self.untraced).store(mark.into(), Ordering::Relaxed) }
+        }
+    }
+
+    // Whether an object in the block was marked with `mark` without being traced.
+    pub fn has_untraced(&self, mark: NonZero<u8>) -> bool {
+        unsafe { (&*self.untraced).load(Ordering::Relaxed) == mark.get() }
+    }
+
+    #[cfg(feature = "card-marks")]
+    pub fn mark_card(&self) {
+        unsafe { (&*self.card).store(DIRTY_CARD, Ordering::Relaxed) }
+    }
+
+    // returns whether the card was dirty, leaving it clean
+    #[cfg(feature = "card-marks")]
+    pub fn take_card(&self) -> bool {
+        unsafe { (&*self.card).swap(CLEAN_CARD, Ordering::Relaxed) == DIRTY_CARD }
+    }
+
     pub fn reset(&self) {
         self.free_block();
+        #[cfg(feature = "card-marks")]
+        self.take_card();
+        self.set_age(0);
+        self.set_pinned(false);
+        unsafe { (&*self.untraced).store(FREE_MARK, Ordering::Relaxed) }
 
-        for i in 0..LINE_COUNT {
+        for i in 0..self.geometry.line_count() {
             self.set_line(i, FREE_MARK);
         }
+
+        #[cfg(feature = "dual-mark")]
+        self.clear_secondary();
     }
 
+    // Searches downward from `starting_at` for a hole that fits `alloc_size`.
+    // The `conservative_lines` following each marked line are treated as marked
+    // too, since a small object starting in a marked line may spill into them.
     pub fn find_next_available_hole(
         &self,
         starting_at: usize,
         alloc_size: usize,
+        conservative_lines: usize,
     ) -> Option<(usize, usize)> {
+        let line_size = self.geometry.line_size();
         let mut free_line_count = 0;
-        let starting_line = starting_at / LINE_SIZE;
-        let lines_required = alloc_size.div_ceil(LINE_SIZE);
+        let starting_line = starting_at / line_size;
+        // even an empty allocation needs a non empty hole, otherwise the hole
+        // would end where it starts
+        let lines_required = alloc_size.div_ceil(line_size).max(1);
         let mut end = starting_line;
 
         for index in (0..starting_line).rev() {
@@ -103,21 +322,24 @@ impl BlockMeta {
                 free_line_count += 1;
 
                 if index == 0 && free_line_count >= lines_required {
-                    let limit = index * LINE_SIZE;
-                    let cursor = end * LINE_SIZE;
+                    let limit = index * line_size;
+                    let cursor = end * line_size;
 
                     debug_assert!(cursor > limit);
 
                     return Some((cursor, limit));
                 }
             } else {
-                if free_line_count > lines_required {
-                    let limit = (index + 2) * LINE_SIZE;
-                    let cursor = end * LINE_SIZE;
+                if free_line_count >= lines_required.saturating_add(conservative_lines) {
+                    let limit = (index + 1 + conservative_lines) * line_size;
+                    let cursor = end * line_size;
 
                     debug_assert!(cursor > limit);
 
-                    return Some((cursor, limit));
+                    // never hand out a window that would underflow the hole size
+                    if cursor > limit {
+                        return Some((cursor, limit));
+                    }
                 }
 
                 free_line_count = 0;
@@ -128,12 +350,55 @@ impl BlockMeta {
         None
     }
 
-    fn get_line(&self, index: usize) -> u8 {
-        self.mark_at(index).load(Ordering::Relaxed).into()
+    // Like find_next_available_hole, but searches upward from `starting_at`,
+    // for heaps that allocate from the bottom of each hole. The hole is
+    // returned the same way, its end followed by its start.
+    pub fn find_next_available_hole_upward(
+        &self,
+        starting_at: usize,
+        alloc_size: usize,
+        conservative_lines: usize,
+    ) -> Option<(usize, usize)> {
+        let line_size = self.geometry.line_size();
+        let line_count = self.geometry.line_count();
+        let starting_line = starting_at.div_ceil(line_size);
+        let lines_required = alloc_size.div_ceil(line_size).max(1);
+        let mut start = None;
+        // a marked line just below the starting line still covers the lines
+        // following it
+        let mut usable_from = (starting_line.saturating_sub(conservative_lines)..starting_line)
+            .filter(|&index| self.get_line(index) != FREE_MARK)
+            .map(|index| index + 1 + conservative_lines)
+            .max()
+            .unwrap_or(starting_line);
+
+        // the end of the block closes the last hole like a marked line would
+        for index in starting_line..=line_count {
+            if index < line_count && self.get_line(index) == FREE_MARK {
+                if start.is_none() && index >= usable_from {
+                    start = Some(index);
+                }
+
+                continue;
+            }
+
+            if let Some(start) = start.filter(|&start| index - start >= lines_required) {
+                return Some((index * line_size, start * line_size));
+            }
+
+            start = None;
+            usable_from = index + 1 + conservative_lines;
+        }
+
+        None
+    }
+
+    pub fn get_line(&self, index: usize) -> u8 {
+        self.mark_at(index).load(Ordering::Relaxed)
     }
 
     fn set_line(&self, index: usize, mark: u8) {
-        self.mark_at(index).store(mark.into(), Ordering::Relaxed)
+        self.mark_at(index).store(mark, Ordering::Relaxed)
     }
 
     fn mark_at(&self, line: usize) -> &AtomicU8 {
@@ -147,28 +412,52 @@ impl BlockMeta {
 
 #[cfg(test)]
 mod tests {
-    use crate::constants::BLOCK_CAPACITY;
     use crate::block::Block;
 
     use super::*;
+    use crate::constants::{
+        BLOCK_CAPACITY, BLOCK_META_BYTES, BLOCK_SIZE, CONSERVATIVE_LINES, LINE_COUNT, LINE_SIZE, MARK_COLORS,
+    };
+    use std::alloc::Layout;
     use std::num::NonZero;
 
+    fn block_of(geometry: Geometry) -> Block {
+        let layout = Layout::from_size_align(geometry.block_size(), geometry.block_size()).unwrap();
+
+        Block::new(layout, &crate::backing::system()).unwrap()
+    }
+
     #[test]
     fn new_block_meta_is_reset() {
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
 
         assert_eq!(meta.get_block_mark(), FREE_MARK);
+        #[cfg(feature = "card-marks")]
+        assert!(!meta.take_card());
 
         for i in 0..LINE_COUNT {
             assert_eq!(meta.get_line(i), FREE_MARK);
         }
     }
 
+    #[cfg(feature = "card-marks")]
+    #[test]
+    fn mark_card() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+
+        meta.mark_card();
+
+        assert!(meta.take_card());
+        assert!(!meta.take_card());
+        assert_eq!(meta.get_block_mark(), FREE_MARK);
+    }
+
     #[test]
     fn mark_block() {
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
 
         meta.mark_block(NonZero::new(1).unwrap());
 
@@ -179,10 +468,140 @@ mod tests {
         }
     }
 
+    #[test]
+    fn count_marked_lines() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        let mark = NonZero::new(3).unwrap();
+
+        meta.set_line(0, 3);
+        meta.set_line(7, 3);
+        meta.set_line(8, 4);
+
+        assert_eq!(meta.marked_line_count(mark), 2);
+    }
+
+    #[test]
+    fn mark_medium_object() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        let ptr = unsafe { block.as_ptr().add(LINE_SIZE) as *mut u8 };
+        let mark = NonZero::new(1).unwrap();
+
+        unsafe { meta.mark(ptr, 3 * LINE_SIZE as u32, SizeClass::Medium, mark).unwrap() };
+
+        assert_eq!(meta.get_block_mark(), 1);
+        assert_eq!(meta.get_line(0), FREE_MARK);
+        assert_eq!(meta.get_line(1), 1);
+        assert_eq!(meta.get_line(3), 1);
+    }
+
+    #[test]
+    fn mark_medium_object_ending_within_a_line() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        let ptr = unsafe { block.as_ptr().add(LINE_SIZE + 16) as *mut u8 };
+        let mark = NonZero::new(1).unwrap();
+
+        // starts partway into line 1 and ends partway into line 3
+        unsafe { meta.mark(ptr, 2 * LINE_SIZE as u32, SizeClass::Medium, mark).unwrap() };
+
+        assert_eq!(meta.get_line(1), 1);
+        assert_eq!(meta.get_line(2), 1);
+        assert_eq!(meta.get_line(3), 1);
+        assert_eq!(meta.get_line(4), FREE_MARK);
+        assert_eq!(meta.marked_line_count(mark), 3);
+    }
+
+    #[test]
+    fn mark_medium_object_in_top_lines() {
+        // the last lines sit right below the line marks, wherever those start
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        let ptr = unsafe { block.as_ptr().add(BLOCK_CAPACITY - 3 * LINE_SIZE) as *mut u8 };
+        let mark = NonZero::new(1).unwrap();
+
+        unsafe { meta.mark(ptr, 3 * LINE_SIZE as u32, SizeClass::Medium, mark).unwrap() };
+
+        assert_eq!(meta.get_line(LINE_COUNT - 4), FREE_MARK);
+        assert!((LINE_COUNT - 3..LINE_COUNT).all(|line| meta.get_line(line) == 1));
+        assert_eq!(meta.marked_line_count(mark), 3);
+
+        let got = meta.find_next_available_hole(BLOCK_CAPACITY, LINE_SIZE, CONSERVATIVE_LINES);
+
+        assert_eq!(got, Some(((LINE_COUNT - 3) * LINE_SIZE, 0)));
+    }
+
+    #[test]
+    fn marking_many_objects_marks_block() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        let old_mark = NonZero::new(1).unwrap();
+        let mark = NonZero::new(2).unwrap();
+
+        meta.mark_block(old_mark);
+
+        for i in 0..LINE_COUNT {
+            let ptr = unsafe { block.as_ptr().add(i * LINE_SIZE) as *mut u8 };
+
+            unsafe { meta.mark(ptr, 16, SizeClass::Small, mark).unwrap() };
+        }
+
+        assert_eq!(meta.get_block_mark(), 2);
+        assert_eq!(meta.marked_line_count(mark), LINE_COUNT);
+    }
+
+    #[test]
+    fn mark_past_last_line_fails() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        let mark = NonZero::new(1).unwrap();
+        let last_line = unsafe { block.as_ptr().add((LINE_COUNT - 1) * LINE_SIZE) as *mut u8 };
+        let past_end = unsafe { block.as_ptr().add(BLOCK_CAPACITY) as *mut u8 };
+
+        let medium = unsafe { meta.mark(last_line, 2 * LINE_SIZE as u32, SizeClass::Medium, mark) };
+
+        assert!(medium.is_err());
+
+        // with side metadata the block holds nothing but lines, so the end of
+        // the block is the start of the next one
+        if BLOCK_CAPACITY < BLOCK_SIZE {
+            let small = unsafe { meta.mark(past_end, 1, SizeClass::Small, mark) };
+
+            assert!(small.is_err());
+        }
+
+        assert_eq!(meta.get_block_mark(), FREE_MARK);
+
+        for i in 0..LINE_COUNT {
+            assert_eq!(meta.get_line(i), FREE_MARK);
+        }
+    }
+
+    #[cfg(feature = "side-meta")]
+    #[test]
+    fn marking_leaves_block_data_untouched() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        let mark = NonZero::new(1).unwrap();
+        let ptr = block.as_ptr() as *mut u8;
+
+        unsafe {
+            std::ptr::write_bytes(ptr, 0xAA, BLOCK_SIZE);
+            meta.mark(ptr, BLOCK_SIZE as u32, SizeClass::Medium, mark).unwrap();
+        }
+
+        let data = unsafe { std::slice::from_raw_parts(ptr, BLOCK_SIZE) };
+
+        assert_eq!(meta.marked_line_count(mark), LINE_COUNT);
+        assert_eq!(meta.get_block_mark(), mark.get());
+        assert!(data.iter().all(|byte| *byte == 0xAA));
+    }
+
     #[test]
     fn mark_line() {
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
 
         for i in 0..LINE_COUNT {
             let mark = 69;
@@ -198,7 +617,7 @@ mod tests {
         // The first hole should be seen as conservatively marked.
         // The second hole should be the one selected.
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
 
         meta.set_line(9, 1);
         meta.set_line(10, 1);
@@ -206,7 +625,7 @@ mod tests {
         // line 5 should be conservatively marked
         let expect = Some((9 * LINE_SIZE, 0));
 
-        let got = meta.find_next_available_hole(10 * LINE_SIZE, LINE_SIZE);
+        let got = meta.find_next_available_hole(10 * LINE_SIZE, LINE_SIZE, CONSERVATIVE_LINES);
 
         assert_eq!(got, expect);
     }
@@ -215,13 +634,13 @@ mod tests {
     fn find_next_hole_at_line_zero() {
         // Should find the hole starting at the beginning of the block
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
 
         meta.set_line(3, 1);
 
         let expect = Some((3 * LINE_SIZE, 0));
 
-        let got = meta.find_next_available_hole(3 * LINE_SIZE, LINE_SIZE);
+        let got = meta.find_next_available_hole(3 * LINE_SIZE, LINE_SIZE, CONSERVATIVE_LINES);
 
         assert_eq!(got, expect);
     }
@@ -230,7 +649,7 @@ mod tests {
     fn hole_with_conservatively_marked_line() {
         // hole size should reflect there being one line conservatively marked
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
 
         meta.set_line(0, 1);
         meta.set_line(3, 1);
@@ -239,7 +658,7 @@ mod tests {
         // marking line 1. Making the hole is be constrained to only line 2.
         let expect = Some((3 * LINE_SIZE, LINE_SIZE * 2));
 
-        let got = meta.find_next_available_hole(3 * LINE_SIZE, LINE_SIZE);
+        let got = meta.find_next_available_hole(3 * LINE_SIZE, LINE_SIZE, CONSERVATIVE_LINES);
 
         assert_eq!(got, expect);
     }
@@ -249,7 +668,7 @@ mod tests {
         // The first half of the block is marked.
         // The second half of the block should be identified as a hole.
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
         let halfway = LINE_COUNT / 2;
 
         for i in halfway..LINE_COUNT {
@@ -258,9 +677,60 @@ mod tests {
 
         // because halfway line should be conservatively marked
         let expect = Some((halfway * LINE_SIZE, 0));
-        let got = meta.find_next_available_hole(BLOCK_CAPACITY, LINE_SIZE);
+        let got = meta.find_next_available_hole(BLOCK_CAPACITY, LINE_SIZE, CONSERVATIVE_LINES);
+
+        assert_eq!(got, expect);
+    }
+
+    #[test]
+    fn no_conservative_lines() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+
+        meta.set_line(0, 1);
+        meta.set_line(3, 1);
+
+        // the hole starts right after the marked line
+        let expect = Some((3 * LINE_SIZE, LINE_SIZE));
+        let got = meta.find_next_available_hole(3 * LINE_SIZE, LINE_SIZE, 0);
+
+        assert_eq!(got, expect);
+    }
+
+    #[test]
+    fn two_conservative_lines() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+
+        meta.set_line(0, 1);
+        meta.set_line(4, 1);
+
+        // lines 1 and 2 are conservatively marked, leaving only line 3
+        let expect = Some((4 * LINE_SIZE, 3 * LINE_SIZE));
+        let got = meta.find_next_available_hole(4 * LINE_SIZE, LINE_SIZE, 2);
 
         assert_eq!(got, expect);
+
+        // a two line object no longer fits
+        let got = meta.find_next_available_hole(4 * LINE_SIZE, 2 * LINE_SIZE, 2);
+
+        assert_eq!(got, None);
+    }
+
+    #[test]
+    fn empty_allocation_near_block_top() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+
+        meta.set_line(LINE_COUNT - 1, 1);
+        meta.set_line(LINE_COUNT - 2, 1);
+        meta.set_line(LINE_COUNT - 4, 1);
+
+        // with no conservative lines the free line between the marks is the hole
+        let (cursor, limit) = meta.find_next_available_hole(BLOCK_CAPACITY, 0, 0).unwrap();
+
+        assert!(cursor > limit);
+        assert_eq!((cursor, limit), ((LINE_COUNT - 2) * LINE_SIZE, (LINE_COUNT - 3) * LINE_SIZE));
     }
 
     #[test]
@@ -268,30 +738,131 @@ mod tests {
         // Every other line is marked.
         // No hole should be found due to conservative marking.
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
 
         for i in (0..LINE_COUNT).step_by(2) {
             meta.set_line(i, 1);
         }
 
-        let got = meta.find_next_available_hole(BLOCK_CAPACITY, 1);
+        let got = meta.find_next_available_hole(BLOCK_CAPACITY, 1, CONSERVATIVE_LINES);
         assert_eq!(got, None);
     }
 
     #[test]
     fn entire_block_is_hole() {
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
         let expect = (BLOCK_CAPACITY, 0);
-        let got = meta.find_next_available_hole(BLOCK_CAPACITY, LINE_SIZE).unwrap();
+        let got = meta.find_next_available_hole(BLOCK_CAPACITY, LINE_SIZE, CONSERVATIVE_LINES).unwrap();
 
         assert_eq!(got, expect);
     }
 
+    #[test]
+    fn holes_between_marked_objects_span_the_whole_block() {
+        // every fourth line holds a live object, up to the last line of the
+        // block, whatever the line size and however many lines there are
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        let mark = NonZero::new(1).unwrap();
+        let mut marked: Vec<usize> = (0..LINE_COUNT).step_by(4).chain([LINE_COUNT - 1]).collect();
+
+        marked.dedup();
+
+        for &line in marked.iter() {
+            let ptr = unsafe { block.as_ptr().add(line * LINE_SIZE) as *mut u8 };
+
+            unsafe { meta.mark(ptr, 8, SizeClass::Small, mark).unwrap() };
+        }
+
+        let mut expect = vec![];
+
+        for pair in marked.windows(2).rev() {
+            // the lines right after each mark are conservatively marked
+            if pair[0] + 1 + CONSERVATIVE_LINES < pair[1] {
+                expect.push((pair[1] * LINE_SIZE, (pair[0] + 1 + CONSERVATIVE_LINES) * LINE_SIZE));
+            }
+        }
+
+        let mut holes = vec![];
+        let mut from = BLOCK_CAPACITY;
+
+        while let Some((cursor, limit)) = meta.find_next_available_hole(from, 1, CONSERVATIVE_LINES) {
+            holes.push((cursor, limit));
+            from = limit;
+        }
+
+        assert_eq!(meta.marked_line_count(mark), marked.len());
+        assert_eq!(holes, expect);
+    }
+
+    #[test]
+    fn find_next_hole_upward() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+
+        meta.set_line(0, 1);
+        meta.set_line(3, 1);
+        meta.set_line(9, 1);
+
+        // line 1 is conservatively marked, leaving only line 2 below line 3
+        let got = meta.find_next_available_hole_upward(0, LINE_SIZE, CONSERVATIVE_LINES);
+
+        assert_eq!(got, Some((3 * LINE_SIZE, 2 * LINE_SIZE)));
+
+        // the hole above line 3 is too small for five lines, the one above line 9 isn't
+        let got = meta.find_next_available_hole_upward(3 * LINE_SIZE, 5 * LINE_SIZE, CONSERVATIVE_LINES);
+
+        assert_eq!(got, Some((BLOCK_CAPACITY, 11 * LINE_SIZE)));
+    }
+
+    #[test]
+    fn upward_hole_search_honors_marks_below_start() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+
+        meta.set_line(4, 1);
+
+        // line 5 is covered by the mark on line 4, even though the search starts there
+        let got = meta.find_next_available_hole_upward(5 * LINE_SIZE, LINE_SIZE, CONSERVATIVE_LINES);
+
+        assert_eq!(got, Some((BLOCK_CAPACITY, 6 * LINE_SIZE)));
+        assert_eq!(meta.find_next_available_hole_upward(BLOCK_CAPACITY, 1, 0), None);
+    }
+
+    #[test]
+    fn upward_and_downward_searches_find_the_same_holes() {
+        let block = Block::default().unwrap();
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+
+        for i in [0, 5, 6, 12, 20, 22, LINE_COUNT - 1] {
+            meta.set_line(i, 1);
+        }
+
+        let mut downward = vec![];
+        let mut from = BLOCK_CAPACITY;
+
+        while let Some((cursor, limit)) = meta.find_next_available_hole(from, 1, CONSERVATIVE_LINES) {
+            downward.push((cursor, limit));
+            from = limit;
+        }
+
+        let mut upward = vec![];
+        let mut from = 0;
+
+        while let Some((cursor, limit)) = meta.find_next_available_hole_upward(from, 1, CONSERVATIVE_LINES) {
+            upward.push((cursor, limit));
+            from = cursor;
+        }
+
+        upward.reverse();
+        assert_eq!(upward, downward);
+    }
+
     #[test]
     fn reset_block_meta() {
         let block = Block::default().unwrap();
-        let meta = BlockMeta::new(&block);
+        let meta = BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
 
         meta.mark_block(NonZero::new(1).unwrap());
 
@@ -308,4 +879,110 @@ mod tests {
             assert_eq!(meta.get_line(i), FREE_MARK);
         }
     }
+
+    #[test]
+    fn smaller_lines_are_sized_and_searched_correctly() {
+        for line_size in [64, 32] {
+            let geometry = Geometry::new(BLOCK_SIZE, line_size).unwrap();
+            let (line_count, capacity) = (geometry.line_count(), geometry.capacity());
+            let block = Block::default().unwrap();
+            let meta = BlockMeta::new(&block, geometry).unwrap();
+            let mark = NonZero::new(1).unwrap();
+
+            // every line has a mark of its own, the lines get whatever the
+            // metadata leaves of the block
+            assert_eq!(geometry.meta_size(), line_count * MARK_COLORS + BLOCK_META_BYTES);
+
+            if cfg!(feature = "side-meta") {
+                assert_eq!(line_count, BLOCK_SIZE / line_size);
+            } else {
+                assert!(capacity + geometry.meta_size() <= BLOCK_SIZE);
+                assert!(capacity + line_size + geometry.meta_size() + MARK_COLORS > BLOCK_SIZE);
+            }
+
+            #[cfg(not(any(feature = "side-meta", feature = "dual-mark", feature = "card-marks")))]
+            assert_eq!(line_count, if line_size == 64 { 252 } else { 496 });
+
+            // an object spanning the two top lines, right below the marks
+            let top = unsafe { block.as_ptr().add(capacity - 2 * line_size) as *mut u8 };
+
+            unsafe { meta.mark(top, 2 * line_size as u32, SizeClass::Medium, mark).unwrap() };
+            meta.set_line(3, 1);
+
+            assert_eq!(meta.get_line(line_count - 2), 1);
+            assert_eq!(meta.get_line(line_count - 1), 1);
+            assert_eq!(meta.get_line(line_count - 3), FREE_MARK);
+
+            // line 4 is conservatively marked by the object in line 3
+            let expect = Some(((line_count - 2) * line_size, 5 * line_size));
+
+            assert_eq!(meta.find_next_available_hole(capacity, line_size, CONSERVATIVE_LINES), expect);
+
+            let below = meta.find_next_available_hole(5 * line_size, line_size, CONSERVATIVE_LINES);
+
+            assert_eq!(below, Some((3 * line_size, 0)));
+            assert_eq!(meta.find_next_available_hole(0, line_size, CONSERVATIVE_LINES), None);
+        }
+    }
+
+    #[test]
+    fn every_line_of_a_larger_block_is_addressable() {
+        let geometry = Geometry::new(256 * 1024, 128).unwrap();
+        let (line_count, capacity) = (geometry.line_count(), geometry.capacity());
+        let block = block_of(geometry);
+        let meta = BlockMeta::new(&block, geometry).unwrap();
+        let mark = NonZero::new(1).unwrap();
+
+        // the marks grow with the block, so it has many more lines than a
+        // default one, each with a mark of its own
+        assert_eq!(meta.geometry().line_count(), line_count);
+        assert!(line_count > 15 * LINE_COUNT);
+
+        for line in (0..line_count).step_by(3) {
+            let ptr = unsafe { block.as_ptr().add(line * 128) as *mut u8 };
+
+            unsafe { meta.mark(ptr, 8, SizeClass::Small, mark).unwrap() };
+        }
+
+        // an object in the top lines, right below the marks
+        let top = unsafe { block.as_ptr().add(capacity - 2 * 128) as *mut u8 };
+
+        unsafe { meta.mark(top, 2 * 128, SizeClass::Medium, mark).unwrap() };
+
+        assert!((0..line_count).all(|line| (line % 3 == 0 || line >= line_count - 2) == (meta.get_line(line) == 1)));
+        assert_eq!(meta.get_block_mark(), 1);
+
+        // past the data region the marks would land on the metadata
+        let past_end = unsafe { block.as_ptr().add(capacity - 128) as *mut u8 };
+
+        assert!(unsafe { meta.mark(past_end, 2 * 128, SizeClass::Medium, mark) }.is_err());
+
+        // with no conservative lines the free lines below each mark are a hole
+        let expect = (1..line_count)
+            .filter(|&line| meta.get_line(line) == 1 && meta.get_line(line - 1) == FREE_MARK)
+            .count();
+        let mut holes = 0;
+        let mut from = capacity;
+
+        while let Some((cursor, limit)) = meta.find_next_available_hole(from, 128, 0) {
+            assert_eq!(cursor % 128, 0);
+            assert!(cursor - limit >= 128);
+            holes += 1;
+            from = limit;
+        }
+
+        assert_eq!(holes, expect);
+        assert!(holes > line_count / 3 - 2);
+
+        meta.reset();
+
+        assert_eq!(
+            meta.find_next_available_hole(capacity, capacity, CONSERVATIVE_LINES),
+            Some((capacity, 0))
+        );
+        assert_eq!(
+            meta.find_next_available_hole_upward(0, capacity, CONSERVATIVE_LINES),
+            Some((capacity, 0))
+        );
+    }
 }
@@ -0,0 +1,74 @@
+use super::block::Block;
+use super::bump_block::BumpBlock;
+use std::sync::Mutex;
+
+const MAX_POOL_BLOCKS: usize = 1024;
+
+// Blocks released by any heap in the process, waiting to be picked up by the
+// next heap that needs one. The pool never allocates, as the global allocator
+// may be a heap that takes its blocks from the pool while it is locked. Heaps
+// with blocks of different sizes share the pool, each block is kept at the
+// default size and only taken back out at the size it was made with.
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    blocks: [const { None }; MAX_POOL_BLOCKS],
+    len: 0,
+});
+
+struct Pool {
+    blocks: [Option<Block>; MAX_POOL_BLOCKS],
+    len: usize,
+}
+
+pub fn take<const BLOCK_SIZE: usize>() -> Option<Block<BLOCK_SIZE>> {
+    let mut pool = POOL.lock().unwrap();
+    let len = pool.len;
+    let index = (0..len)
+        .rev()
+        .find(|&index| pool.blocks[index].as_ref().is_some_and(|block| block.get_size() == BLOCK_SIZE))?;
+
+    pool.blocks.swap(index, len - 1);
+    pool.len -= 1;
+
+    pool.blocks[len - 1].take().map(Block::resized)
+}
+
+// Blocks that don't fit in the pool, or that belong to a region, are dropped.
+pub fn give<const BLOCK_SIZE: usize>(block: BumpBlock<BLOCK_SIZE>) {
+    let block = block.into_block().resized();
+    let mut pool = POOL.lock().unwrap();
+
+    if pool.len < MAX_POOL_BLOCKS && block.is_owned() {
+        let len = pool.len;
+
+        pool.blocks[len] = Some(block);
+        pool.len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_store::BlockStore;
+
+    #[test]
+    fn heaps_share_blocks() {
+        let heaps = 10;
+        let blocks_per_heap = 20;
+        let mut pooled = 0;
+
+        for _ in 0..heaps {
+            let store = BlockStore::new();
+
+            for _ in 0..blocks_per_heap {
+                let block = store.get_overflow().unwrap();
+
+                store.rest(block);
+            }
+
+            pooled += store.pooled_block_count();
+        }
+
+        let from_os = heaps * blocks_per_heap - pooled;
+
+        assert!(from_os < blocks_per_heap * 2);
+    }
+}
@@ -1,53 +1,621 @@
+use super::backing::{self, Backing};
+use super::block::{Block, BlockId};
+use super::block_directory::{self, BlockRecord};
+use super::block_list::BlockList;
+use super::block_meta::BlockMeta;
+#[cfg(feature = "dual-mark")]
+use super::color::Color;
 use super::bump_block::BumpBlock;
-use super::error::AllocError;
-use super::constants::{BLOCK_SIZE, MAX_FREE_BLOCKS, RECYCLE_HOLE_MIN, LARGE_OBJECT_MIN};
+use super::error::{AllocError, OverlapError};
+use super::constants::{
+    self, FREE_MARK, LINE_SIZE, MAX_FREE_BLOCKS, RECYCLE_HOLE_MIN, CONSERVATIVE_LINES, MAX_POOLED_LARGE_PER_SIZE,
+    SMALL_BURST_BLOCKS, TRACED_SHARDS
+};
+use super::geometry::Geometry;
 use super::large_block::LargeBlock;
+use super::observer::HeapObserver;
+use super::region::Region;
+use super::size_class::SizeClass;
+use super::weak_handle::WeakHandle;
 use std::alloc::Layout;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::num::NonZero;
 
-pub struct BlockStore {
+// A finalizer registered with Heap::register_finalizer.
+pub type Finalizer = Box<dyn FnOnce(*const u8) + Send>;
+
+// The objects of a shard visited by mark_if_unmarked with the mark given first.
+type Traced = (u8, HashMap<usize, Layout>);
+
+// Hands out the ids stores are told apart by in the block directory.
+static NEXT_STORE_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// A snapshot of how a heap's memory is laid out, see [`Heap::stats`].
+///
+/// [`Heap::stats`]: crate::Heap::stats
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Blocks owned by the heap, wherever they are.
+    pub block_count: usize,
+    /// Empty blocks waiting to be allocated into.
+    pub free_block_count: usize,
+    /// Blocks with holes large enough to be allocated into again.
+    pub recycle_block_count: usize,
+    /// Blocks too full to be worth allocating into until the next sweep.
+    pub rest_block_count: usize,
+    pub large_object_count: usize,
+    /// Bytes taken by large objects, including their headers and padding.
+    pub large_bytes: usize,
+    /// Bytes taken by dead large objects kept for reuse by later large
+    /// allocations.
+    pub pooled_large_bytes: usize,
+}
+
+/// How many objects of each size class a heap has allocated, as returned by
+/// [`Heap::alloc_counts`].
+///
+/// [`Heap::alloc_counts`]: crate::Heap::alloc_counts
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct AllocCounts {
+    pub small: usize,
+    pub medium: usize,
+    pub large: usize,
+}
+
+/// What a sweep reclaimed, as returned by [`Heap::sweep`].
+///
+/// [`Heap::sweep`]: crate::Heap::sweep
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SweepStats {
+    /// Blocks in which nothing was marked.
+    pub blocks_freed: usize,
+    /// Blocks left with holes large enough to be allocated into again.
+    pub blocks_recycled: usize,
+    pub large_freed: usize,
+    /// Bytes taken by the freed large objects, including their headers and
+    /// padding.
+    pub large_bytes_freed: usize,
+}
+
+/// Whether the cycle an incremental sweep is working through is complete, as
+/// returned by [`Heap::sweep_incremental`].
+///
+/// [`Heap::sweep_incremental`]: crate::Heap::sweep_incremental
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SweepProgress {
+    /// Blocks are left to sweep, the next call picks up where this one stopped.
+    InProgress,
+    /// Every block has been swept, the next call starts a new cycle.
+    Done,
+}
+
+// The blocks an incremental or concurrent sweep has yet to get to, taken off
+// the block lists when the cycle started so allocation can't touch them in the
+// meantime, along with what the cycle has reclaimed so far.
+struct IncrementalSweep<const BLOCK_SIZE: usize> {
+    mark: NonZero<u8>,
+    pending: Vec<(BumpBlock<BLOCK_SIZE>, bool)>,
+    stats: SweepStats,
+    // bytes of the swept objects that survived
+    survived: usize,
+    // the used count when the cycle started
+    used_before: usize,
+    // free lines left in the swept blocks that survived
+    holes: usize,
+}
+
+/// Tuning knobs for a heap, see [`Heap::with_config`]. The default values are
+/// those [`Heap::new`] uses.
+///
+/// [`Heap::with_config`]: crate::Heap::with_config
+/// [`Heap::new`]: crate::Heap::new
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HeapConfig {
+    /// Empty blocks kept after a sweep for later allocations, any beyond that
+    /// are handed back. Blocks reserved with [`Heap::with_min_block_count`] are
+    /// always kept.
+    ///
+    /// [`Heap::with_min_block_count`]: crate::Heap::with_min_block_count
+    pub max_free_blocks: usize,
+    /// The smallest hole, in bytes, that makes a block worth allocating into
+    /// again. A block whose largest hole after a sweep is smaller waits for a
+    /// later sweep instead. With `0` every block with any hole is recycled,
+    /// with more than a block's capacity none ever is.
+    pub recycle_hole_min: usize,
+    /// Dead large objects kept by a sweep for later large allocations, for
+    /// each object size. Any beyond that are handed back.
+    pub max_pooled_large: usize,
+    /// Hands out increasing addresses within each hole, allocating from its
+    /// bottom up, rather than decreasing ones from its top down. Only applies
+    /// to blocks created after the setting is made.
+    pub upward_allocation: bool,
+    /// Blocks a heap handle fills with small objects alone, without a medium
+    /// allocation in between, before it takes free blocks ahead of recycled
+    /// ones. Recycled blocks keep the heap compact, but their holes have to be
+    /// searched for, whereas a free block is one hole spanning the whole block,
+    /// which pays off in such bursts. The `small burst` benchmark compares the
+    /// two. With `0` free blocks always come first, with `usize::MAX` recycled
+    /// ones do. Either way recycled blocks are used before a new block is
+    /// requested.
+    pub small_burst_blocks: usize,
+}
+
+impl Default for HeapConfig {
+    fn default() -> Self {
+        Self {
+            max_free_blocks: MAX_FREE_BLOCKS,
+            recycle_hole_min: RECYCLE_HOLE_MIN,
+            max_pooled_large: MAX_POOLED_LARGE_PER_SIZE,
+            upward_allocation: false,
+            small_burst_blocks: SMALL_BURST_BLOCKS,
+        }
+    }
+}
+
+pub struct BlockStore<const BLOCK_SIZE: usize = { constants::BLOCK_SIZE }> {
     block_count: AtomicUsize,
+    used: AtomicUsize,
+    soft_limit: AtomicUsize,
+    current_mark: AtomicU8,
 
     // TODO use channels instead of mutexes
-    rest: Mutex<Vec<BumpBlock>>,
-    large: Mutex<Vec<LargeBlock>>,
-    recycle: Mutex<Vec<BumpBlock>>,
-    free: Mutex<Vec<BumpBlock>>,
+    rest: BlockList<BumpBlock<BLOCK_SIZE>>,
+    large: BlockList<LargeBlock>,
+    recycle: BlockList<BumpBlock<BLOCK_SIZE>>,
+    free: BlockList<BumpBlock<BLOCK_SIZE>>,
+
+    // tells the store's blocks apart from those of other stores in the block
+    // directory, which resolves arbitrary addresses without taking a lock
+    id: usize,
+    // handles allocating from the store: heap clones, NimixAllocs and the
+    // pointers made by Heap::into_raw. Sweeps on other threads and anything
+    // else holding on to the store without allocating from it aren't counted.
+    handles: AtomicUsize,
+    large_index: Mutex<BTreeMap<usize, Layout>>,
+    // dead large blocks kept for reuse, keyed by the padded object layout they
+    // were made for, which determines both the block layout and where the
+    // object sits
+    large_pool: Mutex<HashMap<Layout, Vec<LargeBlock>>>,
+    // dead large blocks kept for each object layout
+    max_pooled_large: AtomicUsize,
+    // bytes taken up by the blocks in the large pool
+    pooled_large_bytes: AtomicUsize,
+    // bytes taken up by the blocks in the large list
+    large_bytes: AtomicUsize,
+
+    // bytes found live by the last sweep
+    live_bytes: AtomicUsize,
+    // free lines the last sweep found scattered among surviving blocks, and
+    // free lines overall, counting those of the blocks it freed
+    scattered_lines: AtomicUsize,
+    free_lines: AtomicUsize,
+
+    // objects allocated in each size class, for profiling
+    small_allocs: AtomicUsize,
+    medium_allocs: AtomicUsize,
+    large_allocs: AtomicUsize,
+
+    // small and medium objects already visited by mark_if_unmarked, along with
+    // the mark they were visited with, evacuating sweeps may move them. Spread
+    // over shards by block, so threads tracing different blocks seldom contend
+    traced: Box<[Mutex<Traced>]>,
+
+    // objects that evacuating sweeps must not move, the blocks holding them
+    // are flagged as pinned in their metadata as well
+    pins: Mutex<HashSet<usize>>,
+
+    // objects that are marked by every sweep, whatever the mark
+    immortal: Mutex<Vec<(usize, Layout)>>,
+
+    // identities handed out by alloc_with_id, keyed by object address
+    ids: Mutex<HashMap<usize, u64>>,
+    next_id: AtomicU64,
+
+    // run by the sweep that reclaims the object they are registered for
+    finalizers: Mutex<HashMap<usize, Finalizer>>,
+
+    // the slots of weak handles, cleared by the sweep that reclaims their object
+    weak: Mutex<Vec<Arc<AtomicPtr<u8>>>>,
+
+    // with track-allocations, every object allocated since the last sweep, and
+    // what the last sweep found to be unmarked, to catch missed marks
+    #[cfg(feature = "track-allocations")]
+    allocations: Mutex<Vec<(usize, usize)>>,
+    #[cfg(feature = "track-allocations")]
+    unmarked: Mutex<Vec<(usize, usize)>>,
+
+    // set when every allocation is known to share this layout
+    homogeneous: Option<Layout>,
+
+    // when set, surviving blocks are kept in address order
+    ordered_sweep: AtomicBool,
+
+    // free blocks that are never released
+    min_free_blocks: AtomicUsize,
+    // free blocks kept by a sweep before the rest are released
+    max_free_blocks: AtomicUsize,
+    // the smallest hole that gets a block recycled
+    recycle_hole_min: AtomicUsize,
+    // when set, new blocks allocate from the bottom of their holes up
+    upward_allocation: AtomicBool,
+
+    // alignment of blocks requested from the system allocator, a multiple of
+    // the block size
+    block_align: AtomicUsize,
+    // the sizes of the blocks the store allocates and of their lines, read
+    // when a block is made and when a handle is created
+    geometry: Mutex<Geometry>,
+
+    // where blocks and large objects are allocated from
+    backing: Arc<dyn Backing>,
+    // when set, blocks are taken from this region instead of the system allocator
+    region: Option<Region<BLOCK_SIZE>>,
+
+    // when set, blocks are never reused and allocation always moves on to a new block
+    deterministic: AtomicBool,
+
+    observer: OnceLock<Box<dyn HeapObserver>>,
+
+    // when set, running out of memory panics instead of returning an error
+    panic_on_oom: AtomicBool,
+
+    // lines after each marked line that holes may not use
+    conservative_lines: AtomicUsize,
+
+    // blocks filled with small objects in a row after which a handle takes
+    // whole free blocks before searching recycled ones for holes
+    small_burst_blocks: AtomicUsize,
+
+    // sweeps of heaps with at least this many blocks are split across workers
+    parallel_sweep_min_blocks: AtomicUsize,
+    sweep_workers: AtomicUsize,
+    sweep_threads_spawned: AtomicUsize,
+
+    // the cycle of an incremental or concurrent sweep that hasn't gone through
+    // every block yet
+    incremental: Mutex<Option<IncrementalSweep<BLOCK_SIZE>>>,
+
+    // how many of this store's blocks were taken from the process wide pool
+    #[cfg(feature = "block-pool")]
+    pooled: AtomicUsize,
+}
+
+#[cfg(feature = "block-pool")]
+impl<const BLOCK_SIZE: usize> Drop for BlockStore<BLOCK_SIZE> {
+    fn drop(&mut self) {
+        if !self.uses_block_pool() {
+            return;
+        }
+
+        let lists = [&self.free, &self.recycle, &self.rest];
+
+        for list in lists {
+            for block in list.lock().unwrap().drain(..) {
+                block.record().set_owner(None);
+                super::block_pool::give(block);
+            }
+        }
+    }
 }
 
 impl BlockStore {
     pub fn new() -> Self {
+        Self::new_sized()
+    }
+
+    pub fn homogeneous(layout: Layout) -> Self {
+        let mut store = Self::new();
+
+        store.homogeneous = Some(layout);
+        store
+    }
+}
+
+impl<const BLOCK_SIZE: usize> BlockStore<BLOCK_SIZE> {
+    // fails to compile for a block size no geometry can be made with
+    const GEOMETRY: Geometry = match Geometry::new(BLOCK_SIZE, LINE_SIZE) {
+        Ok(geometry) => geometry,
+        Err(_) => panic!("block size must be a power of two from 4KB to 64MB"),
+    };
+
+    pub fn new_sized() -> Self {
         Self {
             block_count: AtomicUsize::new(0),
-            free: Mutex::new(vec![]),
-            recycle: Mutex::new(vec![]),
-            rest: Mutex::new(vec![]),
-            large: Mutex::new(vec![]),
+            used: AtomicUsize::new(0),
+            soft_limit: AtomicUsize::new(usize::MAX),
+            current_mark: AtomicU8::new(FREE_MARK + 1),
+            free: BlockList::new(),
+            recycle: BlockList::new(),
+            rest: BlockList::new(),
+            large: BlockList::new(),
+            id: NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed),
+            handles: AtomicUsize::new(0),
+            large_index: Mutex::new(BTreeMap::new()),
+            large_pool: Mutex::new(HashMap::new()),
+            max_pooled_large: AtomicUsize::new(MAX_POOLED_LARGE_PER_SIZE),
+            pooled_large_bytes: AtomicUsize::new(0),
+            large_bytes: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
+            scattered_lines: AtomicUsize::new(0),
+            free_lines: AtomicUsize::new(0),
+            small_allocs: AtomicUsize::new(0),
+            medium_allocs: AtomicUsize::new(0),
+            large_allocs: AtomicUsize::new(0),
+            traced: (0..TRACED_SHARDS).map(|_| Mutex::new((FREE_MARK, HashMap::new()))).collect(),
+            pins: Mutex::new(HashSet::new()),
+            immortal: Mutex::new(vec![]),
+            ids: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            finalizers: Mutex::new(HashMap::new()),
+            weak: Mutex::new(vec![]),
+            #[cfg(feature = "track-allocations")]
+            allocations: Mutex::new(vec![]),
+            #[cfg(feature = "track-allocations")]
+            unmarked: Mutex::new(vec![]),
+            homogeneous: None,
+            ordered_sweep: AtomicBool::new(false),
+            min_free_blocks: AtomicUsize::new(0),
+            max_free_blocks: AtomicUsize::new(MAX_FREE_BLOCKS),
+            recycle_hole_min: AtomicUsize::new(RECYCLE_HOLE_MIN),
+            upward_allocation: AtomicBool::new(false),
+            block_align: AtomicUsize::new(BLOCK_SIZE),
+            geometry: Mutex::new(Self::GEOMETRY),
+            backing: backing::system(),
+            region: None,
+            deterministic: AtomicBool::new(false),
+            small_burst_blocks: AtomicUsize::new(SMALL_BURST_BLOCKS),
+            panic_on_oom: AtomicBool::new(false),
+            conservative_lines: AtomicUsize::new(CONSERVATIVE_LINES),
+            observer: OnceLock::new(),
+            parallel_sweep_min_blocks: AtomicUsize::new(usize::MAX),
+            sweep_workers: AtomicUsize::new(1),
+            sweep_threads_spawned: AtomicUsize::new(0),
+            incremental: Mutex::new(None),
+            #[cfg(feature = "block-pool")]
+            pooled: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_backing(backing: Arc<dyn Backing>) -> Self {
+        let mut store = Self::new_sized();
+
+        store.backing = backing;
+        store
+    }
+
+    pub fn in_region(region: Region<BLOCK_SIZE>) -> Self {
+        let mut store = Self::new_sized();
+
+        store.region = Some(region);
+        store
+    }
+
+    pub fn get_homogeneous_layout(&self) -> Option<Layout> {
+        self.homogeneous
+    }
+
+    // Visits each slot of a homogeneous heap that lies within a line marked with `mark`.
+    pub fn for_each_live_slot(&self, mark: NonZero<u8>, mut f: impl FnMut(*const u8)) {
+        let layout = self.homogeneous.expect("store is not homogeneous");
+        let stride = layout.pad_to_align().size();
+        let rest = self.rest.lock().unwrap();
+        let recycle = self.recycle.lock().unwrap();
+
+        for block in rest.iter().chain(recycle.iter()) {
+            block.for_each_marked_slot(mark, stride, &mut f);
+        }
+    }
+
+    // The sizes of the blocks the store allocates and of their lines. Handles
+    // keep a copy of their own to allocate with, see AllocHead::geometry.
+    pub fn geometry(&self) -> Geometry {
+        *self.geometry.lock().unwrap()
+    }
+
+    // Blocks keep the geometry they were made with, so the line size can only
+    // change before the store has any, and while no other handle may be
+    // allocating from it. Objects of a homogeneous store must still tile a
+    // line.
+    pub fn set_line_size(&self, line_size: usize) -> Result<(), AllocError> {
+        let geometry = Geometry::new(BLOCK_SIZE, line_size)?;
+
+        if self.homogeneous.is_some_and(|layout| line_size % layout.pad_to_align().size() != 0) {
+            return Err(AllocError::LayoutError);
         }
+
+        if self.block_count() != 0 || self.handle_count() > 1 {
+            return Err(AllocError::LayoutError);
+        }
+
+        *self.geometry.lock().unwrap() = geometry;
+
+        Ok(())
     }
 
     pub fn get_size(&self) -> usize {
         let block_space = self.block_count() * BLOCK_SIZE;
         let large_space = self.count_large_space();
+        let pooled_space = self.pooled_large_bytes.load(Ordering::Relaxed);
+
+        block_space + large_space + pooled_space
+    }
+
+    pub fn set_deterministic(&self, deterministic: bool) {
+        self.deterministic.store(deterministic, Ordering::Relaxed);
+    }
+
+    pub fn set_observer(&self, observer: Box<dyn HeapObserver>) {
+        if self.observer.set(observer).is_err() {
+            panic!("heap already has an observer");
+        }
+    }
+
+    pub fn observer(&self) -> Option<&dyn HeapObserver> {
+        self.observer.get().map(|observer| observer.as_ref())
+    }
+
+    pub fn set_panic_on_oom(&self, panic_on_oom: bool) {
+        self.panic_on_oom.store(panic_on_oom, Ordering::Relaxed);
+    }
+
+    pub fn panics_on_oom(&self) -> bool {
+        self.panic_on_oom.load(Ordering::Relaxed)
+    }
+
+    pub fn set_ordered_sweep(&self, ordered: bool) {
+        self.ordered_sweep.store(ordered, Ordering::Relaxed);
+    }
+
+    // Keeps at least `count` free blocks around, allocating them up front.
+    pub fn set_min_free_blocks(&self, count: usize) -> Result<(), AllocError> {
+        self.min_free_blocks.store(count, Ordering::Relaxed);
+
+        let missing = count.saturating_sub(self.free.lock().unwrap().len());
+
+        for _ in 0..missing {
+            let block = self.new_block()?;
+
+            block.record().set_free(true);
+            self.free.lock().unwrap().push(block);
+        }
+
+        Ok(())
+    }
+
+    // Adds up to `count` blocks to the free list, stopping at the first block
+    // that can't be allocated, and returns how many were added.
+    pub fn prealloc_best_effort(&self, count: usize) -> usize {
+        let mut added = 0;
+
+        while added < count {
+            match self.new_block() {
+                Ok(block) => {
+                    block.record().set_free(true);
+                    self.free.lock().unwrap().push(block);
+                }
+                Err(_) => break,
+            }
+
+            added += 1;
+        }
+
+        added
+    }
+
+    fn min_free_blocks(&self) -> usize {
+        self.min_free_blocks.load(Ordering::Relaxed)
+    }
+
+    pub fn set_block_alignment(&self, align: usize) -> Result<(), AllocError> {
+        Block::<BLOCK_SIZE>::layout_aligned_to(align)?;
+        self.block_align.store(align, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn block_alignment(&self) -> usize {
+        self.block_align.load(Ordering::Relaxed)
+    }
+
+    pub fn set_conservative_lines(&self, lines: usize) {
+        self.conservative_lines.store(lines, Ordering::Relaxed);
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic.load(Ordering::Relaxed)
+    }
+
+    pub fn current_mark(&self) -> NonZero<u8> {
+        NonZero::new(self.current_mark.load(Ordering::Relaxed)).unwrap()
+    }
+
+    pub fn set_live_mark(&self, mark: NonZero<u8>) {
+        self.current_mark.store(mark.get(), Ordering::Relaxed);
+    }
+
+    pub fn get_used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn set_soft_limit(&self, bytes: usize) {
+        self.soft_limit.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn get_soft_limit(&self) -> usize {
+        self.soft_limit.load(Ordering::Relaxed)
+    }
+
+    pub fn get_live_bytes(&self) -> usize {
+        self.live_bytes.load(Ordering::Relaxed)
+    }
+
+    // The share of the free lines found by the last sweep that are scattered
+    // among surviving blocks rather than making up whole free blocks.
+    pub fn fragmentation(&self) -> f32 {
+        let free_lines = self.free_lines.load(Ordering::Relaxed);
+
+        if free_lines == 0 {
+            return 0.0;
+        }
 
-        block_space + large_space
+        self.scattered_lines.load(Ordering::Relaxed) as f32 / free_lines as f32
     }
 
-    pub fn rest(&self, block: BumpBlock) {
+    fn record_fragmentation(&self, scattered_lines: usize, blocks_freed: usize) {
+        self.scattered_lines.store(scattered_lines, Ordering::Relaxed);
+        self.free_lines.store(
+            scattered_lines + blocks_freed * self.geometry().line_count(),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn add_used(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn rest(&self, block: BumpBlock<BLOCK_SIZE>) {
         self.rest.lock().unwrap().push(block);
     }
 
-    pub fn recycle(&self, block: BumpBlock) {
-        if block.current_hole_size() >= RECYCLE_HOLE_MIN {
+    pub fn recycle(&self, block: BumpBlock<BLOCK_SIZE>) {
+        if is_recyclable(&block, self.recycle_hole_min()) && !self.is_deterministic() {
             self.recycle.lock().unwrap().push(block);
         } else {
             self.rest(block);
         }
     }
 
-    pub fn get_head(&self) -> Result<BumpBlock, AllocError> {
+    // Takes a recycled block whose current hole can hold `size` bytes.
+    pub fn take_recycled_fitting(&self, size: usize) -> Option<BumpBlock<BLOCK_SIZE>> {
+        let mut recycle = self.recycle.lock().unwrap();
+        let index = recycle
+            .iter()
+            .position(|block| block.current_hole_size() >= size)?;
+
+        Some(recycle.swap_remove(index))
+    }
+
+    // Hands out a block for a handle that has filled `burst` blocks in a row
+    // with small objects alone. Long enough bursts are given a free block over
+    // a recycled one, as it saves them searching for holes.
+    pub fn get_burst_head(&self, burst: usize) -> Result<BumpBlock<BLOCK_SIZE>, AllocError> {
+        if burst >= self.small_burst_blocks.load(Ordering::Relaxed) && !self.is_deterministic() {
+            if let Some(free_block) = self.free.lock().unwrap().pop() {
+                free_block.record().set_free(false);
+                return Ok(free_block);
+            }
+        }
+
+        self.get_head()
+    }
+
+    pub fn get_head(&self) -> Result<BumpBlock<BLOCK_SIZE>, AllocError> {
+        if self.is_deterministic() {
+            return self.new_block();
+        }
+
         if let Some(recycle_block) = self.recycle.lock().unwrap().pop() {
             Ok(recycle_block)
         } else {
@@ -55,106 +623,2348 @@ impl BlockStore {
         }
     }
 
-    pub fn get_overflow(&self) -> Result<BumpBlock, AllocError> {
+    pub fn get_overflow(&self) -> Result<BumpBlock<BLOCK_SIZE>, AllocError> {
+        if self.is_deterministic() {
+            return self.new_block();
+        }
+
         if let Some(free_block) = self.free.lock().unwrap().pop() {
+            free_block.record().set_free(false);
             Ok(free_block)
         } else {
             self.new_block()
         }
     }
 
-    pub fn block_count(&self) -> usize {
-        self.block_count.load(Ordering::Relaxed)
-    }
+    // Returns the blocks whose card was marked since they were last returned,
+    // cleaning their cards. Blocks currently held by an allocation head are
+    // not inspected.
+    #[cfg(feature = "card-marks")]
+    pub fn dirty_blocks(&self) -> Vec<*const u8> {
+        let rest = self.rest.lock().unwrap();
+        let recycle = self.recycle.lock().unwrap();
 
-    pub fn count_large_space(&self) -> usize {
-        self.large
-            .lock()
-            .unwrap()
-            .iter()
-            .fold(0, |sum, block| sum + block.get_size())
+        rest.iter()
+            .chain(recycle.iter())
+            .filter(|block| block.take_card())
+            .map(|block| block.as_ptr())
+            .collect()
     }
 
-    // large objects are stored with a single byte of meta info to store their mark
-    pub fn create_large(&self, layout: Layout) -> Result<*const u8, AllocError> {
-        assert!(layout.size() >= LARGE_OBJECT_MIN);
+    // Treats every word as a potential pointer, marking the object it points
+    // into. Words that don't point into the data of a block or large object
+    // owned by this store are ignored, as are words pointing into a free block,
+    // since nothing in it is allocated. A small object may start in the line
+    // before the one the word points into, so both lines are marked, and a
+    // medium object has every one of its lines marked, as recorded when it
+    // was allocated.
+    //
+    // Blocks are only released with the free list locked, and on_memory_pressure
+    // releases them with every list locked, so holding the lists for the whole
+    // scan keeps any block it finds from being released before it is marked.
+    pub fn scan_conservative(&self, words: &[usize], mark: NonZero<u8>) {
+        let _rest = self.rest.lock().unwrap();
+        let _recycle = self.recycle.lock().unwrap();
+        let _free = self.free.lock().unwrap();
+        let large_index = self.large_index.lock().unwrap();
 
-        let large_block = LargeBlock::new(layout)?;
-        let ptr = large_block.as_ptr();
+        for &word in words {
+            if let Some(record) = self.find_record(word) {
+                if record.is_free() {
+                    continue;
+                }
 
-        self.large.lock().unwrap().push(large_block);
+                let meta = unsafe { BlockMeta::from_block_ptr(record.base(), record.geometry()) }
+                    .expect("address was checked to be within a block");
+                let line = meta.line_of(word as *const u8);
 
-        Ok(ptr)
+                meta.mark_lines(line.saturating_sub(1)..=line, mark);
+                record.for_each_medium(line, |lines| meta.mark_lines(lines, mark));
+                // a root found this way can't be updated, so the block must stay put
+                meta.mark_untraced(mark);
+            } else if let Some((&start, layout)) = large_index.range(..=word).next_back() {
+                if word < start + layout.size() {
+                    unsafe { LargeBlock::mark(start as *const u8, mark) };
+                }
+            }
+        }
     }
 
-    // REFACTOR THIS: there needs to be a better story behind what this callback is
-    pub fn sweep<F>(&self, mark: NonZero<u8>, sweep_callback: F) 
-    where
-        F: FnOnce()
-    {
-        let mut rest = self.rest.lock().unwrap();
-        let mut large = self.large.lock().unwrap();
-        let mut recycle = self.recycle.lock().unwrap();
+    // Whether the object starting at `addr` was marked with `mark`. Addresses
+    // that don't belong to this store are never live.
+    pub fn is_live(&self, addr: usize, mark: NonZero<u8>) -> bool {
+        if self.find_block(addr).is_some() {
+            unsafe { BlockMeta::from_ptr(addr as *const u8) }
+                .is_ok_and(|meta| meta.get_line(meta.line_of(addr as *const u8)) == mark.get())
+        } else if let Some((start, _)) = self.find_large(addr) {
+            start == addr && unsafe { LargeBlock::is_marked_at(addr as *const u8, mark) }
+        } else {
+            false
+        }
+    }
 
-        sweep_callback();
+    // The size of the allocation holding `addr`. Small and medium objects are
+    // only known by their line marks, so this is the length of the run of
+    // lines around `addr` carrying the same mark, and None if its line isn't
+    // marked. Large objects are known exactly.
+    pub fn allocation_size(&self, addr: usize) -> Option<usize> {
+        if self.find_block(addr).is_some() {
+            let meta = unsafe { BlockMeta::from_ptr(addr as *const u8) }.ok()?;
+            let geometry = meta.geometry();
+            let line = meta.line_of(addr as *const u8);
+            let mark = meta.get_line(line);
 
-        let mut new_rest = vec![];
-        let mut new_recycle = vec![];
-        let mut new_large = vec![];
-        let mut new_free = vec![];
+            if mark == FREE_MARK {
+                return None;
+            }
 
-        while let Some(large_block) = large.pop() {
-            if large_block.is_marked(mark) {
-                new_large.push(large_block);
+            let mut start = line;
+            let mut end = line + 1;
+
+            while start > 0 && meta.get_line(start - 1) == mark {
+                start -= 1;
             }
+
+            while end < geometry.line_count() && meta.get_line(end) == mark {
+                end += 1;
+            }
+
+            Some((end - start) * geometry.line_size())
+        } else {
+            let (_, layout) = self.find_large(addr)?;
+
+            Some(layout.size())
         }
+    }
 
-        *large = new_large;
-        drop(large);
+    // How many sweeps the block or large object holding `addr` has survived.
+    pub fn age_of(&self, addr: usize) -> Option<u8> {
+        if self.find_block(addr).is_some() {
+            unsafe { BlockMeta::from_ptr(addr as *const u8) }.ok().map(|meta| meta.get_age())
+        } else {
+            let (start, _) = self.find_large(addr)?;
 
-        while let Some(mut block) = recycle.pop() {
-            block.reset_hole(mark);
+            Some(unsafe { LargeBlock::age_at(start as *const u8) })
+        }
+    }
 
-            if block.is_marked(mark) {
-                new_recycle.push(block);
-            } else {
-                new_free.push(block);
+    // Marks the object, returning whether this call is the first to mark it
+    // with `mark`. Line marks are shared by every object in the line, so for
+    // small and medium objects the objects visited are tracked separately.
+    //
+    // SAFETY: ptr must point to an object allocated by this store with the given layout
+    pub unsafe fn mark_if_unmarked(&self, ptr: *mut u8, layout: Layout, mark: NonZero<u8>) -> Result<bool, AllocError> {
+        let Some((meta, size_class)) = block_holding(ptr, layout)? else {
+            return Ok(!LargeBlock::swap_mark(ptr, mark));
+        };
+
+        let newly_marked = {
+            let mut traced = self.traced_shard(ptr as usize).lock().unwrap();
+
+            if traced.0 != mark.get() {
+                *traced = (mark.get(), HashMap::new());
             }
+
+            traced.1.insert(ptr as usize, layout).is_none()
+        };
+
+        meta.mark(ptr, layout.size() as u32, size_class, mark)?;
+
+        Ok(newly_marked)
+    }
+
+    // The shard of `traced` the objects of the block holding `addr` go in.
+    fn traced_shard(&self, addr: usize) -> &Mutex<Traced> {
+        &self.traced[(addr / BLOCK_SIZE) % TRACED_SHARDS]
+    }
+
+    // Forgets every object traced so far, whatever mark it was traced with.
+    fn clear_traced(&self) {
+        for shard in self.traced.iter() {
+            *shard.lock().unwrap() = (FREE_MARK, HashMap::new());
+        }
+    }
+
+    pub fn pin(&self, ptr: *const u8) -> Result<(), AllocError> {
+        let addr = ptr as usize;
+
+        if self.find_block(addr).is_some() {
+            unsafe { BlockMeta::from_ptr(ptr)? }.set_pinned(true);
+        } else if self.find_large(addr).is_none() {
+            return Err(AllocError::UnknownObject);
         }
 
-        while let Some(mut block) = rest.pop() {
-            block.reset_hole(mark);
+        self.pins.lock().unwrap().insert(addr);
 
-            if block.is_marked(mark) {
-                if block.current_hole_size() >= RECYCLE_HOLE_MIN {
-                    new_recycle.push(block);
-                } else {
-                    new_rest.push(block);
-                }
-            } else {
-                new_free.push(block);
+        Ok(())
+    }
+
+    pub fn unpin(&self, ptr: *const u8) -> Result<(), AllocError> {
+        let mut pins = self.pins.lock().unwrap();
+
+        if !pins.remove(&(ptr as usize)) {
+            return Err(AllocError::UnknownObject);
+        }
+
+        self.refresh_pinned(ptr as usize, &pins);
+
+        Ok(())
+    }
+
+    // Flags the block holding `addr` as pinned as long as any of `pins` lies
+    // in it.
+    fn refresh_pinned(&self, addr: usize, pins: &HashSet<usize>) {
+        if let Some(block) = self.find_block(addr) {
+            let pinned = pins.iter().any(|&pin| self.find_block(pin) == Some(block));
+
+            if let Ok(meta) = unsafe { BlockMeta::from_ptr(addr as *const u8) } {
+                meta.set_pinned(pinned);
             }
         }
+    }
 
-        *rest = new_rest;
-        *recycle = new_recycle;
-        drop(rest);
-        drop(recycle);
+    pub fn immortalize(&self, ptr: *const u8, layout: Layout) -> Result<(), AllocError> {
+        self.geometry().size_class(layout)?;
 
-        let mut free = self.free.lock().unwrap();
-        while let Some(free_block) = new_free.pop() {
-            if free.len() < MAX_FREE_BLOCKS {
-                free.push(free_block);
-            } else {
-                break;
+        self.immortal.lock().unwrap().push((ptr as usize, layout));
+
+        Ok(())
+    }
+
+    pub fn assign_id(&self, ptr: *const u8) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.ids.lock().unwrap().insert(ptr as usize, id);
+
+        id
+    }
+
+    pub fn register_finalizer(&self, ptr: *const u8, finalizer: Finalizer) {
+        self.finalizers.lock().unwrap().insert(ptr as usize, finalizer);
+    }
+
+    pub fn register_weak(&self, ptr: *const u8) -> WeakHandle {
+        let handle = WeakHandle::new(ptr);
+
+        self.weak.lock().unwrap().push(handle.slot());
+
+        handle
+    }
+
+    #[cfg(feature = "track-allocations")]
+    pub fn record_allocation(&self, ptr: *const u8, size: usize) {
+        self.allocations.lock().unwrap().push((ptr as usize, size));
+    }
+
+    // The objects the last sweep reclaimed, as recorded by record_allocation.
+    #[cfg(feature = "track-allocations")]
+    pub fn take_unmarked(&self) -> Vec<(*const u8, usize)> {
+        std::mem::take(&mut *self.unmarked.lock().unwrap())
+            .into_iter()
+            .map(|(addr, size)| (addr as *const u8, size))
+            .collect()
+    }
+
+    pub fn get_id(&self, ptr: *const u8) -> Option<u64> {
+        self.ids.lock().unwrap().get(&(ptr as usize)).copied()
+    }
+
+    // The runs of lines marked with `mark` in the rest and recycle blocks,
+    // followed by the large objects marked with it, each as a start address
+    // and length.
+    pub fn live_ranges(&self, mark: NonZero<u8>) -> Vec<(*const u8, usize)> {
+        let mut ranges = vec![];
+
+        for block in self.rest.lock().unwrap().iter().chain(self.recycle.lock().unwrap().iter()) {
+            block.for_each_marked_run(mark, |ptr, len| ranges.push((ptr, len)));
+        }
+
+        for (&addr, layout) in self.large_index.lock().unwrap().iter() {
+            if unsafe { LargeBlock::is_marked_at(addr as *const u8, mark) } {
+                ranges.push((addr as *const u8, layout.size()));
             }
         }
 
-        self.block_count.fetch_sub(new_free.len(), Ordering::Relaxed);
+        ranges
     }
 
-    fn new_block(&self) -> Result<BumpBlock, AllocError> {
-        self.block_count.fetch_add(1, Ordering::Relaxed);
-        BumpBlock::new()
+    // Checks that no two live objects overlap. Objects marked without their
+    // extent being recorded are only seen through the runs of lines they mark,
+    // so the runs are checked against the large objects first. The objects
+    // whose extent is known are then checked one by one, so two of them that
+    // share a marked line still clash, and each must lie within the run
+    // holding its start.
+    pub fn verify_no_overlap(&self, mark: NonZero<u8>) -> Result<(), OverlapError> {
+        let mut ranges = self.live_ranges(mark);
+
+        ranges.sort_by_key(|(ptr, _)| *ptr as usize);
+        check_disjoint(&ranges)?;
+
+        let mut objects = self.live_objects(mark);
+
+        objects.sort_by_key(|(ptr, size)| (*ptr as usize, *size));
+        // an object may be known both as traced and as allocated
+        objects.dedup();
+        check_disjoint(&objects)?;
+
+        for &object in objects.iter() {
+            let start = object.0 as usize;
+            let end = start + object.1;
+            // the first range ending past the object's start
+            let first = ranges.partition_point(|&(ptr, len)| ptr as usize + len <= start);
+
+            for &range in ranges[first..].iter().take_while(|(ptr, _)| (*ptr as usize) < end) {
+                // large objects are ranges of their own
+                if range != object && range.0 as usize > start {
+                    return Err(OverlapError { first: object, second: range });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The live objects whose extent is known, each as a start address and
+    // size: those traced with mark_if_unmarked, the immortal ones, those
+    // recorded by record_allocation and the large ones marked with `mark`.
+    fn live_objects(&self, mark: NonZero<u8>) -> Vec<(*const u8, usize)> {
+        let mut objects: Vec<(*const u8, usize)> = vec![];
+
+        for shard in self.traced.iter() {
+            let traced = shard.lock().unwrap();
+
+            if traced.0 == mark.get() {
+                objects.extend(traced.1.iter().map(|(&addr, layout)| (addr as *const u8, layout.size())));
+            }
+        }
+
+        objects.extend(
+            self.immortal
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|&(addr, layout)| (addr as *const u8, layout.size())),
+        );
+
+        #[cfg(feature = "track-allocations")]
+        objects.extend(
+            self.allocations
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|&&(addr, _)| self.is_live(addr, mark))
+                .map(|&(addr, size)| (addr as *const u8, size)),
+        );
+
+        for (&addr, layout) in self.large_index.lock().unwrap().iter() {
+            if unsafe { LargeBlock::is_marked_at(addr as *const u8, mark) } {
+                objects.push((addr as *const u8, layout.size()));
+            }
+        }
+
+        objects
+    }
+
+    // Returns the base of the block whose data region holds `addr`.
+    pub fn find_block(&self, addr: usize) -> Option<usize> {
+        self.find_record(addr).map(|record| record.base() as usize)
+    }
+
+    // Returns the record of the block whose data region holds `addr`, if the
+    // block is one of this store's.
+    pub fn find_record(&self, addr: usize) -> Option<BlockRecord> {
+        let record = block_directory::get(addr as *const u8)?;
+        let offset = addr - record.base() as usize;
+
+        (offset < record.geometry().capacity() && record.is_owned_by(self.id)).then_some(record)
+    }
+
+    // Returns the start and layout of the large object holding `addr`.
+    pub fn find_large(&self, addr: usize) -> Option<(usize, Layout)> {
+        let large_index = self.large_index.lock().unwrap();
+        let (&start, &layout) = large_index.range(..=addr).next_back()?;
+
+        if addr < start + layout.size() {
+            Some((start, layout))
+        } else {
+            None
+        }
+    }
+
+    // Returns the fraction of lines marked with `mark` in each block that isn't
+    // free, least utilized first.
+    pub fn utilization(&self, mark: NonZero<u8>) -> Vec<(BlockId, f32)> {
+        let rest = self.rest.lock().unwrap();
+        let recycle = self.recycle.lock().unwrap();
+        let mut report: Vec<(BlockId, f32)> = rest
+            .iter()
+            .chain(recycle.iter())
+            .map(|block| {
+                let live_lines = block.marked_line_count(mark);
+
+                (block.id(), live_lines as f32 / block.geometry().line_count() as f32)
+            })
+            .collect();
+
+        report.sort_by(|a, b| a.1.total_cmp(&b.1));
+        report
+    }
+
+    // Reads the lengths the lists had when last unlocked, without locking any
+    // of them, so the counts may be off by blocks that are being moved between
+    // lists at the time.
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            block_count: self.block_count(),
+            free_block_count: self.free.len(),
+            recycle_block_count: self.recycle.len(),
+            rest_block_count: self.rest.len(),
+            large_object_count: self.large.len(),
+            large_bytes: self.count_large_space(),
+            pooled_large_bytes: self.pooled_large_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn add_handle(&self) {
+        self.handles.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn remove_handle(&self) {
+        self.handles.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn handle_count(&self) -> usize {
+        self.handles.load(Ordering::Relaxed)
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.block_count.load(Ordering::Relaxed)
+    }
+
+    pub fn count_large_space(&self) -> usize {
+        self.large_bytes.load(Ordering::Relaxed)
+    }
+
+    // large objects are stored with a single byte of meta info to store their mark
+    pub fn create_large(&self, layout: Layout) -> Result<*const u8, AllocError> {
+        assert_eq!(self.geometry().size_class(layout)?, SizeClass::Large);
+
+        let large_block = match self.take_pooled_large(layout) {
+            Some(large_block) => {
+                large_block.reset();
+                large_block
+            }
+            None => LargeBlock::new(layout, &self.backing)?,
+        };
+        let ptr = large_block.as_ptr();
+
+        self.add_used(large_block.get_size());
+        self.large_bytes.fetch_add(large_block.get_size(), Ordering::Relaxed);
+
+        // the list and the index are updated together, see free_large
+        let mut large = self.large.lock().unwrap();
+
+        self.large_index.lock().unwrap().insert(ptr as usize, layout);
+        large.push(large_block);
+        drop(large);
+
+        self.count_alloc(SizeClass::Large);
+
+        Ok(ptr)
+    }
+
+    pub fn count_alloc(&self, size_class: SizeClass) {
+        let counter = match size_class {
+            SizeClass::Small => &self.small_allocs,
+            SizeClass::Medium => &self.medium_allocs,
+            SizeClass::Large => &self.large_allocs,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn alloc_counts(&self) -> AllocCounts {
+        AllocCounts {
+            small: self.small_allocs.load(Ordering::Relaxed),
+            medium: self.medium_allocs.load(Ordering::Relaxed),
+            large: self.large_allocs.load(Ordering::Relaxed),
+        }
+    }
+
+    // Takes the smallest pooled block that the object fits in. A block fits
+    // when it was made for an object at least as large and as aligned, but no
+    // more than twice as large, so small objects don't hold on to big blocks.
+    fn take_pooled_large(&self, layout: Layout) -> Option<LargeBlock> {
+        let layout = layout.pad_to_align();
+        let mut large_pool = self.large_pool.lock().unwrap();
+        let fits = |pooled: &Layout| {
+            pooled.align() >= layout.align()
+                && pooled.size() >= layout.size()
+                && pooled.size() / 2 <= layout.size()
+        };
+        let key = *large_pool
+            .iter()
+            .filter(|(pooled, blocks)| !blocks.is_empty() && fits(pooled))
+            .min_by_key(|(pooled, _)| pooled.size())?
+            .0;
+        let large_block = large_pool.get_mut(&key)?.pop()?;
+
+        self.pooled_large_bytes.fetch_sub(large_block.get_size(), Ordering::Relaxed);
+
+        Some(large_block)
+    }
+
+    // Frees the large object at `ptr` right away rather than at the next sweep.
+    pub fn free_large(&self, ptr: *const u8) -> Result<(), AllocError> {
+        let addr = ptr as usize;
+        // locked in the order sweeps take them, and held together so the list
+        // and the index never disagree about the object
+        let mut large = self.large.lock().unwrap();
+        let mut large_index = self.large_index.lock().unwrap();
+
+        if !large_index.contains_key(&addr) {
+            return Err(AllocError::UnknownObject);
+        }
+
+        let index = large
+            .iter()
+            .position(|block| block.as_ptr() == ptr)
+            .ok_or(AllocError::UnknownObject)?;
+        let large_block = large.swap_remove(index);
+
+        large_index.remove(&addr);
+        drop(large_index);
+        drop(large);
+
+        self.large_bytes.fetch_sub(large_block.get_size(), Ordering::Relaxed);
+        self.ids.lock().unwrap().remove(&addr);
+        self.finalizers.lock().unwrap().remove(&addr);
+        self.pins.lock().unwrap().remove(&addr);
+        self.immortal.lock().unwrap().retain(|(obj, _)| *obj != addr);
+
+        #[cfg(feature = "track-allocations")]
+        self.allocations.lock().unwrap().retain(|(obj, _)| *obj != addr);
+
+        self.used.fetch_sub(large_block.get_size(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    // Finds the object realloc is about to move, before its new copy is
+    // allocated, so an unknown pointer fails without side effects. Returns the
+    // record of the block holding it, or None for a large object.
+    pub fn find_moved(&self, ptr: *const u8) -> Result<Option<BlockRecord>, AllocError> {
+        if let Some(record) = block_directory::get(ptr) {
+            return Ok(Some(record));
+        }
+
+        if !self.large_index.lock().unwrap().contains_key(&(ptr as usize)) {
+            return Err(AllocError::UnknownObject);
+        }
+
+        Ok(None)
+    }
+
+    // Frees an object that was moved elsewhere, so it is reclaimed even if it
+    // was marked, by the birth color or otherwise. The lines only the object
+    // takes up are cleared and a medium object is forgotten by the block
+    // directory, while lines it shares with its neighbours are left alone.
+    // Large objects are freed outright.
+    // SAFETY: ptr must point to an object allocated by this store with the
+    // given layout, which is not used anymore, found by find_moved
+    pub unsafe fn free_moved(
+        &self,
+        ptr: *const u8,
+        layout: Layout,
+        record: Option<BlockRecord>,
+    ) -> Result<(), AllocError> {
+        let Some(record) = record else {
+            return self.free_large(ptr);
+        };
+        let line_size = record.geometry().line_size();
+        let start = ptr as usize - record.base() as usize;
+        let end = start + layout.size();
+
+        BlockMeta::from_block_ptr(record.base(), record.geometry())?
+            .free_lines(start.div_ceil(line_size)..end / line_size);
+
+        if layout.size() > line_size {
+            record.unrecord_medium(start / line_size, (end - 1) / line_size);
+        }
+
+        Ok(())
+    }
+
+    // The callback runs once the block lists are locked and before anything is
+    // reclaimed, so handles that need a block wait for it. This is where a
+    // runtime can flip the mark it considers live.
+    //
+    // Returns what was reclaimed, along with the blocks that were moved to the
+    // free list. Blocks released back to the allocator are counted as freed but
+    // not listed.
+    pub fn sweep<F>(&self, mark: NonZero<u8>, sweep_callback: F) -> (SweepStats, Vec<BlockId>)
+    where
+        F: FnOnce()
+    {
+        self.sweep_retaining(mark, sweep_callback, |_| false)
+    }
+
+    // Sweeps by the marks of the `live` color. When that is the second color,
+    // its marks replace the primary ones before the sweep, either way the
+    // second color is cleared so it can be used to mark the next cycle.
+    #[cfg(feature = "dual-mark")]
+    pub fn sweep_color<F>(&self, live: Color, mark: NonZero<u8>, sweep_callback: F) -> (SweepStats, Vec<BlockId>)
+    where
+        F: FnOnce()
+    {
+        self.sweep_locked(mark, sweep_callback, |_| false, |rest, recycle, large| {
+            for block in rest.iter().chain(recycle.iter()) {
+                match live {
+                    Color::Primary => block.clear_secondary(),
+                    Color::Secondary => block.fold_secondary(mark),
+                }
+            }
+
+            for large_block in large.iter() {
+                match live {
+                    Color::Primary => large_block.clear_secondary(),
+                    Color::Secondary => large_block.fold_secondary(mark),
+                }
+            }
+        })
+    }
+
+    // Sweeps like `sweep`, after moving the live objects out of sparsely
+    // marked blocks so those blocks can be freed. Only blocks whose marked
+    // lines are all covered by objects traced with mark_if_unmarked, and in
+    // which nothing was marked any other way, are evacuated, since only then
+    // is every live object in them known.
+    pub fn sweep_evacuating<F, R>(&self, mark: NonZero<u8>, sweep_callback: F, relocate: R) -> (SweepStats, Vec<BlockId>)
+    where
+        F: FnOnce(),
+        R: FnMut(*const u8, *const u8),
+    {
+        self.sweep_locked(mark, sweep_callback, |_| false, |rest, recycle, _| {
+            self.evacuate(mark, rest, recycle, relocate);
+        })
+    }
+
+    // Moves the traced objects out of the evacuable blocks of the locked rest
+    // and recycle lists, leaving every block in `rest` for the sweep to sort
+    // out again.
+    fn evacuate<R>(
+        &self,
+        mark: NonZero<u8>,
+        rest: &mut Vec<BumpBlock<BLOCK_SIZE>>,
+        recycle: &mut Vec<BumpBlock<BLOCK_SIZE>>,
+        mut relocate: R,
+    )
+    where
+        R: FnMut(*const u8, *const u8),
+    {
+        for &(addr, layout) in self.immortal.lock().unwrap().iter() {
+            // marked ahead of the sweep so blocks holding them aren't evacuated
+            // unless they were traced, their records follow them if moved
+            unsafe { mark_traced(addr as *mut u8, layout, mark).unwrap() };
+        }
+
+        let mut by_block: HashMap<usize, Vec<(usize, Layout)>> = HashMap::new();
+
+        for shard in self.traced.iter() {
+            let traced = std::mem::take(&mut *shard.lock().unwrap());
+
+            if traced.0 != mark.get() {
+                continue;
+            }
+
+            for (addr, layout) in traced.1 {
+                if let Some(block) = self.find_block(addr) {
+                    by_block.entry(block).or_default().push((addr, layout));
+                }
+            }
+        }
+
+        let mut candidates = vec![];
+        let mut keep = vec![];
+
+        for block in rest.drain(..).chain(recycle.drain(..)) {
+            let objects = by_block.remove(&(block.as_ptr() as usize)).unwrap_or_default();
+
+            if Self::is_evacuable(&block, &objects, mark) {
+                candidates.push((block, objects));
+            } else {
+                keep.push(block);
+            }
+        }
+
+        rest.extend(keep);
+
+        let mut dest: Option<BumpBlock<BLOCK_SIZE>> = None;
+        let mut out_of_room = false;
+        // where each moved object went, to forward the store's records with
+        let mut moved: HashMap<usize, usize> = HashMap::new();
+
+        for (block, mut objects) in candidates {
+            objects.sort_by_key(|(addr, _)| *addr);
+
+            for (old, layout) in objects {
+                if out_of_room {
+                    break;
+                }
+
+                // without room to move to, the rest of the objects stay put
+                let Some(new) = self.evacuation_target(&mut dest, layout, rest) else {
+                    out_of_room = true;
+                    break;
+                };
+
+                unsafe {
+                    std::ptr::copy_nonoverlapping(old as *const u8, new as *mut u8, layout.size());
+                    mark_object(new as *mut u8, layout, mark).unwrap();
+                }
+
+                moved.insert(old, new as usize);
+                relocate(old as *const u8, new);
+            }
+
+            // a block that was only partly evacuated keeps all of its marks
+            if !out_of_room {
+                block.clear_marks();
+            }
+
+            rest.push(block);
+        }
+
+        if let Some(dest) = dest {
+            rest.push(dest);
+        }
+
+        self.forward(&moved);
+    }
+
+    // Allocates room for an evacuated object, moving on to another block once
+    // `dest` is full.
+    fn evacuation_target(
+        &self,
+        dest: &mut Option<BumpBlock<BLOCK_SIZE>>,
+        layout: Layout,
+        rest: &mut Vec<BumpBlock<BLOCK_SIZE>>,
+    ) -> Option<*const u8> {
+        if let Some(new) = dest.as_mut().and_then(|dest| dest.inner_alloc(layout)) {
+            return Some(new);
+        }
+
+        if let Some(full) = dest.take() {
+            rest.push(full);
+        }
+
+        let mut block = self.get_overflow().ok()?;
+        let new = block.inner_alloc(layout);
+
+        *dest = Some(block);
+        new
+    }
+
+    fn is_evacuable(block: &BumpBlock<BLOCK_SIZE>, objects: &[(usize, Layout)], mark: NonZero<u8>) -> bool {
+        let geometry = block.geometry();
+        let marked = block.marked_line_count(mark);
+
+        if marked == 0 || marked > geometry.evacuation_line_max() {
+            return false;
+        }
+
+        let Ok(meta) = (unsafe { BlockMeta::from_block_ptr(block.as_ptr(), geometry) }) else {
+            return false;
+        };
+        let mut covered = vec![false; geometry.line_count()];
+
+        for &(addr, layout) in objects {
+            let Ok(size_class) = geometry.size_class(layout) else {
+                return false;
+            };
+            let Ok(lines) = meta.marked_lines(addr as *mut u8, layout.size() as u32, size_class) else {
+                return false;
+            };
+
+            for line in lines {
+                covered[line] = true;
+            }
+        }
+
+        // an object marked without being traced may share a covered line
+        if meta.is_pinned() || meta.has_untraced(mark) {
+            return false;
+        }
+
+        (0..geometry.line_count()).all(|line| covered[line] || meta.get_line(line) != mark.get())
+    }
+
+    // Moves whatever the store tracks about each moved object from its old
+    // address to its new one, taking each lock once for every object moved.
+    // Records are taken out before any is put back, so a new address that is
+    // also an old one can't be overwritten.
+    fn forward(&self, moved: &HashMap<usize, usize>) {
+        if moved.is_empty() {
+            return;
+        }
+
+        {
+            let mut ids = self.ids.lock().unwrap();
+            let forwarded: Vec<_> = moved
+                .iter()
+                .filter_map(|(old, &new)| Some((new, ids.remove(old)?)))
+                .collect();
+
+            ids.extend(forwarded);
+        }
+
+        {
+            let mut finalizers = self.finalizers.lock().unwrap();
+            let forwarded: Vec<_> = moved
+                .iter()
+                .filter_map(|(old, &new)| Some((new, finalizers.remove(old)?)))
+                .collect();
+
+            finalizers.extend(forwarded);
+        }
+
+        for slot in self.weak.lock().unwrap().iter() {
+            let old = slot.load(Ordering::Acquire);
+
+            if let Some(&new) = moved.get(&(old as usize)) {
+                let _ = slot.compare_exchange(old, new as *mut u8, Ordering::AcqRel, Ordering::Relaxed);
+            }
+        }
+
+        // later sweeps mark immortal objects where they are recorded, which
+        // must not be the block the object was moved out of
+        for (addr, _) in self.immortal.lock().unwrap().iter_mut() {
+            if let Some(&new) = moved.get(addr) {
+                *addr = new;
+            }
+        }
+
+        #[cfg(feature = "track-allocations")]
+        for (addr, _) in self.allocations.lock().unwrap().iter_mut() {
+            if let Some(&new) = moved.get(addr) {
+                *addr = new;
+            }
+        }
+    }
+
+    // Sweeps like `sweep`, but also retains every large object, and every block,
+    // whose address `keep` returns true for. Kept blocks are left untouched,
+    // their lines are neither cleared nor recolored.
+    pub fn sweep_retaining<F, K>(&self, mark: NonZero<u8>, sweep_callback: F, keep: K) -> (SweepStats, Vec<BlockId>)
+    where
+        F: FnOnce(),
+        K: Fn(*const u8) -> bool,
+    {
+        self.sweep_locked(mark, sweep_callback, keep, |_, _, _| {})
+    }
+
+    // The sweep every other one goes through. `prepare` is handed the locked
+    // rest, recycle and large lists right after the callback, so sweeps that
+    // rework marks or move objects first see whatever the callback marked.
+    fn sweep_locked<F, K, P>(&self, mark: NonZero<u8>, sweep_callback: F, keep: K, prepare: P) -> (SweepStats, Vec<BlockId>)
+    where
+        F: FnOnce(),
+        K: Fn(*const u8) -> bool,
+        P: FnOnce(&mut Vec<BumpBlock<BLOCK_SIZE>>, &mut Vec<BumpBlock<BLOCK_SIZE>>, &mut Vec<LargeBlock>),
+    {
+        self.abandon_incremental();
+
+        let mut rest = self.rest.lock().unwrap();
+        let mut large = self.large.lock().unwrap();
+        let mut recycle = self.recycle.lock().unwrap();
+
+        if let Some(observer) = self.observer() {
+            observer.on_sweep_start(mark);
+        }
+
+        sweep_callback();
+        prepare(&mut rest, &mut recycle, &mut large);
+
+        let (mut stats, mut used, dead) = self.sweep_objects(mark, &keep, &mut large);
+        drop(large);
+
+        #[cfg(feature = "track-allocations")]
+        self.take_swept_allocations(mark, &keep, rest.iter().chain(recycle.iter()));
+
+        let mut blocks: Vec<(BumpBlock<BLOCK_SIZE>, bool)> = recycle.drain(..).map(|block| (block, true)).collect();
+
+        blocks.extend(rest.drain(..).map(|block| (block, false)));
+
+        let (kept, blocks): (Vec<_>, Vec<_>) = blocks
+            .into_iter()
+            .partition(|(block, _)| keep(block.as_ptr()));
+
+        let mut swept = self.sweep_blocks(blocks, mark);
+        let new_free = swept.free;
+
+        stats.blocks_freed = new_free.len();
+        stats.blocks_recycled = swept.recycle.len();
+
+        // which lines of a kept block are in use isn't known, so all of them count
+        for (block, recycled) in kept {
+            used += block.capacity();
+
+            if recycled {
+                swept.recycle.push(block);
+            } else {
+                swept.rest.push(block);
+            }
+        }
+
+        used += swept.used;
+
+        // allocations made into blocks still held by an allocation head are
+        // reported once the block is handed back
+        self.used.store(used, Ordering::Relaxed);
+        self.live_bytes.store(used, Ordering::Relaxed);
+        self.record_fragmentation(swept.holes, stats.blocks_freed);
+
+        // Every line that survived now carries `mark` and every other line has
+        // been cleared, so no stale color is left behind when the marks wrap
+        // around to this one again.
+        self.current_mark.store(next_mark(mark).get(), Ordering::Relaxed);
+
+        // walking the blocks in address order lets the next sweep stream
+        // through their metadata rather than jump around the heap
+        let ordered = self.ordered_sweep.load(Ordering::Relaxed);
+
+        if ordered {
+            swept.rest.sort_unstable_by_key(|block| block.as_ptr() as usize);
+        }
+
+        *rest = swept.rest;
+        drop(rest);
+        drop(recycle);
+
+        // the holes of recycled blocks may hold dead objects a finalizer reads,
+        // so they are only handed out once the finalizers have run
+        self.finalize(dead);
+
+        let mut recycle = self.recycle.lock().unwrap();
+
+        recycle.extend(swept.recycle);
+
+        if ordered {
+            recycle.sort_unstable_by_key(|block| block.as_ptr() as usize);
+        }
+
+        drop(recycle);
+
+        let freed = self.free_swept(new_free);
+
+        if let Some(observer) = self.observer() {
+            observer.on_sweep_end(mark);
+        }
+
+        (stats, freed)
+    }
+
+    // Marks the immortal objects and forgets whatever the sweep is about to
+    // reclaim besides the blocks themselves: ids, pins, finalizers, weak handles
+    // and large objects. Returns what was reclaimed along with the bytes taken
+    // by the surviving large objects, and the dead objects to hand to finalize.
+    fn sweep_objects<K>(
+        &self,
+        mark: NonZero<u8>,
+        keep: &K,
+        large: &mut Vec<LargeBlock>,
+    ) -> (SweepStats, usize, DeadObjects)
+    where
+        K: Fn(*const u8) -> bool,
+    {
+        for &(addr, layout) in self.immortal.lock().unwrap().iter() {
+            // the layout was checked when the object was made immortal
+            unsafe { mark_traced(addr as *mut u8, layout, mark).unwrap() };
+        }
+
+        // addresses visited this cycle may be handed out again after the sweep
+        self.clear_traced();
+
+        // ids of dead objects must be forgotten before their memory can be reused
+        self.ids.lock().unwrap().retain(|&addr, _| {
+            let owner = match self.find_block(addr) {
+                Some(block) => block,
+                None => addr,
+            };
+
+            self.is_live(addr, mark) || keep(owner as *const u8)
+        });
+
+        // dead objects no longer need pinning, nor do their blocks
+        {
+            let mut pins = self.pins.lock().unwrap();
+            let dead: Vec<usize> = pins
+                .iter()
+                .copied()
+                .filter(|&addr| {
+                    let owner = match self.find_block(addr) {
+                        Some(block) => block,
+                        None => addr,
+                    };
+
+                    !(self.is_live(addr, mark) || keep(owner as *const u8))
+                })
+                .collect();
+
+            for addr in dead.iter() {
+                pins.remove(addr);
+            }
+
+            for addr in dead {
+                self.refresh_pinned(addr, &pins);
+            }
+        }
+
+        // finalizers are taken out so none runs twice, they run once the block
+        // lists are unlocked, see finalize
+        let finalizers: Vec<(usize, Finalizer)> = {
+            let mut finalizers = self.finalizers.lock().unwrap();
+            let dead_addrs: Vec<usize> = finalizers
+                .keys()
+                .copied()
+                .filter(|&addr| {
+                    let owner = match self.find_block(addr) {
+                        Some(block) => block,
+                        None => addr,
+                    };
+
+                    !(self.is_live(addr, mark) || keep(owner as *const u8))
+                })
+                .collect();
+
+            dead_addrs
+                .into_iter()
+                .filter_map(|addr| finalizers.remove_entry(&addr))
+                .collect()
+        };
+
+        // slots whose handles were all dropped no longer need to be tracked
+        self.weak.lock().unwrap().retain(|slot| {
+            let addr = slot.load(Ordering::Relaxed) as usize;
+            let owner = match self.find_block(addr) {
+                Some(block) => block,
+                None => addr,
+            };
+
+            if !(self.is_live(addr, mark) || keep(owner as *const u8)) {
+                WeakHandle::clear(slot);
+
+                return false;
+            }
+
+            Arc::strong_count(slot) > 1
+        });
+
+        let mut new_large = vec![];
+        let mut dead_large = vec![];
+        let mut used = 0;
+        let mut stats = SweepStats::default();
+
+        let mut large_index = self.large_index.lock().unwrap();
+
+        while let Some(large_block) = large.pop() {
+            if large_block.is_marked(mark) || keep(large_block.as_ptr()) {
+                used += large_block.get_size();
+                large_block.increment_age();
+                new_large.push(large_block);
+            } else {
+                self.large_bytes.fetch_sub(large_block.get_size(), Ordering::Relaxed);
+
+                if large_index.remove(&(large_block.as_ptr() as usize)).is_some() {
+                    stats.large_freed += 1;
+                    stats.large_bytes_freed += large_block.get_size();
+                    dead_large.push(large_block);
+                }
+            }
+        }
+
+        *large = new_large;
+        drop(large_index);
+
+        (stats, used, DeadObjects { finalizers, large: dead_large })
+    }
+
+    // Runs the finalizers of the dead objects, then pools their large blocks.
+    // No block list is locked, so a finalizer may use the heap, yet nothing
+    // the finalizers read has been handed out again: the callers only publish
+    // recycled and free blocks once this returns.
+    fn finalize(&self, dead: DeadObjects) {
+        for (addr, finalizer) in dead.finalizers {
+            finalizer(addr as *const u8);
+        }
+
+        let mut large_pool = self.large_pool.lock().unwrap();
+
+        for large_block in dead.large {
+            let pooled = large_pool.entry(large_block.obj_layout()).or_default();
+
+            if pooled.len() < self.max_pooled_large.load(Ordering::Relaxed) {
+                self.pooled_large_bytes.fetch_add(large_block.get_size(), Ordering::Relaxed);
+                pooled.push(large_block);
+            }
+        }
+    }
+
+    // Sets aside the recorded allocations the sweep of `blocks`, and of the
+    // large objects, is about to reclaim. Those in blocks held by an allocation
+    // head aren't swept, so they stay recorded for a later sweep.
+    #[cfg(feature = "track-allocations")]
+    fn take_swept_allocations<'a, K>(
+        &self,
+        mark: NonZero<u8>,
+        keep: &K,
+        blocks: impl Iterator<Item = &'a BumpBlock<BLOCK_SIZE>>,
+    )
+    where
+        K: Fn(*const u8) -> bool,
+    {
+        let swept: HashSet<usize> = blocks.map(|block| block.as_ptr() as usize).collect();
+        let mut allocations = self.allocations.lock().unwrap();
+        let (kept, unmarked) = allocations.drain(..).partition(|&(addr, _)| {
+            let owner = match self.find_block(addr) {
+                Some(block) if !swept.contains(&block) => return true,
+                Some(block) => block,
+                None => addr,
+            };
+
+            self.is_live(addr, mark) || keep(owner as *const u8)
+        });
+
+        *allocations = kept;
+        *self.unmarked.lock().unwrap() = unmarked;
+    }
+
+    // Keeps as many of the swept free blocks as the store is configured to,
+    // releasing the others, and returns the ids of those kept.
+    fn free_swept(&self, mut new_free: Vec<BumpBlock<BLOCK_SIZE>>) -> Vec<BlockId> {
+        if let Some(observer) = self.observer() {
+            for block in new_free.iter() {
+                observer.on_block_freed(block.id());
+            }
+        }
+
+        // blocks borrowed from a region can't be handed back, so they are all kept
+        let mut free = self.free.lock().unwrap();
+        let mut freed = vec![];
+        let max_free = self.max_free_blocks.load(Ordering::Relaxed).max(self.min_free_blocks());
+        while let Some(free_block) = new_free.pop() {
+            if free.len() < max_free || !free_block.is_owned() {
+                freed.push(free_block.id());
+                free_block.record().set_free(true);
+                free.push(free_block);
+            } else {
+                new_free.push(free_block);
+                break;
+            }
+        }
+
+        // released before the free list is unlocked, see scan_conservative
+        self.release_blocks(new_free);
+        drop(free);
+
+        freed
+    }
+
+    // Sweeps up to `block_budget` blocks, at least one, of the cycle started by
+    // the first call. Starting a cycle takes the rest and recycle blocks off
+    // their lists, so the mutator can only allocate in blocks that were already
+    // swept or that it got hold of otherwise. Those are left for the next cycle
+    // rather than freed while they may hold objects allocated since the marking.
+    // Objects other than small and medium ones are swept when the cycle starts.
+    pub fn sweep_incremental(&self, mark: NonZero<u8>, block_budget: usize) -> SweepProgress {
+        let mut incremental = self.incremental.lock().unwrap();
+
+        // a cycle marked with another mark can't be finished by this one
+        if incremental.as_ref().is_some_and(|cycle| cycle.mark != mark) {
+            self.return_pending(incremental.take().unwrap());
+        }
+
+        let cycle = incremental.get_or_insert_with(|| self.begin_cycle(mark));
+
+        self.sweep_pending(cycle, block_budget.max(1));
+
+        if !cycle.pending.is_empty() {
+            return SweepProgress::InProgress;
+        }
+
+        self.end_cycle(incremental.take().unwrap());
+
+        SweepProgress::Done
+    }
+
+    // Gives up on the incremental cycle in progress, handing the blocks it had
+    // yet to sweep back as they were. Returns whether there was a cycle.
+    pub fn cancel_incremental(&self) -> bool {
+        match self.incremental.lock().unwrap().take() {
+            Some(cycle) => {
+                self.return_pending(cycle);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Takes the blocks to sweep off their lists, giving up on any unfinished
+    // incremental cycle, so a later `finish_concurrent_sweep` can sweep them
+    // while other handles keep allocating. Only the lists are locked while
+    // doing so, and again while the survivors are handed back.
+    pub fn begin_concurrent_sweep(&self, mark: NonZero<u8>) {
+        let mut incremental = self.incremental.lock().unwrap();
+
+        if let Some(cycle) = incremental.take() {
+            self.return_pending(cycle);
+        }
+
+        *incremental = Some(self.begin_cycle(mark));
+    }
+
+    // Sweeps every block taken by `begin_concurrent_sweep`. Reclaims nothing if
+    // another sweep took over the blocks in the meantime.
+    pub fn finish_concurrent_sweep(&self, mark: NonZero<u8>) -> SweepStats {
+        let mut incremental = self.incremental.lock().unwrap();
+
+        match incremental.take() {
+            Some(mut cycle) if cycle.mark == mark => {
+                self.sweep_pending(&mut cycle, usize::MAX);
+                self.end_cycle(cycle)
+            }
+            cycle => {
+                *incremental = cycle;
+
+                SweepStats::default()
+            }
+        }
+    }
+
+    fn begin_cycle(&self, mark: NonZero<u8>) -> IncrementalSweep<BLOCK_SIZE> {
+        let mut rest = self.rest.lock().unwrap();
+        let mut large = self.large.lock().unwrap();
+        let mut recycle = self.recycle.lock().unwrap();
+
+        if let Some(observer) = self.observer() {
+            observer.on_sweep_start(mark);
+        }
+
+        let used_before = self.get_used();
+        let (stats, survived, dead) = self.sweep_objects(mark, &|_| false, &mut large);
+
+        #[cfg(feature = "track-allocations")]
+        self.take_swept_allocations(mark, &|_| false, rest.iter().chain(recycle.iter()));
+        let mut pending: Vec<(BumpBlock<BLOCK_SIZE>, bool)> = recycle.drain(..).map(|block| (block, true)).collect();
+
+        pending.extend(rest.drain(..).map(|block| (block, false)));
+        drop(rest);
+        drop(large);
+        drop(recycle);
+
+        // pending blocks are only handed out once swept, after this
+        self.finalize(dead);
+
+        IncrementalSweep { mark, pending, stats, survived, used_before, holes: 0 }
+    }
+
+    fn sweep_pending(&self, cycle: &mut IncrementalSweep<BLOCK_SIZE>, block_budget: usize) {
+        let blocks = cycle.pending.split_off(cycle.pending.len().saturating_sub(block_budget));
+        let swept = self.sweep_blocks(blocks, cycle.mark);
+
+        cycle.stats.blocks_freed += swept.free.len();
+        cycle.stats.blocks_recycled += swept.recycle.len();
+        cycle.survived += swept.used;
+        cycle.holes += swept.holes;
+
+        {
+            let mut rest = self.rest.lock().unwrap();
+            let mut recycle = self.recycle.lock().unwrap();
+
+            // blocks handed back in the meantime stay where they were put
+            rest.extend(swept.rest);
+            recycle.extend(swept.recycle);
+        }
+
+        self.free_swept(swept.free);
+    }
+
+    fn end_cycle(&self, cycle: IncrementalSweep<BLOCK_SIZE>) -> SweepStats {
+        if self.ordered_sweep.load(Ordering::Relaxed) {
+            self.rest.lock().unwrap().sort_unstable_by_key(|block| block.as_ptr() as usize);
+            self.recycle.lock().unwrap().sort_unstable_by_key(|block| block.as_ptr() as usize);
+        }
+
+        // bytes allocated since the cycle started are still in use
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(cycle.used_before) + cycle.survived)
+            })
+            .unwrap();
+        self.live_bytes.store(cycle.survived, Ordering::Relaxed);
+        self.record_fragmentation(cycle.holes, cycle.stats.blocks_freed);
+        self.current_mark.store(next_mark(cycle.mark).get(), Ordering::Relaxed);
+
+        if let Some(observer) = self.observer() {
+            observer.on_sweep_end(cycle.mark);
+        }
+
+        cycle.stats
+    }
+
+    // Puts the blocks an unfinished incremental sweep had yet to get to back on
+    // their lists, so a sweep of the whole heap sees them.
+    fn abandon_incremental(&self) {
+        if let Some(cycle) = self.incremental.lock().unwrap().take() {
+            self.return_pending(cycle);
+        }
+    }
+
+    fn return_pending(&self, cycle: IncrementalSweep<BLOCK_SIZE>) {
+        let mut rest = self.rest.lock().unwrap();
+        let mut recycle = self.recycle.lock().unwrap();
+
+        for (block, recycled) in cycle.pending {
+            if recycled {
+                recycle.push(block);
+            } else {
+                rest.push(block);
+            }
+        }
+    }
+
+    pub fn recycle_hole_min(&self) -> usize {
+        self.recycle_hole_min.load(Ordering::Relaxed)
+    }
+
+    pub fn set_config(&self, config: HeapConfig) {
+        self.max_free_blocks.store(config.max_free_blocks, Ordering::Relaxed);
+        self.recycle_hole_min.store(config.recycle_hole_min, Ordering::Relaxed);
+        self.max_pooled_large.store(config.max_pooled_large, Ordering::Relaxed);
+        self.upward_allocation.store(config.upward_allocation, Ordering::Relaxed);
+        self.small_burst_blocks.store(config.small_burst_blocks, Ordering::Relaxed);
+    }
+
+    pub fn set_parallel_sweep(&self, min_blocks: usize, workers: usize) {
+        self.parallel_sweep_min_blocks.store(min_blocks, Ordering::Relaxed);
+        self.sweep_workers.store(workers, Ordering::Relaxed);
+    }
+
+    // Sorts the blocks into the lists they belong on, spreading the work across
+    // the configured workers once the heap is large enough to be worth it.
+    fn sweep_blocks(
+        &self,
+        mut blocks: Vec<(BumpBlock<BLOCK_SIZE>, bool)>,
+        mark: NonZero<u8>,
+    ) -> SweptBlocks<BLOCK_SIZE> {
+        let workers = self.sweep_workers.load(Ordering::Relaxed);
+        let min_blocks = self.parallel_sweep_min_blocks.load(Ordering::Relaxed);
+
+        let hole_min = self.recycle_hole_min();
+
+        if workers <= 1 || self.block_count() < min_blocks {
+            return SweptBlocks::sweep(blocks, mark, hole_min);
+        }
+
+        let chunk_size = blocks.len().div_ceil(workers).max(1);
+        let mut swept = SweptBlocks::default();
+
+        std::thread::scope(|scope| {
+            let mut handles = vec![];
+
+            while !blocks.is_empty() {
+                let chunk = blocks.split_off(blocks.len().saturating_sub(chunk_size));
+
+                self.sweep_threads_spawned.fetch_add(1, Ordering::Relaxed);
+                handles.push(scope.spawn(move || SweptBlocks::sweep(chunk, mark, hole_min)));
+            }
+
+            for handle in handles {
+                swept.merge(handle.join().unwrap());
+            }
+        });
+
+        swept
+    }
+
+    // Drops every free block, along with any rest or recycle block that nothing
+    // has been allocated in, returning the number of bytes released. Nothing is
+    // allocated while doing so, and the blocks go straight back to the global
+    // allocator rather than to the block pool.
+    pub fn on_memory_pressure(&self) -> usize {
+        let mut rest = self.rest.lock().unwrap();
+        let mut recycle = self.recycle.lock().unwrap();
+        let mut free = self.free.lock().unwrap();
+        let mut released = 0;
+        let mut keep = |block: &BumpBlock<BLOCK_SIZE>, dead: bool| {
+            // blocks borrowed from a region can't be handed back
+            if dead && block.is_owned() {
+                released += 1;
+                false
+            } else {
+                true
+            }
+        };
+
+        // the reserve of free blocks is kept even under pressure
+        let mut reserve = self.min_free_blocks();
+
+        free.retain(|block| {
+            if reserve > 0 {
+                reserve -= 1;
+                true
+            } else {
+                keep(block, true)
+            }
+        });
+        rest.retain(|block| keep(block, block.is_empty()));
+        recycle.retain(|block| keep(block, block.is_empty()));
+
+        self.block_count.fetch_sub(released, Ordering::Relaxed);
+
+        let pooled_large: usize = self
+            .large_pool
+            .lock()
+            .unwrap()
+            .drain()
+            .flat_map(|(_, blocks)| blocks)
+            .map(|large_block| large_block.get_size())
+            .sum();
+
+        self.pooled_large_bytes.fetch_sub(pooled_large, Ordering::Relaxed);
+
+        released * BLOCK_SIZE + pooled_large
+    }
+
+    // Reclaims everything, as a sweep in which nothing was marked would, but
+    // without looking at any marks. Finalizers run and weak handles are
+    // cleared, large objects are released, and the blocks are cleared and
+    // moved to the free list, up to the number of free blocks kept by a sweep.
+    pub fn reset(&self) {
+        self.abandon_incremental();
+
+        let mut rest = self.rest.lock().unwrap();
+        let mut large = self.large.lock().unwrap();
+        let mut recycle = self.recycle.lock().unwrap();
+
+        self.immortal.lock().unwrap().clear();
+        self.clear_traced();
+        self.ids.lock().unwrap().clear();
+        self.pins.lock().unwrap().clear();
+
+        let finalizers: Vec<(usize, Finalizer)> = self.finalizers.lock().unwrap().drain().collect();
+
+        for slot in self.weak.lock().unwrap().drain(..) {
+            WeakHandle::clear(&slot);
+        }
+
+        #[cfg(feature = "track-allocations")]
+        {
+            self.allocations.lock().unwrap().clear();
+            self.unmarked.lock().unwrap().clear();
+        }
+
+        self.large_index.lock().unwrap().clear();
+        self.large_bytes.store(0, Ordering::Relaxed);
+
+        let large_blocks: Vec<LargeBlock> = large.drain(..).collect();
+        let mut blocks: Vec<BumpBlock<BLOCK_SIZE>> = rest.drain(..).chain(recycle.drain(..)).collect();
+
+        drop(rest);
+        drop(large);
+        drop(recycle);
+
+        // the finalizers run with the lists unlocked, before any object's
+        // memory is released or its block reset
+        for (addr, finalizer) in finalizers {
+            finalizer(addr as *const u8);
+        }
+
+        drop(large_blocks);
+
+        for block in blocks.iter_mut() {
+            block.reset();
+        }
+
+        // allocations made into blocks still held by an allocation head are
+        // reported once the block is handed back
+        self.used.store(0, Ordering::Relaxed);
+        self.live_bytes.store(0, Ordering::Relaxed);
+        self.record_fragmentation(0, 0);
+        self.free_swept(blocks);
+    }
+
+    // Drops the free blocks beyond the reserve, returning the number of bytes
+    // released. Unlike on_memory_pressure only the free list is touched, and
+    // the blocks go straight back to the system allocator here as well.
+    pub fn trim(&self) -> usize {
+        let mut free = self.free.lock().unwrap();
+        let reserve = self.min_free_blocks();
+        // blocks borrowed from a region can't be handed back
+        let (kept, trimmed): (Vec<_>, Vec<_>) = free
+            .drain(..)
+            .enumerate()
+            .partition(|(i, block)| *i < reserve || !block.is_owned());
+
+        *free = kept.into_iter().map(|(_, block)| block).collect();
+
+        let released = trimmed.len();
+
+        // released before the free list is unlocked, see scan_conservative
+        drop(trimmed);
+        drop(free);
+
+        self.block_count.fetch_sub(released, Ordering::Relaxed);
+
+        released * BLOCK_SIZE
+    }
+
+    // Reclaims everything as reset does, then releases every block and large
+    // object rather than keeping any for reuse, once nothing allocates from the
+    // store anymore. A concurrent sweep is given up on, waiting for it when its
+    // thread is already sweeping, so it can't hold on to blocks being released.
+    pub fn shutdown(&self) {
+        self.reset();
+
+        let mut free = self.free.lock().unwrap();
+        // blocks borrowed from a region can't be handed back
+        let (kept, released): (Vec<_>, Vec<_>) = free.drain(..).partition(|block| !block.is_owned());
+
+        *free = kept;
+        self.release_blocks(released);
+        drop(free);
+
+        self.large_pool.lock().unwrap().clear();
+        self.pooled_large_bytes.store(0, Ordering::Relaxed);
+    }
+
+    // registers a large object without allocating it, to fake a placement bug
+    #[cfg(test)]
+    pub fn insert_large_index(&self, addr: usize, layout: Layout) {
+        self.large_index.lock().unwrap().insert(addr, layout);
+    }
+
+    #[cfg(test)]
+    pub fn sweep_threads_spawned(&self) -> usize {
+        self.sweep_threads_spawned.load(Ordering::Relaxed)
+    }
+
+    #[cfg(all(test, feature = "block-pool"))]
+    pub fn pooled_block_count(&self) -> usize {
+        self.pooled.load(Ordering::Relaxed)
+    }
+
+    fn new_block(&self) -> Result<BumpBlock<BLOCK_SIZE>, AllocError> {
+        // homogeneous heaps may be walked slot by slot, so a slot that was never
+        // handed out must still hold a valid (zeroed) value
+        let geometry = self.geometry();
+        let mut block = if self.homogeneous.is_some() {
+            BumpBlock::new_zeroed(self.block_alignment(), geometry, &self.backing)?
+        } else {
+            self.alloc_block(geometry)?
+        };
+
+        block.set_conservative_lines(self.conservative_lines.load(Ordering::Relaxed));
+        block.set_upward(self.upward_allocation.load(Ordering::Relaxed));
+
+        self.block_count.fetch_add(1, Ordering::Relaxed);
+        block.record().set_owner(Some(self.id));
+
+        if let Some(observer) = self.observer() {
+            observer.on_new_block(block.id());
+        }
+
+        Ok(block)
+    }
+
+    #[cfg(feature = "block-pool")]
+    fn alloc_block(&self, geometry: Geometry) -> Result<BumpBlock<BLOCK_SIZE>, AllocError> {
+        if let Some(region) = self.region.as_ref() {
+            return BumpBlock::from_block(region.take_block()?, geometry);
+        }
+
+        // pooled blocks are only known to be aligned to their size
+        if self.block_alignment() != geometry.block_size() || !self.uses_block_pool() {
+            return BumpBlock::new_aligned(self.block_alignment(), geometry, &self.backing);
+        }
+
+        match super::block_pool::take() {
+            Some(block) => {
+                self.pooled.fetch_add(1, Ordering::Relaxed);
+                BumpBlock::from_block(block, geometry)
+            }
+            None => BumpBlock::new_aligned(geometry.block_size(), geometry, &self.backing),
+        }
+    }
+
+    // Pooled blocks come from the system allocator, so a heap with a backing
+    // of its own neither takes them nor hands its blocks to other heaps.
+    #[cfg(feature = "block-pool")]
+    fn uses_block_pool(&self) -> bool {
+        backing::is_system(&self.backing)
+    }
+
+    #[cfg(not(feature = "block-pool"))]
+    fn alloc_block(&self, geometry: Geometry) -> Result<BumpBlock<BLOCK_SIZE>, AllocError> {
+        if let Some(region) = self.region.as_ref() {
+            return BumpBlock::from_block(region.take_block()?, geometry);
+        }
+
+        BumpBlock::new_aligned(self.block_alignment(), geometry, &self.backing)
+    }
+
+    // drops blocks, returning their memory, which must be done with the free
+    // list locked
+    fn release_blocks(&self, blocks: Vec<BumpBlock<BLOCK_SIZE>>) {
+        self.block_count.fetch_sub(blocks.len(), Ordering::Relaxed);
+
+        for block in blocks {
+            block.record().set_owner(None);
+
+            #[cfg(feature = "block-pool")]
+            if self.uses_block_pool() {
+                super::block_pool::give(block);
+            }
+        }
+    }
+}
+
+// What a sweep reclaimed that is only let go of once the block lists are
+// unlocked: the finalizers of dead objects, and the dead large objects those
+// may still read.
+struct DeadObjects {
+    finalizers: Vec<(usize, Finalizer)>,
+    large: Vec<LargeBlock>,
+}
+
+// The outcome of sweeping a set of bump blocks.
+#[derive(Default)]
+struct SweptBlocks<const BLOCK_SIZE: usize> {
+    rest: Vec<BumpBlock<BLOCK_SIZE>>,
+    recycle: Vec<BumpBlock<BLOCK_SIZE>>,
+    free: Vec<BumpBlock<BLOCK_SIZE>>,
+    used: usize,
+    // free lines left in the marked blocks
+    holes: usize,
+}
+
+impl<const BLOCK_SIZE: usize> SweptBlocks<BLOCK_SIZE> {
+    // Each block is paired with whether it came from the recycle list. Marked
+    // blocks coming from the recycle list stay there, marked blocks from the
+    // rest list are promoted if sweeping opened up a large enough hole.
+    fn sweep(blocks: Vec<(BumpBlock<BLOCK_SIZE>, bool)>, mark: NonZero<u8>, hole_min: usize) -> Self {
+        let mut swept = Self::default();
+
+        for (mut block, recycled) in blocks {
+            block.reset_hole(mark);
+
+            if block.is_marked(mark) {
+                let marked_lines = block.marked_line_count(mark);
+                let geometry = block.geometry();
+
+                swept.used += marked_lines * geometry.line_size();
+                swept.holes += geometry.line_count() - marked_lines;
+                block.increment_age();
+
+                if recycled || is_recyclable(&block, hole_min) {
+                    swept.recycle.push(block);
+                } else {
+                    swept.rest.push(block);
+                }
+            } else {
+                swept.free.push(block);
+            }
+        }
+
+        swept
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.rest.extend(other.rest);
+        self.recycle.extend(other.recycle);
+        self.free.extend(other.free);
+        self.used += other.used;
+        self.holes += other.holes;
+    }
+}
+
+// Marks an object nothing records the extent of, which keeps evacuating
+// sweeps from moving anything out of its block.
+// SAFETY: ptr must point to an object allocated by a heap with the given layout
+pub unsafe fn mark_object(ptr: *mut u8, layout: Layout, mark: NonZero<u8>) -> Result<(), AllocError> {
+    if let Some((meta, size_class)) = block_holding(ptr, layout)? {
+        meta.mark(ptr, layout.size() as u32, size_class, mark)?;
+        meta.mark_untraced(mark);
+
+        Ok(())
+    } else {
+        LargeBlock::mark(ptr, mark);
+
+        Ok(())
+    }
+}
+
+// Marks an object the store records the extent of, traced by mark_if_unmarked
+// or immortal, which an evacuating sweep may move.
+// SAFETY: ptr must point to an object allocated by a heap with the given layout
+unsafe fn mark_traced(ptr: *mut u8, layout: Layout, mark: NonZero<u8>) -> Result<(), AllocError> {
+    if let Some((meta, size_class)) = block_holding(ptr, layout)? {
+        meta.mark(ptr, layout.size() as u32, size_class, mark)
+    } else {
+        LargeBlock::mark(ptr, mark);
+
+        Ok(())
+    }
+}
+
+// Marks the object with the given color.
+// SAFETY: ptr must point to an object allocated by a heap with the given layout
+#[cfg(feature = "dual-mark")]
+pub unsafe fn mark_object_color(ptr: *mut u8, layout: Layout, color: Color, mark: NonZero<u8>) -> Result<(), AllocError> {
+    if color == Color::Primary {
+        return mark_object(ptr, layout, mark);
+    }
+
+    if let Some((meta, size_class)) = block_holding(ptr, layout)? {
+        meta.mark_secondary(ptr, layout.size() as u32, size_class, mark)
+    } else {
+        LargeBlock::mark_secondary(ptr, mark);
+
+        Ok(())
+    }
+}
+
+// The metadata of the block holding the object, and how blocks of its geometry
+// hold an object of its layout, or None for an object with a large block of
+// its own. Blocks of any geometry are found through the block directory, so
+// this works for objects of every heap.
+unsafe fn block_holding(ptr: *mut u8, layout: Layout) -> Result<Option<(BlockMeta, SizeClass)>, AllocError> {
+    SizeClass::get_for_size(layout.size())?;
+
+    let Some(record) = block_directory::get(ptr) else {
+        return Ok(None);
+    };
+
+    match record.geometry().size_class(layout)? {
+        SizeClass::Large => Err(AllocError::AllocOverflow),
+        size_class => Ok(Some((
+            BlockMeta::from_block_ptr(record.base(), record.geometry())?,
+            size_class,
+        ))),
+    }
+}
+
+// Fails with the first two of the sorted `ranges` that overlap.
+fn check_disjoint(ranges: &[(*const u8, usize)]) -> Result<(), OverlapError> {
+    for pair in ranges.windows(2) {
+        let (first, second) = (pair[0], pair[1]);
+
+        if first.0 as usize + first.1 > second.0 as usize {
+            return Err(OverlapError { first, second });
+        }
+    }
+
+    Ok(())
+}
+
+// Whether the block's current hole is worth allocating into, a full block
+// never is.
+fn is_recyclable<const BLOCK_SIZE: usize>(block: &BumpBlock<BLOCK_SIZE>, hole_min: usize) -> bool {
+    let hole = block.current_hole_size();
+
+    hole > 0 && hole >= hole_min
+}
+
+// the mark following `mark`, wrapping around past FREE_MARK
+pub fn next_mark(mark: NonZero<u8>) -> NonZero<u8> {
+    match mark.get().checked_add(1) {
+        Some(next) => NonZero::new(next).unwrap(),
+        None => NonZero::new(FREE_MARK + 1).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_meta::BlockMeta;
+    use crate::constants::{BLOCK_CAPACITY, BLOCK_SIZE, LARGE_OBJECT_MIN, LINE_COUNT, LINE_SIZE};
+
+    #[cfg(feature = "card-marks")]
+    #[test]
+    fn dirty_blocks_are_cleaned() {
+        let store = BlockStore::new();
+        let layout = Layout::new::<u64>();
+        let mut dirty = store.get_head().unwrap();
+        let mut clean = store.get_head().unwrap();
+        let dirty_ptr = dirty.inner_alloc(layout).unwrap();
+        let dirty_base = dirty.as_ptr();
+
+        clean.inner_alloc(layout).unwrap();
+        store.rest(dirty);
+        store.rest(clean);
+
+        unsafe { BlockMeta::from_ptr(dirty_ptr).unwrap().mark_card() };
+
+        assert_eq!(store.dirty_blocks(), vec![dirty_base]);
+        assert!(store.dirty_blocks().is_empty());
+    }
+
+    #[test]
+    fn deterministic_store_never_reuses_blocks() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+
+        store.set_deterministic(true);
+        store.recycle(store.get_head().unwrap());
+        store.recycle(store.get_head().unwrap());
+        store.sweep(mark, || {});
+
+        let block = store.get_head().unwrap();
+
+        assert_eq!(store.block_count(), 3);
+        assert_eq!(block.current_hole_size(), BLOCK_CAPACITY);
+    }
+
+    #[test]
+    fn next_mark_skips_free_mark() {
+        assert_eq!(next_mark(NonZero::new(1).unwrap()).get(), 2);
+        assert_eq!(next_mark(NonZero::new(u8::MAX).unwrap()).get(), 1);
+    }
+
+    #[test]
+    fn stale_marks_do_not_survive_wraparound() {
+        let store = BlockStore::new();
+        let layout = Layout::from_size_align(LINE_SIZE, LINE_SIZE).unwrap();
+        let mut block = store.get_overflow().unwrap();
+        let stale = block.inner_alloc(layout).unwrap() as *mut u8;
+        let live = block.inner_alloc(layout).unwrap() as *mut u8;
+        let stale_line = (stale as usize - block.as_ptr() as usize) / LINE_SIZE;
+        let meta = unsafe { BlockMeta::from_ptr(stale).unwrap() };
+
+        store.rest(block);
+
+        unsafe { meta.mark(stale, 1, SizeClass::Small, store.current_mark()).unwrap() };
+
+        for cycle in 0..600 {
+            let mark = store.current_mark();
+
+            unsafe { meta.mark(live, 1, SizeClass::Small, mark).unwrap() };
+            store.sweep(mark, || {});
+
+            assert_ne!(store.current_mark(), mark);
+
+            // the stale object was only marked during the first cycle
+            if cycle > 0 {
+                assert_eq!(meta.get_line(stale_line), FREE_MARK);
+            }
+        }
+    }
+
+    fn fill_store(store: &BlockStore, blocks: usize, mark: NonZero<u8>) {
+        for i in 0..blocks {
+            let mut block = store.get_overflow().unwrap();
+            let ptr = block.inner_alloc(Layout::new::<u64>()).unwrap() as *mut u8;
+
+            if i % 2 == 0 {
+                unsafe { BlockMeta::from_ptr(ptr).unwrap().mark(ptr, 8, SizeClass::Small, mark).unwrap() };
+            }
+
+            store.rest(block);
+        }
+    }
+
+    #[test]
+    fn small_heap_sweeps_serially() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+
+        store.set_parallel_sweep(64, 4);
+        fill_store(&store, 10, mark);
+        store.sweep(mark, || {});
+
+        assert_eq!(store.sweep_threads_spawned(), 0);
+        assert_eq!(store.recycle.lock().unwrap().len(), 5);
+        assert_eq!(store.free.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn large_heap_sweeps_in_parallel() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+
+        store.set_parallel_sweep(64, 4);
+        fill_store(&store, 100, mark);
+        store.sweep(mark, || {});
+
+        assert_eq!(store.sweep_threads_spawned(), 4);
+        assert_eq!(store.recycle.lock().unwrap().len(), 50);
+        assert_eq!(store.free.lock().unwrap().len(), 50);
+        assert_eq!(store.get_used(), 50 * LINE_SIZE);
+    }
+
+    // Fills blocks that end up empty, sparse or full depending on how many of
+    // their lines are marked.
+    fn fill_striped(store: &BlockStore, count: usize, mark: NonZero<u8>) {
+        let layout = Layout::from_size_align(LINE_SIZE, 8).unwrap();
+
+        for i in 0..count {
+            let mut block = store.get_overflow().unwrap();
+
+            for line in 0..LINE_COUNT {
+                let ptr = block.inner_alloc(layout).unwrap() as *mut u8;
+
+                if i % 3 != 0 && line % ((i % 5 + 1) * 4) == 0 {
+                    unsafe { mark_object(ptr, layout, mark).unwrap() };
+                }
+            }
+
+            store.rest(block);
+        }
+    }
+
+    #[test]
+    fn stats_are_read_without_locking_the_lists() {
+        let store = BlockStore::new();
+
+        store.rest(store.get_head().unwrap());
+        store.create_large(Layout::from_size_align(LARGE_OBJECT_MIN, 8).unwrap()).unwrap();
+
+        // as a sweep on another thread would
+        let _rest = store.rest.lock().unwrap();
+        let _large = store.large.lock().unwrap();
+        let stats = store.stats();
+
+        assert_eq!(stats.rest_block_count, 1);
+        assert_eq!(stats.large_object_count, 1);
+    }
+
+    // The lists a sweep left the store's blocks in, with the marked lines of
+    // each surviving block, in an order that doesn't depend on the sweep.
+    fn swept_state(store: &BlockStore, mark: NonZero<u8>) -> (Vec<usize>, Vec<usize>, usize, usize) {
+        let lines = |list: &BlockList<BumpBlock>| {
+            let mut lines: Vec<usize> = list
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|block| block.marked_line_count(mark))
+                .collect();
+
+            lines.sort();
+            lines
+        };
+
+        (lines(&store.rest), lines(&store.recycle), store.free.lock().unwrap().len(), store.get_used())
+    }
+
+    #[test]
+    fn parallel_sweep_matches_serial_sweep() {
+        let serial = BlockStore::new();
+        let parallel = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+
+        parallel.set_parallel_sweep(0, 4);
+
+        for store in [&serial, &parallel] {
+            fill_striped(store, 100, mark);
+            store.sweep(mark, || {});
+        }
+
+        assert_eq!(serial.sweep_threads_spawned(), 0);
+        assert_eq!(parallel.sweep_threads_spawned(), 4);
+        let (rest, recycle, free, _) = swept_state(&serial, mark);
+
+        assert!(!rest.is_empty() && !recycle.is_empty() && free > 0);
+        assert_eq!(swept_state(&serial, mark), swept_state(&parallel, mark));
+    }
+
+    #[test]
+    fn incremental_sweep_matches_full_sweep() {
+        let full = BlockStore::new();
+        let incremental = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+
+        fill_striped(&full, 100, mark);
+        fill_striped(&incremental, 100, mark);
+        full.sweep(mark, || {});
+
+        let mut calls = 1;
+
+        while incremental.sweep_incremental(mark, 3) == SweepProgress::InProgress {
+            calls += 1;
+        }
+
+        assert_eq!(calls, 34);
+        assert_eq!(incremental.current_mark(), next_mark(mark));
+        assert_eq!(swept_state(&full, mark), swept_state(&incremental, mark));
+    }
+
+    #[test]
+    fn incremental_sweep_leaves_blocks_allocated_in_alone() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+
+        fill_striped(&store, 10, mark);
+        assert_eq!(store.sweep_incremental(mark, 4), SweepProgress::InProgress);
+
+        // allocated in while the cycle is in progress, without being marked
+        let mut block = store.get_overflow().unwrap();
+        let base = block.as_ptr();
+
+        block.inner_alloc(Layout::new::<u64>()).unwrap();
+        store.rest(block);
+
+        while store.sweep_incremental(mark, 4) == SweepProgress::InProgress {}
+
+        assert!(store.rest.lock().unwrap().iter().any(|block| block.as_ptr() == base));
+    }
+
+    #[test]
+    fn memory_pressure_releases_dead_blocks() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+        let mut live = store.get_overflow().unwrap();
+        let ptr = live.inner_alloc(Layout::new::<u64>()).unwrap() as *mut u8;
+        let live_base = live.as_ptr();
+
+        fill_store(&store, 6, mark);
+        store.sweep(mark, || {});
+
+        unsafe { BlockMeta::from_ptr(ptr).unwrap().mark(ptr, 8, SizeClass::Small, mark).unwrap() };
+
+        store.rest(live);
+        store.recycle(store.get_overflow().unwrap());
+        store.recycle(store.get_overflow().unwrap());
+
+        assert_eq!(store.free.lock().unwrap().len(), 1);
+        assert_eq!(store.block_count(), 7);
+
+        // the free block and the two untouched blocks are released, while the
+        // blocks that survived the sweep are kept
+        let released = store.on_memory_pressure();
+
+        assert_eq!(released, 3 * BLOCK_SIZE);
+        assert_eq!(store.block_count(), 4);
+        assert!(store.free.lock().unwrap().is_empty());
+        assert!(store.find_block(ptr as usize).is_some());
+        assert_eq!(store.rest.lock().unwrap()[0].as_ptr(), live_base);
+        assert!(store.rest.lock().unwrap()[0].is_marked(mark));
+    }
+
+    #[test]
+    fn utilization_is_sorted_ascending() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+        let mut expect = vec![];
+
+        for percent in [50, 90, 10] {
+            let block = store.get_overflow().unwrap();
+            let lines = LINE_COUNT * percent / 100;
+            let ptr = block.as_ptr() as *mut u8;
+
+            unsafe {
+                BlockMeta::from_ptr(ptr).unwrap()
+                    .mark(ptr, (lines * LINE_SIZE) as u32, SizeClass::Medium, mark)
+                    .unwrap();
+            }
+
+            expect.push((block.id(), lines as f32 / LINE_COUNT as f32));
+            store.rest(block);
+        }
+
+        expect.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let report = store.utilization(mark);
+
+        assert_eq!(report.len(), 3);
+
+        for ((id, fraction), (expect_id, expect_fraction)) in report.into_iter().zip(expect) {
+            assert_eq!(id, expect_id);
+            assert!((fraction - expect_fraction).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn scan_conservative_marks_only_heap_pointers() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+        let small = Layout::new::<u64>();
+        let large = Layout::from_size_align(LARGE_OBJECT_MIN, 8).unwrap();
+        let mut block = store.get_head().unwrap();
+        let live = block.inner_alloc(small).unwrap();
+        let dead = unsafe { block.as_ptr().add(BLOCK_CAPACITY / 2) };
+        let live_large = store.create_large(large).unwrap();
+        let dead_large = store.create_large(large).unwrap();
+        let stack_value = 0usize;
+        let words = [
+            live as usize + 3,
+            live_large as usize + LARGE_OBJECT_MIN - 1,
+            block.as_ptr() as usize + BLOCK_CAPACITY,
+            dead_large as usize + LARGE_OBJECT_MIN,
+            &stack_value as *const usize as usize,
+            0xdead_beef,
+            0,
+        ];
+
+        store.scan_conservative(&words, mark);
+
+        let meta = unsafe { BlockMeta::from_ptr(live).unwrap() };
+        let live_line = (live as usize - block.as_ptr() as usize) / LINE_SIZE;
+        let dead_line = (dead as usize - block.as_ptr() as usize) / LINE_SIZE;
+
+        assert!(block.is_marked(mark));
+        assert_eq!(meta.get_line(live_line), 1);
+        // along with the line before, which a small object may start in
+        assert_eq!(meta.get_line(live_line - 1), 1);
+        assert_eq!(meta.marked_line_count(mark), 2);
+        assert_ne!(meta.get_line(dead_line), 1);
+
+        store.rest(block);
+        store.sweep(mark, || {});
+
+        assert!(store.find_large(live_large as usize).is_some());
+        assert!(store.find_large(dead_large as usize).is_none());
+    }
+
+    #[test]
+    fn scan_conservative_skips_free_blocks() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+
+        assert_eq!(store.prealloc_best_effort(1), 1);
+
+        let free = store.free.lock().unwrap()[0].as_ptr();
+
+        store.scan_conservative(&[free as usize + LINE_SIZE], mark);
+
+        let meta = unsafe { BlockMeta::from_ptr(free).unwrap() };
+
+        assert_eq!(meta.marked_line_count(mark), 0);
+        assert_ne!(meta.get_block_mark(), mark.get());
+        assert!(!meta.has_untraced(mark));
+    }
+
+    #[test]
+    fn scan_conservative_races_memory_pressure() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+        let words = Mutex::new(vec![]);
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..200 {
+                    // untouched blocks are released by on_memory_pressure
+                    // without ever being on the free list
+                    for _ in 0..4 {
+                        let block = store.get_overflow().unwrap();
+
+                        words.lock().unwrap().push(block.as_ptr() as usize + LINE_SIZE);
+                        store.recycle(block);
+                    }
+
+                    store.on_memory_pressure();
+                }
+
+                done.store(true, Ordering::Relaxed);
+            });
+
+            while !done.load(Ordering::Relaxed) {
+                let words = words.lock().unwrap().clone();
+
+                store.scan_conservative(&words, mark);
+            }
+        });
+
+        assert_eq!(store.block_count(), 0);
+    }
+
+    #[test]
+    fn scan_conservative_marks_every_line_of_a_medium_object() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+        let medium = Layout::from_size_align(LINE_SIZE * 4, 8).unwrap();
+        let mut block = store.get_head().unwrap();
+        let above = block.inner_alloc(medium).unwrap();
+        let object = block.inner_alloc(medium).unwrap();
+        let first = block.line_of(object).unwrap();
+
+        store.scan_conservative(&[object as usize + LINE_SIZE * 3 + 8], mark);
+
+        let meta = unsafe { BlockMeta::from_ptr(object).unwrap() };
+
+        for line in first..first + 4 {
+            assert_eq!(meta.get_line(line), 1);
+        }
+
+        assert_eq!(meta.marked_line_count(mark), 4);
+        assert_ne!(meta.get_line(block.line_of(above).unwrap()), 1);
+    }
+
+    #[test]
+    fn verify_no_overlap_checks_untraced_marks() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+        let small = Layout::new::<[u64; 4]>();
+        let large = Layout::from_size_align(LARGE_OBJECT_MIN, 8).unwrap();
+        let mut block = store.get_head().unwrap();
+        let first = block.inner_alloc(small).unwrap() as *mut u8;
+        let second = block.inner_alloc(small).unwrap() as *mut u8;
+        let live_large = store.create_large(large).unwrap() as *mut u8;
+
+        // as Heap::mark does, without recording either object
+        unsafe {
+            mark_object(first, small, mark).unwrap();
+            mark_object(live_large, large, mark).unwrap();
+        }
+
+        store.rest(block);
+
+        assert!(store.verify_no_overlap(mark).is_ok());
+
+        // pretend a large object was handed out on top of a live line, with
+        // its mark byte landing in the object below it
+        unsafe { second.add(small.size() - 1).write(mark.get()) };
+        store.insert_large_index(first as usize, large);
+
+        let err = store.verify_no_overlap(mark).unwrap_err();
+        let line_start = first as usize - first as usize % LINE_SIZE;
+
+        assert_eq!(err.first.0 as usize, line_start);
+        assert_eq!(err.second, (first as *const u8, LARGE_OBJECT_MIN));
+    }
+
+    #[test]
+    fn ordered_sweep_sorts_surviving_blocks() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+
+        store.set_ordered_sweep(true);
+        fill_store(&store, 40, mark);
+        store.recycle.lock().unwrap().reverse();
+        store.sweep(mark, || {});
+
+        let recycle = store.recycle.lock().unwrap();
+        let addrs: Vec<usize> = recycle.iter().map(|block| block.as_ptr() as usize).collect();
+
+        assert_eq!(addrs.len(), 20);
+        assert!(addrs.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn dead_large_blocks_are_reused() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+        let layout = Layout::from_size_align(BLOCK_SIZE * 2, 8).unwrap();
+        let ptr = store.create_large(layout).unwrap();
+
+        unsafe { LargeBlock::mark(ptr, mark) };
+        store.sweep(NonZero::new(2).unwrap(), || {});
+
+        assert_eq!(store.large_pool.lock().unwrap()[&layout].len(), 1);
+
+        let reused = store.create_large(layout).unwrap();
+
+        // the block comes back unmarked, with no new allocation made
+        assert_eq!(reused, ptr);
+        assert!(!unsafe { LargeBlock::is_marked_at(reused, mark) });
+        assert!(store.large_pool.lock().unwrap()[&layout].is_empty());
+
+        let other = store.create_large(Layout::from_size_align(BLOCK_SIZE * 3, 8).unwrap()).unwrap();
+
+        assert_ne!(other, ptr);
+    }
+
+    #[test]
+    fn pooled_large_blocks_serve_objects_that_fit() {
+        let store = BlockStore::new();
+        let layout = Layout::from_size_align(BLOCK_SIZE * 3, 64).unwrap();
+        let ptr = store.create_large(layout).unwrap();
+
+        store.sweep(NonZero::new(1).unwrap(), || {});
+
+        // more aligned than the pooled block, or far smaller, doesn't fit
+        let over_aligned = Layout::from_size_align(BLOCK_SIZE * 2, 128).unwrap();
+        let much_smaller = Layout::from_size_align(BLOCK_SIZE + 8, 8).unwrap();
+
+        assert_ne!(store.create_large(over_aligned).unwrap(), ptr);
+        assert_ne!(store.create_large(much_smaller).unwrap(), ptr);
+
+        let smaller = Layout::from_size_align(BLOCK_SIZE * 2, 8).unwrap();
+        let reused = store.create_large(smaller).unwrap();
+
+        assert_eq!(reused, ptr);
+        assert_eq!(reused as usize % smaller.align(), 0);
+
+        // the block goes back to the pool under the layout it was made for
+        store.sweep(NonZero::new(2).unwrap(), || {});
+
+        assert_eq!(store.large_pool.lock().unwrap()[&layout].len(), 1);
+    }
+
+    #[test]
+    fn large_bytes_follow_surviving_objects() {
+        let store = BlockStore::new();
+        let mark = NonZero::new(1).unwrap();
+        let sizes = [BLOCK_SIZE * 2, BLOCK_SIZE * 3, BLOCK_SIZE * 5];
+        let objects: Vec<*const u8> = sizes
+            .iter()
+            .map(|size| store.create_large(Layout::from_size_align(*size, 8).unwrap()).unwrap())
+            .collect();
+        let total: usize = store.large.lock().unwrap().iter().map(|block| block.get_size()).sum();
+
+        assert_eq!(store.count_large_space(), total);
+
+        unsafe {
+            LargeBlock::mark(objects[0], mark);
+            LargeBlock::mark(objects[2], mark);
+        }
+
+        store.sweep(mark, || {});
+
+        let surviving: usize = store.large.lock().unwrap().iter().map(|block| block.get_size()).sum();
+
+        assert_eq!(store.large.lock().unwrap().len(), 2);
+        assert_eq!(store.count_large_space(), surviving);
+
+        store.free_large(objects[0]).unwrap();
+
+        assert!(!store.large_index.lock().unwrap().contains_key(&(objects[0] as usize)));
+        assert!(matches!(store.free_large(objects[0]), Err(AllocError::UnknownObject)));
+
+        let remaining: usize = store.large.lock().unwrap().iter().map(|block| block.get_size()).sum();
+
+        assert!(remaining < surviving);
+        assert_eq!(store.count_large_space(), remaining);
+    }
+
+    #[test]
+    fn large_pool_is_bounded_and_released_under_pressure() {
+        let store = BlockStore::new();
+        let layout = Layout::from_size_align(BLOCK_SIZE * 2, 8).unwrap();
+
+        for _ in 0..(MAX_POOLED_LARGE_PER_SIZE + 2) {
+            store.create_large(layout).unwrap();
+        }
+
+        store.sweep(NonZero::new(1).unwrap(), || {});
+
+        let pooled = store.large_pool.lock().unwrap()[&layout].len();
+
+        assert_eq!(pooled, MAX_POOLED_LARGE_PER_SIZE);
+        assert!(store.on_memory_pressure() >= pooled * layout.size());
+        assert!(store.large_pool.lock().unwrap().is_empty());
+
+        store.set_config(HeapConfig {
+            max_pooled_large: 1,
+            ..HeapConfig::default()
+        });
+
+        for _ in 0..3 {
+            store.create_large(layout).unwrap();
+        }
+
+        store.sweep(NonZero::new(2).unwrap(), || {});
+
+        assert_eq!(store.large_pool.lock().unwrap()[&layout].len(), 1);
     }
 }
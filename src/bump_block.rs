@@ -1,44 +1,92 @@
-use super::block::Block;
+use super::backing::Backing;
+use super::block::{Block, BlockId};
+use super::block_directory::{self, BlockRecord};
 use super::block_meta::BlockMeta;
-use super::constants::{BLOCK_CAPACITY, SMALL_OBJECT_MIN};
+use super::constants::{self, CONSERVATIVE_LINES, FREE_MARK, SMALL_OBJECT_MIN};
 use super::error::AllocError;
+use super::geometry::Geometry;
 use std::alloc::Layout;
 use std::num::NonZero;
+use std::sync::Arc;
 
-pub struct BumpBlock {
+// The current hole spans from `limit` up to `cursor`. Objects are normally
+// bumped down from `cursor`, and up from `limit` when allocating upward.
+pub struct BumpBlock<const BLOCK_SIZE: usize = { constants::BLOCK_SIZE }> {
     cursor: usize,
     limit: usize,
-    block: Block,
+    block: Block<BLOCK_SIZE>,
     meta: BlockMeta,
+    record: BlockRecord,
+    conservative_lines: usize,
+    upward: bool,
 }
 
+// SAFETY: the block is Send, and the cursor and limit are plain integers only
+// the owner of the bump block moves. The metadata's raw pointers lead to atomic
+// mark bytes inside the block, or beside it with side-meta, which live as long
+// as the block does and which other threads only ever touch atomically, when
+// marking or sweeping.
+unsafe impl<const BLOCK_SIZE: usize> Send for BumpBlock<BLOCK_SIZE> {}
+
 impl BumpBlock {
+    #[cfg(test)]
     pub fn new() -> Result<BumpBlock, AllocError> {
-        let block = Block::default()?;
-        let meta = BlockMeta::new(&block);
+        Self::from_block(Block::default()?, Geometry::DEFAULT)
+    }
+}
+
+impl<const BLOCK_SIZE: usize> BumpBlock<BLOCK_SIZE> {
+    pub fn new_aligned(align: usize, geometry: Geometry, backing: &Arc<dyn Backing>) -> Result<Self, AllocError> {
+        Self::from_block(Block::aligned(align, backing)?, geometry)
+    }
+
+    pub fn new_zeroed(align: usize, geometry: Geometry, backing: &Arc<dyn Backing>) -> Result<Self, AllocError> {
+        Self::from_block(Block::zeroed(align, backing)?, geometry)
+    }
+
+    // Blocks are laid out by the geometry given, which is recorded with them
+    // so their objects can be found from an address alone.
+    pub fn from_block(block: Block<BLOCK_SIZE>, geometry: Geometry) -> Result<Self, AllocError> {
+        debug_assert!(geometry.block_size() == BLOCK_SIZE);
+
+        let record = block_directory::insert(block.as_ptr(), geometry)?;
+        let meta = BlockMeta::new(&block, geometry)?;
         let bump_block = BumpBlock {
-            cursor: BLOCK_CAPACITY,
+            cursor: geometry.capacity(),
             limit: 0,
             block,
-            meta
+            meta,
+            record,
+            conservative_lines: CONSERVATIVE_LINES,
+            upward: false,
         };
 
         Ok(bump_block)
     }
 
+    #[cfg(feature = "block-pool")]
+    pub fn into_block(self) -> Block<BLOCK_SIZE> {
+        self.block
+    }
+
     pub fn reset_hole(&mut self, mark: NonZero<u8>) {
         self.meta.free_unmarked(mark);
+        self.record.forget_medium(|line| self.meta.get_line(line) == FREE_MARK);
 
         if self.meta.get_block_mark() != mark.into() {
-            self.cursor = BLOCK_CAPACITY;
+            self.cursor = self.capacity();
             self.limit = 0;
             return;
         }
 
-        if let Some((cursor, limit)) = self
-            .meta
-            .find_next_available_hole(BLOCK_CAPACITY, SMALL_OBJECT_MIN)
-        {
+        let hole = if self.upward {
+            self.meta.find_next_available_hole_upward(0, SMALL_OBJECT_MIN, self.conservative_lines)
+        } else {
+            self.meta
+                .find_next_available_hole(self.capacity(), SMALL_OBJECT_MIN, self.conservative_lines)
+        };
+
+        if let Some((cursor, limit)) = hole {
             self.cursor = cursor;
             self.limit = limit;
         } else {
@@ -48,44 +96,239 @@ impl BumpBlock {
     }
 
     pub fn inner_alloc(&mut self, layout: Layout) -> Option<*const u8> {
+        let align_mask = !(layout.align() - 1);
+
         loop {
-            let next = self.cursor.checked_sub(layout.size())? & !(layout.align() - 1);
+            let next = if self.upward {
+                self.limit.checked_add(layout.align() - 1)? & align_mask
+            } else {
+                self.cursor.checked_sub(layout.size())? & align_mask
+            };
 
-            if self.limit <= next {
-                self.cursor = next;
+            if self.limit <= next && next.checked_add(layout.size())? <= self.cursor {
+                return Some(self.bump_to(next, layout.size()));
+            }
 
-                let ptr = unsafe { self.block.as_ptr().add(self.cursor) };
+            self.next_hole(layout.size())?;
+        }
+    }
 
-                debug_assert!(self.block.as_ptr() as usize <= ptr as usize);
-                debug_assert!(self.block.as_ptr() as usize + BLOCK_CAPACITY >= ptr as usize + layout.size());
+    // like inner_alloc, but with no alignment to round to
+    pub fn inner_alloc_packed(&mut self, size: usize) -> Option<*const u8> {
+        loop {
+            let next = if self.upward {
+                self.limit
+            } else {
+                self.cursor.checked_sub(size)?
+            };
 
-                return Some(ptr);
+            if self.limit <= next && next.checked_add(size)? <= self.cursor {
+                return Some(self.bump_to(next, size));
             }
 
-            if let Some((cursor, limit)) = self
-                .meta
-                .find_next_available_hole(self.limit, layout.size())
-            {
-                self.cursor = cursor;
-                self.limit = limit;
-            } else {
-                return None;
-            }
+            self.next_hole(size)?;
+        }
+    }
+
+    // hands out the `size` bytes at `offset`, which lie within the current hole
+    fn bump_to(&mut self, offset: usize, size: usize) -> *const u8 {
+        if self.upward {
+            self.limit = offset + size;
+        } else {
+            self.cursor = offset;
+        }
+
+        let ptr = unsafe { self.block.as_ptr().add(offset) };
+
+        // medium objects are recorded so an interior pointer can be traced
+        // back to every line of the object
+        let line_size = self.geometry().line_size();
+
+        if size > line_size {
+            self.record
+                .record_medium(offset / line_size, (offset + size - 1) / line_size);
         }
+
+        debug_assert!(self.owns(ptr));
+        debug_assert!(self.block.as_ptr() as usize + self.capacity() >= ptr as usize + size);
+
+        ptr
+    }
+
+    // moves on to the next hole past the current one that can fit `size`
+    fn next_hole(&mut self, size: usize) -> Option<()> {
+        let (cursor, limit) = if self.upward {
+            self.meta
+                .find_next_available_hole_upward(self.cursor, size, self.conservative_lines)?
+        } else {
+            self.meta
+                .find_next_available_hole(self.limit, size, self.conservative_lines)?
+        };
+
+        self.cursor = cursor;
+        self.limit = limit;
+
+        Some(())
+    }
+
+    pub fn set_conservative_lines(&mut self, lines: usize) {
+        self.conservative_lines = lines;
+    }
+
+    // Allocates from the bottom of each hole up, and moves on to the holes
+    // above it, so successive objects get increasing addresses.
+    pub fn set_upward(&mut self, upward: bool) {
+        self.upward = upward;
     }
 
     pub fn current_hole_size(&self) -> usize {
-        self.cursor - self.limit
+        self.cursor.saturating_sub(self.limit)
+    }
+
+    // whether the whole block is one hole, meaning nothing in it is in use
+    pub fn is_empty(&self) -> bool {
+        self.current_hole_size() == self.capacity()
     }
 
     pub fn is_marked(&self, mark: NonZero<u8>) -> bool {
         self.meta.get_block_mark() == mark.into()
     }
+
+    // whether ptr points into the data region of this block
+    pub fn owns(&self, ptr: *const u8) -> bool {
+        self.line_of(ptr).is_some()
+    }
+
+    pub fn line_of(&self, ptr: *const u8) -> Option<usize> {
+        let offset = (ptr as usize).checked_sub(self.block.as_ptr() as usize)?;
+
+        if offset < self.capacity() {
+            Some(offset / self.geometry().line_size())
+        } else {
+            None
+        }
+    }
+
+    pub fn marked_line_count(&self, mark: NonZero<u8>) -> usize {
+        self.meta.marked_line_count(mark)
+    }
+
+    pub fn id(&self) -> BlockId {
+        BlockId::new(self.block.as_ptr())
+    }
+
+    pub fn is_owned(&self) -> bool {
+        self.block.is_owned()
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.block.as_ptr()
+    }
+
+    pub fn record(&self) -> BlockRecord {
+        self.record
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.meta.geometry()
+    }
+
+    // the bytes of the block that hold objects
+    pub fn capacity(&self) -> usize {
+        self.geometry().capacity()
+    }
+
+    pub fn increment_age(&self) {
+        self.meta.increment_age();
+    }
+
+    #[cfg(feature = "card-marks")]
+    pub fn take_card(&self) -> bool {
+        self.meta.take_card()
+    }
+
+    // Forgets every mark, as if nothing in the block had survived.
+    pub fn clear_marks(&self) {
+        self.meta.reset();
+    }
+
+    // Forgets every mark and makes the whole block one hole again, as a new
+    // block would be.
+    pub fn reset(&mut self) {
+        self.meta.reset();
+        self.record.forget_medium(|_| true);
+        self.cursor = self.capacity();
+        self.limit = 0;
+    }
+
+    #[cfg(feature = "dual-mark")]
+    pub fn fold_secondary(&self, mark: NonZero<u8>) {
+        self.meta.fold_secondary(mark);
+    }
+
+    #[cfg(feature = "dual-mark")]
+    pub fn clear_secondary(&self) {
+        self.meta.clear_secondary();
+    }
+
+    // Visits every run of consecutive lines marked with `mark`, passing the
+    // address the run starts at and its length in bytes.
+    pub fn for_each_marked_run(&self, mark: NonZero<u8>, mut f: impl FnMut(*const u8, usize)) {
+        let (line_size, line_count) = (self.geometry().line_size(), self.geometry().line_count());
+        let mut run_start = None;
+
+        for line in 0..=line_count {
+            let marked = line < line_count && self.meta.get_line(line) == mark.get();
+
+            match (marked, run_start) {
+                (true, None) => run_start = Some(line),
+                (false, Some(start)) => {
+                    let ptr = unsafe { self.block.as_ptr().add(start * line_size) };
+
+                    f(ptr, (line - start) * line_size);
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Visits every stride sized slot within a line marked with `mark`. Holes
+    // start and end on line boundaries, so stride must divide the line size for
+    // the slots to line up with the objects that were actually allocated.
+    pub fn for_each_marked_slot(&self, mark: NonZero<u8>, stride: usize, mut f: impl FnMut(*const u8)) {
+        let line_size = self.geometry().line_size();
+
+        debug_assert!(line_size % stride == 0);
+
+        if !self.is_marked(mark) {
+            return;
+        }
+
+        for line in 0..self.geometry().line_count() {
+            if self.meta.get_line(line) != mark.get() {
+                continue;
+            }
+
+            let line_start = line * line_size;
+
+            for offset in (line_start..line_start + line_size).step_by(stride) {
+                // the rest of the current hole has not been handed out yet
+                if self.limit <= offset && offset < self.cursor {
+                    continue;
+                }
+
+                f(unsafe { self.block.as_ptr().add(offset) });
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::{BLOCK_CAPACITY, LINE_COUNT, LINE_SIZE};
+    use crate::size_class::SizeClass;
 
     #[test]
     fn test_empty_block() {
@@ -101,6 +344,86 @@ mod tests {
         assert!(b.inner_alloc(Layout::new::<u8>()).is_none());
     }
 
+    #[test]
+    fn packed_allocations_have_no_padding() {
+        let mut b = BumpBlock::new().unwrap();
+        let mut prev = b.inner_alloc_packed(3).unwrap();
+
+        for _ in 0..100 {
+            let ptr = b.inner_alloc_packed(3).unwrap();
+
+            assert_eq!(ptr as usize + 3, prev as usize);
+            prev = ptr;
+        }
+    }
+
+    #[test]
+    fn owns_interior_pointers() {
+        let mut b = BumpBlock::new().unwrap();
+        let ptr = b.inner_alloc(Layout::new::<[u64; 4]>()).unwrap();
+        let interior = unsafe { ptr.add(17) };
+        let past_data = unsafe { b.as_ptr().add(BLOCK_CAPACITY) };
+        let before = unsafe { b.as_ptr().sub(1) };
+
+        assert!(b.owns(interior));
+        assert_eq!(b.line_of(interior), Some(LINE_COUNT - 1));
+        assert_eq!(b.line_of(b.as_ptr()), Some(0));
+        assert!(!b.owns(past_data));
+        assert!(!b.owns(before));
+        assert_eq!(b.line_of(past_data), None);
+    }
+
+    #[test]
+    fn upward_allocations_increase() {
+        let mut b = BumpBlock::new().unwrap();
+        let layout = Layout::new::<[u64; 3]>();
+
+        b.set_upward(true);
+
+        let mut prev = b.inner_alloc(layout).unwrap();
+
+        assert_eq!(prev, b.as_ptr());
+
+        while let Some(ptr) = b.inner_alloc(layout) {
+            assert_eq!(ptr as usize, prev as usize + layout.size());
+            prev = ptr;
+        }
+
+        assert!(b.current_hole_size() < layout.size());
+    }
+
+    #[test]
+    fn upward_allocations_skip_marked_lines() {
+        let mut b = BumpBlock::new().unwrap();
+        let layout = Layout::from_size_align(LINE_SIZE, 8).unwrap();
+        let mark = NonZero::new(1).unwrap();
+        let marked: Vec<*const u8> = (0..LINE_COUNT)
+            .map(|_| b.inner_alloc(layout).unwrap())
+            .step_by(4)
+            .collect();
+
+        for ptr in marked.iter() {
+            unsafe { b.meta.mark(*ptr as *mut u8, layout.size() as u32, SizeClass::Small, mark).unwrap() };
+        }
+
+        b.set_upward(true);
+        b.reset_hole(mark);
+
+        let mut prev = None;
+
+        while let Some(ptr) = b.inner_alloc(Layout::new::<u64>()) {
+            let line = b.line_of(ptr).unwrap();
+
+            assert!(prev < Some(ptr as usize));
+            assert_ne!(b.meta.get_line(line), mark.get());
+            // the line after a marked one is conservatively treated as in use
+            assert!(line == 0 || b.meta.get_line(line - 1) != mark.get());
+            prev = Some(ptr as usize);
+        }
+
+        assert!(prev.is_some());
+    }
+
     #[test]
     fn test_current_hole_size() {
         let block = BumpBlock::new().unwrap();
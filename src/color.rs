@@ -0,0 +1,15 @@
+/// Selects one of the two independent sets of marks kept by every line, block
+/// and large object when the `dual-mark` feature is enabled.
+///
+/// The primary marks are the ones [`Heap::mark`] sets and sweeps go by. The
+/// secondary marks let a collector trace the next cycle while the marks of the
+/// previous one stay intact, [`Heap::sweep_color`] then decides which of the
+/// two is authoritative.
+///
+/// [`Heap::mark`]: crate::Heap::mark
+/// [`Heap::sweep_color`]: crate::Heap::sweep_color
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Color {
+    Primary,
+    Secondary,
+}
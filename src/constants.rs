@@ -1,16 +1,138 @@
 pub const FREE_MARK: u8 = 0;
+// the geometry heaps get unless they ask for another one
 pub const BLOCK_SIZE: usize = 1024 * 16;
 pub const LINE_SIZE: usize = 128;
-pub const LINE_COUNT: usize = (BLOCK_SIZE - 1) / (LINE_SIZE + 1);
-pub const BLOCK_CAPACITY: usize = LINE_COUNT * LINE_SIZE;
-pub const LINE_MARK_START: usize = BLOCK_CAPACITY;
-pub const BLOCK_MARK_OFFSET: usize = LINE_MARK_START + LINE_COUNT;
+// Blocks are found by rounding an address down to their size, and recorded in
+// the block directory for every MIN_BLOCK_SIZE stretch they span. Medium
+// objects are recorded there by the MIN_LINE_SIZE granule they start and end
+// in, so no line may be smaller.
+pub const MIN_BLOCK_SIZE: usize = 1024 * 4;
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024 * 64;
+pub const MIN_LINE_SIZE: usize = 32;
+// every line has a mark byte for each color it can be marked with
+#[cfg(not(feature = "dual-mark"))]
+pub const MARK_COLORS: usize = 1;
+#[cfg(feature = "dual-mark")]
+pub const MARK_COLORS: usize = 2;
+// the card byte of a write barrier is only reserved when it is asked for
+#[cfg(not(feature = "card-marks"))]
+pub const CARD_MARK_BYTES: usize = 0;
+#[cfg(feature = "card-marks")]
+pub const CARD_MARK_BYTES: usize = 1;
+// bytes besides the line marks: a block mark for each color, the age, the
+// pinned flag, the untraced mark and the card mark
+pub const BLOCK_META_BYTES: usize = 3 + CARD_MARK_BYTES + MARK_COLORS;
+#[cfg_attr(not(test), allow(dead_code))]
+pub const LINE_COUNT: usize = line_count(BLOCK_SIZE, LINE_SIZE);
+pub const BLOCK_CAPACITY: usize = block_capacity(BLOCK_SIZE, LINE_SIZE);
+#[cfg(feature = "card-marks")]
+pub const CLEAN_CARD: u8 = 0;
+#[cfg(feature = "card-marks")]
+pub const DIRTY_CARD: u8 = 1;
+pub const CACHE_LINE_SIZE: usize = 64;
 pub const MAX_ALLOC_SIZE: usize = u32::MAX as usize;
 pub const SMALL_OBJECT_MIN: usize = 1;
+/// The largest [`Small`](crate::SizeClass::Small) object, one line, in a heap
+/// with the default line size.
 pub const SMALL_OBJECT_MAX: usize = LINE_SIZE;
-pub const MEDIUM_OBJECT_MIN: usize = SMALL_OBJECT_MAX + 1;
+/// The largest [`Medium`](crate::SizeClass::Medium) object, the data region of
+/// a block, in a heap with the default block and line sizes.
 pub const MEDIUM_OBJECT_MAX: usize = BLOCK_CAPACITY;
+/// The smallest [`Large`](crate::SizeClass::Large) object in a heap with the
+/// default block and line sizes.
 pub const LARGE_OBJECT_MIN: usize = MEDIUM_OBJECT_MAX + 1;
-pub const LARGE_OBJECT_MAX: usize = MAX_ALLOC_SIZE;
+// lines following a marked line that are assumed to be in use as well
+pub const CONSERVATIVE_LINES: usize = 1;
 pub const MAX_FREE_BLOCKS: usize = 100;
+// dead large blocks kept for reuse, per object size
+pub const MAX_POOLED_LARGE_PER_SIZE: usize = 4;
 pub const RECYCLE_HOLE_MIN: usize = LINE_SIZE * 5;
+// blocks a handle fills with small objects alone before it takes free blocks
+// ahead of recycled ones
+pub const SMALL_BURST_BLOCKS: usize = 4;
+// tables mark_if_unmarked records objects in, picked by the object's block
+pub const TRACED_SHARDS: usize = 64;
+
+// Number of lines that fit in a block once every line has its mark bytes and
+// the block metadata is accounted for, so the mark region grows with the block.
+// With the metadata kept on the side every line of the block holds data. Line
+// sizes must be powers of two no larger than a block, and there must be room
+// for at least one line.
+pub const fn line_count(block_size: usize, line_size: usize) -> usize {
+    assert!(line_size.is_power_of_two() && line_size <= block_size);
+
+    if cfg!(feature = "side-meta") {
+        return block_size / line_size;
+    }
+
+    let count = (block_size - BLOCK_META_BYTES) / (line_size + MARK_COLORS);
+
+    assert!(count > 0);
+    assert!(count * (line_size + MARK_COLORS) + BLOCK_META_BYTES <= block_size);
+
+    count
+}
+
+// the bytes of a block that hold objects, below the metadata
+pub const fn block_capacity(block_size: usize, line_size: usize) -> usize {
+    line_count(block_size, line_size) * line_size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg_attr(feature = "side-meta", allow(dead_code))]
+    fn assert_metadata_fits(block_size: usize, line_size: usize) {
+        let count = line_count(block_size, line_size);
+        let capacity = block_capacity(block_size, line_size);
+
+        // line marks plus block metadata end within the block
+        assert!(capacity + count * MARK_COLORS + BLOCK_META_BYTES <= block_size);
+        // and one more line would no longer fit
+        assert!((count + 1) * (line_size + MARK_COLORS) + BLOCK_META_BYTES > block_size);
+    }
+
+    #[cfg(not(any(feature = "side-meta", feature = "dual-mark")))]
+    #[test]
+    fn default_line_size() {
+        assert_eq!(LINE_COUNT, line_count(BLOCK_SIZE, 128));
+        assert_eq!(LINE_COUNT, 126);
+        assert_metadata_fits(BLOCK_SIZE, LINE_SIZE);
+    }
+
+    #[cfg(all(feature = "dual-mark", not(feature = "side-meta")))]
+    #[test]
+    fn second_color_costs_one_line() {
+        // the extra mark bytes take up the space left over by the first color,
+        // and a line besides
+        assert_eq!(line_count(1024 * 16, 128), 125);
+        assert_metadata_fits(BLOCK_SIZE, LINE_SIZE);
+        assert_metadata_fits(BLOCK_SIZE, 64);
+    }
+
+    #[cfg(not(feature = "side-meta"))]
+    #[test]
+    fn metadata_fits_every_block_and_line_size() {
+        let mut block_size = MIN_BLOCK_SIZE;
+
+        while block_size <= 1024 * 1024 {
+            let mut line_size = MIN_LINE_SIZE;
+
+            while line_size < block_size / 2 {
+                assert_metadata_fits(block_size, line_size);
+                line_size *= 2;
+            }
+
+            block_size *= 2;
+        }
+    }
+
+    #[cfg(feature = "side-meta")]
+    #[test]
+    fn side_metadata_leaves_whole_block_for_data() {
+        assert_eq!(BLOCK_CAPACITY, BLOCK_SIZE);
+        assert_eq!(LINE_COUNT, BLOCK_SIZE / LINE_SIZE);
+        assert_eq!(line_count(256 * 1024, 64), 4096);
+    }
+}
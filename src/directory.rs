@@ -0,0 +1,112 @@
+use super::constants::MIN_BLOCK_SIZE;
+use super::error::AllocError;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+// A slot for each stretch of the address space as large as the smallest
+// block, found from an address without taking a lock, so a larger block spans
+// several slots. The index of a stretch, its address over the smallest block
+// size, is split in two: the high half picks a chunk of the directory and the
+// low half a slot in that chunk. A chunk is allocated along with the first
+// block in its range and kept for good.
+const ADDRESS_BITS: u32 = if usize::BITS < 48 { usize::BITS } else { 48 };
+const INDEX_BITS: u32 = ADDRESS_BITS - MIN_BLOCK_SIZE.trailing_zeros();
+const SLOT_BITS: u32 = INDEX_BITS / 2;
+const CHUNK_COUNT: usize = 1 << (INDEX_BITS - SLOT_BITS);
+const CHUNK_SLOTS: usize = 1 << SLOT_BITS;
+
+type Chunk<T> = [T; CHUNK_SLOTS];
+
+/// What a directory holds for each stretch.
+///
+/// # Safety
+///
+/// Implementors must be valid when all zeroes, as that is how the slots of a
+/// new chunk start out.
+pub unsafe trait Slot: Sync + 'static {}
+
+unsafe impl Slot for AtomicBool {}
+unsafe impl<T: 'static> Slot for AtomicPtr<T> {}
+
+pub struct Directory<T: Slot> {
+    chunks: [AtomicPtr<Chunk<T>>; CHUNK_COUNT],
+}
+
+impl<T: Slot> Directory<T> {
+    pub const fn new() -> Self {
+        Self {
+            chunks: [const { AtomicPtr::new(ptr::null_mut()) }; CHUNK_COUNT],
+        }
+    }
+
+    // The chunk and slot of the stretch holding `addr`, or None if the
+    // address is past those the directory covers.
+    fn position(addr: *const u8) -> Option<(usize, usize)> {
+        let index = addr as usize / MIN_BLOCK_SIZE;
+        let chunk = index >> SLOT_BITS;
+
+        (chunk < CHUNK_COUNT).then_some((chunk, index & (CHUNK_SLOTS - 1)))
+    }
+
+    // The slot of the stretch holding `addr`, or None if no block in its range
+    // has been inserted yet.
+    pub fn get(&self, addr: *const u8) -> Option<&T> {
+        let (chunk, slot) = Self::position(addr)?;
+
+        // SAFETY: chunks are never freed
+        unsafe { self.chunks[chunk].load(Ordering::Acquire).as_ref() }.map(|chunk| &chunk[slot])
+    }
+
+    // Returns the slot of the stretch holding `addr`, allocating its chunk if
+    // no block in the range has been inserted yet.
+    pub fn get_or_insert(&self, addr: *const u8) -> Result<&T, AllocError> {
+        let (index, slot) = Self::position(addr).ok_or(AllocError::OOM)?;
+        let entry = &self.chunks[index];
+        let chunk = entry.load(Ordering::Acquire);
+
+        if !chunk.is_null() {
+            return Ok(unsafe { &(*chunk)[slot] });
+        }
+
+        // chunks are kept for good, so they come straight from the system
+        // allocator rather than from a global allocator that may be a heap
+        let layout = Layout::new::<Chunk<T>>();
+        let new = unsafe { System.alloc_zeroed(layout) } as *mut Chunk<T>;
+
+        if new.is_null() {
+            return Err(AllocError::OOM);
+        }
+
+        match entry.compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => Ok(unsafe { &(*new)[slot] }),
+            Err(chunk) => {
+                // another block in the range got there first
+                unsafe { System.dealloc(new as *mut u8, layout) };
+
+                Ok(unsafe { &(*chunk)[slot] })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_start_out_zeroed() {
+        static DIRECTORY: Directory<AtomicBool> = Directory::new();
+        let block = (MIN_BLOCK_SIZE * 3) as *const u8;
+        let inside = (MIN_BLOCK_SIZE * 4 - 1) as *const u8;
+
+        assert!(DIRECTORY.get(block).is_none());
+        assert!(!DIRECTORY.get_or_insert(block).unwrap().load(Ordering::Relaxed));
+
+        DIRECTORY.get_or_insert(block).unwrap().store(true, Ordering::Relaxed);
+
+        // every address in the stretch shares its slot, the next stretch has its own
+        assert!(DIRECTORY.get(inside).unwrap().load(Ordering::Relaxed));
+        assert!(!DIRECTORY.get(inside.wrapping_add(1)).unwrap().load(Ordering::Relaxed));
+    }
+}
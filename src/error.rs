@@ -1,4 +1,5 @@
 use std::alloc::LayoutError;
+use std::fmt;
 
 impl From<LayoutError> for AllocError {
     fn from(_: LayoutError) -> Self {
@@ -11,4 +12,58 @@ pub enum AllocError {
     OOM,
     AllocOverflow,
     LayoutError,
+    // the pointer isn't an object the operation can be applied to
+    UnknownObject,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OOM => write!(f, "out of memory"),
+            Self::AllocOverflow => write!(f, "allocation size exceeds the maximum the heap supports"),
+            Self::LayoutError => write!(f, "invalid layout"),
+            Self::UnknownObject => write!(f, "pointer is not an object the heap knows of"),
+        }
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+/// Two live objects of a heap that were found to overlap, each given as its
+/// start address and size in bytes. An object only known by its line marks is
+/// given as the run of marked lines holding it.
+#[derive(Debug)]
+pub struct OverlapError {
+    pub first: (*const u8, usize),
+    pub second: (*const u8, usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn alloc_errors_describe_themselves() {
+        assert_eq!(AllocError::OOM.to_string(), "out of memory");
+        assert_eq!(
+            AllocError::AllocOverflow.to_string(),
+            "allocation size exceeds the maximum the heap supports"
+        );
+        assert_eq!(AllocError::LayoutError.to_string(), "invalid layout");
+        assert_eq!(AllocError::UnknownObject.to_string(), "pointer is not an object the heap knows of");
+    }
+
+    #[test]
+    fn alloc_error_is_an_error() {
+        let err = AllocError::OOM;
+        let err: &dyn Error = &err;
+
+        assert_eq!(err.to_string(), "out of memory");
+        assert!(err.source().is_none());
+
+        let boxed: Box<dyn Error> = AllocError::LayoutError.into();
+
+        assert_eq!(boxed.to_string(), "invalid layout");
+    }
 }
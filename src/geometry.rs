@@ -0,0 +1,222 @@
+use super::constants::{
+    line_count, BLOCK_META_BYTES, BLOCK_SIZE, LINE_SIZE, MARK_COLORS, MAX_ALLOC_SIZE, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE,
+    MIN_LINE_SIZE,
+};
+#[cfg(feature = "dual-mark")]
+use super::constants::CARD_MARK_BYTES;
+use super::error::AllocError;
+use super::size_class::SizeClass;
+use std::alloc::Layout;
+
+// The sizes of a block and of its lines, and where the metadata of such a
+// block lies. Every block records its geometry in the block directory, so a
+// heap can hold blocks of any size and still find the marks of an object from
+// its address alone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Geometry {
+    block_size: usize,
+    line_size: usize,
+    line_count: usize,
+}
+
+impl Geometry {
+    pub const DEFAULT: Geometry = match Geometry::new(BLOCK_SIZE, LINE_SIZE) {
+        Ok(geometry) => geometry,
+        Err(_) => panic!("the default block geometry is invalid"),
+    };
+
+    // Fails with a layout error unless both sizes are powers of two, the block
+    // is within the sizes the directory can record, and it holds at least one
+    // line along with the marks of its lines.
+    pub const fn new(block_size: usize, line_size: usize) -> Result<Geometry, AllocError> {
+        if !block_size.is_power_of_two() || block_size < MIN_BLOCK_SIZE || block_size > MAX_BLOCK_SIZE {
+            return Err(AllocError::LayoutError);
+        }
+
+        if !line_size.is_power_of_two() || line_size < MIN_LINE_SIZE || line_size >= block_size {
+            return Err(AllocError::LayoutError);
+        }
+
+        Ok(Geometry {
+            block_size,
+            line_size,
+            line_count: line_count(block_size, line_size),
+        })
+    }
+
+    pub const fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub const fn line_size(&self) -> usize {
+        self.line_size
+    }
+
+    pub const fn line_count(&self) -> usize {
+        self.line_count
+    }
+
+    // the bytes of the block that hold objects, below the metadata
+    pub const fn capacity(&self) -> usize {
+        self.line_count * self.line_size
+    }
+
+    // The metadata follows the data region of the block, or starts the side
+    // allocation holding it. The marks of the lines come first, then a byte
+    // each for the block mark, the age, the pinned flag and the untraced mark,
+    // then the card, then the marks of the second color.
+    pub const fn line_mark_start(&self) -> usize {
+        if cfg!(feature = "side-meta") {
+            0
+        } else {
+            self.capacity()
+        }
+    }
+
+    pub const fn block_mark_offset(&self) -> usize {
+        self.line_mark_start() + self.line_count
+    }
+
+    pub const fn age_offset(&self) -> usize {
+        self.block_mark_offset() + 1
+    }
+
+    pub const fn pinned_offset(&self) -> usize {
+        self.block_mark_offset() + 2
+    }
+
+    pub const fn untraced_offset(&self) -> usize {
+        self.block_mark_offset() + 3
+    }
+
+    #[cfg(feature = "card-marks")]
+    pub const fn card_offset(&self) -> usize {
+        self.untraced_offset() + 1
+    }
+
+    #[cfg(feature = "dual-mark")]
+    pub const fn secondary_line_mark_start(&self) -> usize {
+        self.untraced_offset() + 1 + CARD_MARK_BYTES
+    }
+
+    #[cfg(feature = "dual-mark")]
+    pub const fn secondary_block_mark_offset(&self) -> usize {
+        self.secondary_line_mark_start() + self.line_count
+    }
+
+    // the size of the metadata, wherever it is kept
+    #[cfg_attr(not(feature = "side-meta"), allow(dead_code))]
+    pub const fn meta_size(&self) -> usize {
+        self.line_count * MARK_COLORS + BLOCK_META_BYTES
+    }
+
+    // blocks with fewer marked lines than this are evacuation candidates
+    pub const fn evacuation_line_max(&self) -> usize {
+        self.line_count / 4
+    }
+
+    // Classifies an object by its size within blocks of this geometry: small
+    // objects fit in a line, medium ones in the data region of a block.
+    pub fn size_class_of(&self, size: usize) -> Result<SizeClass, AllocError> {
+        match size {
+            0 => Err(AllocError::AllocOverflow),
+            size if size <= self.line_size => Ok(SizeClass::Small),
+            size if size <= self.capacity() => Ok(SizeClass::Medium),
+            size if size <= MAX_ALLOC_SIZE => Ok(SizeClass::Large),
+            _ => Err(AllocError::AllocOverflow),
+        }
+    }
+
+    // Small and medium objects are placed relative to a block, which is only
+    // aligned to its own size, so objects asking for more alignment than that
+    // get a large block padded to whatever alignment they need.
+    pub fn size_class(&self, layout: Layout) -> Result<SizeClass, AllocError> {
+        let size_class = self.size_class_of(layout.size())?;
+
+        if layout.align() > self.block_size {
+            return Ok(SizeClass::Large);
+        }
+
+        Ok(size_class)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{BLOCK_CAPACITY, LINE_COUNT};
+
+    #[test]
+    fn default_geometry_matches_the_constants() {
+        let geometry = Geometry::DEFAULT;
+
+        assert_eq!(geometry.block_size(), BLOCK_SIZE);
+        assert_eq!(geometry.line_count(), LINE_COUNT);
+        assert_eq!(geometry.capacity(), BLOCK_CAPACITY);
+    }
+
+    #[test]
+    fn metadata_ends_within_its_space() {
+        for (block_size, line_size) in [
+            (BLOCK_SIZE, LINE_SIZE),
+            (4096, 32),
+            (256 * 1024, 128),
+            (MAX_BLOCK_SIZE, 1024),
+        ] {
+            let geometry = Geometry::new(block_size, line_size).unwrap();
+            let meta_end = geometry.line_mark_start() + geometry.meta_size();
+
+            assert!(geometry.block_mark_offset() + 3 < meta_end);
+
+            if cfg!(feature = "side-meta") {
+                assert_eq!(geometry.capacity(), block_size);
+            } else {
+                assert!(meta_end <= block_size);
+            }
+        }
+    }
+
+    #[cfg(not(any(feature = "side-meta", feature = "dual-mark")))]
+    #[test]
+    fn marks_grow_with_the_block() {
+        let geometry = Geometry::new(256 * 1024, 128).unwrap();
+
+        // the marks take up almost 16 lines worth of space, not a fixed amount
+        assert_eq!(geometry.line_count(), 2032);
+        assert_eq!(geometry.line_mark_start(), 2032 * 128);
+        assert_eq!(geometry.meta_size(), 2032 + BLOCK_META_BYTES);
+    }
+
+    #[test]
+    fn invalid_geometries_are_rejected() {
+        for (block_size, line_size) in [
+            (BLOCK_SIZE + 1, LINE_SIZE),
+            (MIN_BLOCK_SIZE / 2, LINE_SIZE),
+            (MAX_BLOCK_SIZE * 2, LINE_SIZE),
+            (BLOCK_SIZE, 48),
+            (BLOCK_SIZE, MIN_LINE_SIZE / 2),
+            (BLOCK_SIZE, BLOCK_SIZE),
+        ] {
+            assert!(matches!(
+                Geometry::new(block_size, line_size),
+                Err(AllocError::LayoutError)
+            ));
+        }
+    }
+
+    #[test]
+    fn objects_are_classified_by_the_geometry() {
+        let geometry = Geometry::new(256 * 1024, 64).unwrap();
+        let capacity = geometry.capacity();
+
+        assert_eq!(geometry.size_class_of(64).unwrap(), SizeClass::Small);
+        assert_eq!(geometry.size_class_of(65).unwrap(), SizeClass::Medium);
+        assert_eq!(geometry.size_class_of(capacity).unwrap(), SizeClass::Medium);
+        assert_eq!(geometry.size_class_of(capacity + 1).unwrap(), SizeClass::Large);
+        assert!(geometry.size_class_of(0).is_err());
+
+        let over_aligned = Layout::from_size_align(8, 512 * 1024).unwrap();
+
+        assert_eq!(geometry.size_class(over_aligned).unwrap(), SizeClass::Large);
+    }
+}
@@ -0,0 +1,221 @@
+use super::alloc_head::AllocHead;
+use super::block_directory;
+use super::block_store::{BlockStore, SweepStats};
+use super::error::AllocError;
+use super::size_class::SizeClass;
+use super::Heap;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::num::NonZero;
+use std::ptr;
+use std::sync::{Arc, OnceLock};
+
+thread_local! {
+    // set while this thread runs heap code, whose own bookkeeping is served by
+    // the system allocator rather than by the heap it is managing
+    static IN_HEAP: Cell<bool> = const { Cell::new(false) };
+
+    // this thread's handle for each global heap it has allocated from, keyed
+    // by the address of the heap's block store
+    static HANDLES: Handles = const { Handles(RefCell::new(Vec::new())) };
+}
+
+struct Handles(RefCell<Vec<(usize, Heap)>>);
+
+// the heap behind the crate root `alloc` and `sweep`
+static DEFAULT_HEAP: NimixGlobal = NimixGlobal::new();
+
+/// Allocates from a heap shared by the whole process, created on first use.
+///
+/// The heap's block store is shared by every thread, while each thread
+/// allocates through a handle of its own, as with [`NimixGlobal`]. Allocating
+/// only takes a lock when the thread's handle needs a new block, so threads
+/// can allocate at the same time. Fails with [`AllocError::OOM`] when called
+/// from a thread that is exiting, as it no longer has a handle.
+///
+/// # Safety
+///
+/// The returned memory is uninitialized and only remains valid until a call to
+/// [`sweep`] in which the object was not marked.
+pub unsafe fn alloc(layout: Layout) -> Result<*const u8, AllocError> {
+    match DEFAULT_HEAP.with_handle(|heap| heap.alloc(layout)) {
+        Some(result) => result.map(|ptr| ptr as *const u8),
+        None => Err(AllocError::OOM),
+    }
+}
+
+/// Sweeps the heap [`alloc`] allocates from, reclaiming every object that was
+/// not marked with `mark`, and calls `cb` once the blocks are locked.
+///
+/// The calling thread's handle hands its blocks back first so they are swept
+/// too. Blocks held by the handles of other threads are not swept, so those
+/// threads may keep allocating while this runs; their blocks are swept once
+/// the threads exit or by a later sweep after they handed them back. Does
+/// nothing if called from a thread that is exiting.
+///
+/// # Safety
+///
+/// Every object allocated by [`alloc`] that is still in use must have been
+/// marked with `mark`, whichever thread allocated it.
+pub unsafe fn sweep(mark: NonZero<u8>, cb: impl FnOnce()) -> SweepStats {
+    DEFAULT_HEAP
+        .with_handle(|heap| {
+            heap.head.flush();
+            heap.sweep(mark, cb)
+        })
+        .unwrap_or_default()
+}
+
+impl Drop for Handles {
+    fn drop(&mut self) {
+        // handing the blocks back to the heaps may allocate
+        let handles = self.0.take();
+
+        enter(|| drop(handles));
+    }
+}
+
+// Runs `f` as heap code, unless this thread is already running heap code.
+fn enter<R>(f: impl FnOnce() -> R) -> Option<R> {
+    if IN_HEAP.with(|in_heap| in_heap.replace(true)) {
+        return None;
+    }
+
+    let result = f();
+
+    IN_HEAP.with(|in_heap| in_heap.set(false));
+
+    Some(result)
+}
+
+/// A heap that can be installed as the program's `#[global_allocator]`.
+///
+/// The heap is created on first use. Each thread allocates through a handle
+/// of its own, created the first time the thread allocates, so allocations
+/// only contend on the heap when a thread needs a new block. Whatever the
+/// heap needs to allocate for its own bookkeeping, as well as anything
+/// allocated by a thread after its handle was dropped on exit, is served by
+/// the system allocator.
+///
+/// Freeing an object does nothing, objects are reclaimed by
+/// [`NimixGlobal::collect`] like those of any other heap. Only memory that
+/// came from the system allocator is freed right away, except for large
+/// allocations freed while the heap itself is running, such as from the
+/// callback given to a sweep, which are leaked as telling them apart from the
+/// heap's own would mean taking its locks.
+///
+/// Other heaps are unaffected by it: their blocks come from the system
+/// allocator directly, see [`SystemBacking`], so they are neither objects of
+/// this heap nor left to its sweeps once released.
+///
+/// [`SystemBacking`]: crate::SystemBacking
+pub struct NimixGlobal {
+    store: OnceLock<Arc<BlockStore>>,
+}
+
+impl Default for NimixGlobal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NimixGlobal {
+    pub const fn new() -> Self {
+        Self {
+            store: OnceLock::new(),
+        }
+    }
+
+    /// Sweeps the heap with `mark`, reclaiming every object that was not
+    /// marked with [`Heap::mark`]. Does nothing if the heap has not been used
+    /// yet, or if called from a thread that is exiting.
+    ///
+    /// The calling thread's handle hands its blocks back first so they are
+    /// swept too. Blocks held by the handles of other threads are not swept, as
+    /// with any other heap handle.
+    ///
+    /// # Safety
+    ///
+    /// Every object allocated through this allocator that is still in use must
+    /// have been marked with `mark`. Note this includes allocations made by the
+    /// standard library and any other code running in the program.
+    pub unsafe fn collect(&self, mark: NonZero<u8>) {
+        if self.store.get().is_some() {
+            self.with_handle(|heap| {
+                heap.head.flush();
+                heap.sweep(mark, || {});
+            });
+        }
+    }
+
+    /// The number of bytes taken up by the heap's blocks and large objects, as
+    /// with [`Heap::size`]. Zero if the heap has not been used yet.
+    pub fn size(&self) -> usize {
+        self.store.get().map_or(0, |store| store.get_size())
+    }
+
+    // Calls `f` with this thread's handle, returning None if this thread is
+    // already running heap code or no longer has a handle.
+    fn with_handle<R>(&self, f: impl FnOnce(&Heap) -> R) -> Option<R> {
+        enter(|| {
+            HANDLES
+                .try_with(|handles| {
+                    let store = self.store.get_or_init(|| Arc::new(BlockStore::new()));
+                    let key = Arc::as_ptr(store) as usize;
+                    let mut handles = handles.0.borrow_mut();
+
+                    let index = match handles.iter().position(|(id, _)| *id == key) {
+                        Some(index) => index,
+                        None => {
+                            let heap = Heap {
+                                head: AllocHead::new(store.clone()),
+                            };
+
+                            handles.push((key, heap));
+                            handles.len() - 1
+                        }
+                    };
+
+                    f(&handles[index].1)
+                })
+                .ok()
+        })
+        .flatten()
+    }
+}
+
+unsafe impl GlobalAlloc for NimixGlobal {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.with_handle(|heap| heap.alloc(layout)) {
+            Some(Ok(ptr)) => ptr,
+            Some(Err(_)) => ptr::null_mut(),
+            None => System.alloc(layout),
+        }
+    }
+
+    // Small and medium objects are looked up in the block directory, which
+    // takes no lock and so is safe wherever the free comes from. Only layouts
+    // the heap would have given a large block of their own go through its large
+    // index, and when that can't be locked, because the thread is already
+    // running heap code, the memory is left alone rather than risk handing a
+    // heap object to the system allocator.
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if block_directory::contains(ptr) {
+            return;
+        }
+
+        let store = match SizeClass::get_for_layout(layout) {
+            Ok(SizeClass::Large) => self.store.get(),
+            _ => None,
+        };
+
+        let owned = match store {
+            Some(store) => enter(|| store.find_large(ptr as usize).is_some()),
+            None => Some(false),
+        };
+
+        if owned == Some(false) {
+            System.dealloc(ptr, layout);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use super::backing::Backing;
+use super::constants::BLOCK_SIZE;
+use super::mmap_backing::MmapBacking;
+use std::alloc::Layout;
+use std::sync::Mutex;
+
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// A [`Backing`] that carves blocks out of 2MB chunks the kernel is asked to
+/// back with transparent huge pages, so walking a heap's blocks takes fewer
+/// TLB entries. Chunks are aligned to their size, which keeps every block
+/// aligned to its own size as well.
+///
+/// Released blocks are kept for reuse rather than returned to the OS, since
+/// that would split the huge page they sit in, and the chunks are only
+/// unmapped once the backing is dropped. Large objects and over-aligned blocks
+/// are mapped on their own, like [`MmapBacking`] does.
+#[derive(Debug, Default)]
+pub struct HugePageBacking {
+    chunks: Mutex<Chunks>,
+}
+
+#[derive(Debug, Default)]
+struct Chunks {
+    // every chunk mapped so far
+    mapped: Vec<usize>,
+    // the part of the newest chunk no block has been carved from yet
+    cursor: usize,
+    end: usize,
+    // released blocks, waiting to be handed out again
+    free: Vec<usize>,
+}
+
+impl HugePageBacking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn chunk_layout() -> Layout {
+        Layout::from_size_align(HUGE_PAGE_SIZE, HUGE_PAGE_SIZE).unwrap()
+    }
+
+    fn is_block(layout: Layout) -> bool {
+        layout.size() == BLOCK_SIZE && layout.align() <= BLOCK_SIZE
+    }
+}
+
+impl Backing for HugePageBacking {
+    fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !Self::is_block(layout) {
+            return MmapBacking.alloc(layout);
+        }
+
+        let mut chunks = self.chunks.lock().unwrap();
+
+        if let Some(block) = chunks.free.pop() {
+            return block as *mut u8;
+        }
+
+        if chunks.cursor == chunks.end {
+            let chunk = MmapBacking.alloc(Self::chunk_layout());
+
+            if chunk.is_null() {
+                return chunk;
+            }
+
+            // only a hint, the chunk works the same without huge pages
+            unsafe { libc::madvise(chunk as *mut libc::c_void, HUGE_PAGE_SIZE, libc::MADV_HUGEPAGE) };
+
+            chunks.mapped.push(chunk as usize);
+            chunks.cursor = chunk as usize;
+            chunks.end = chunk as usize + HUGE_PAGE_SIZE;
+        }
+
+        let block = chunks.cursor;
+
+        chunks.cursor += BLOCK_SIZE;
+        block as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if Self::is_block(layout) {
+            self.chunks.lock().unwrap().free.push(ptr as usize);
+        } else {
+            MmapBacking.dealloc(ptr, layout);
+        }
+    }
+
+    // Blocks are reused, so unlike a fresh mapping they may hold old data.
+    fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+
+        if !ptr.is_null() && Self::is_block(layout) {
+            unsafe { ptr.write_bytes(0, layout.size()) };
+        }
+
+        ptr
+    }
+}
+
+impl Drop for HugePageBacking {
+    fn drop(&mut self) {
+        for chunk in self.chunks.get_mut().unwrap().mapped.drain(..) {
+            unsafe { MmapBacking.dealloc(chunk as *mut u8, Self::chunk_layout()) };
+        }
+    }
+}
@@ -1,71 +1,157 @@
+use super::backing::Backing;
 use super::block::Block;
 use super::error::AllocError;
-use super::constants::{FREE_MARK, LARGE_OBJECT_MIN};
+use super::constants::{FREE_MARK, MARK_COLORS};
 
 use std::alloc::Layout;
 use std::num::NonZero;
 use std::sync::atomic::{AtomicU8, Ordering};
 use std::ptr::write;
+use std::sync::Arc;
 
 pub struct LargeBlock {
     block: Block,
-    mark: *const AtomicU8
+    obj: *const u8,
+    // the padded layout the block was made for, which any object no larger
+    // and no more aligned fits in as well
+    obj_layout: Layout,
 }
 
+// SAFETY: the block is Send, and `obj` points into it, so it stays valid for
+// as long as the large block is alive wherever it is moved. The header in front
+// of the object is only accessed through atomics, which is how Heap::mark
+// reaches it from other threads while the store's large list holds the block.
+unsafe impl Send for LargeBlock {}
+
+// The mark byte sits immediately before the object, so it can be found from the
+// object pointer alone no matter how far the object is offset into its block.
+// The object's age, the sweeps it has survived, sits right before the mark,
+// and the mark of the second color, if any, before the age.
 impl LargeBlock {
-    pub fn new(obj_layout: Layout) -> Result<Self, AllocError> {
-        debug_assert!(obj_layout.size() >= LARGE_OBJECT_MIN);
-
-        let mark_layout = Layout::new::<AtomicU8>();
-        let (obj_mark_layout, mark_offset) = obj_layout.extend(mark_layout)?;
-        let block_layout = obj_mark_layout.pad_to_align();
-        let block = Block::new(block_layout)?;
-        let mark = unsafe { 
-            let mark = block.as_ptr().add(mark_offset) as *const AtomicU8;
-            write(mark as *mut AtomicU8, AtomicU8::new(FREE_MARK));
-            mark
+    pub fn new(obj_layout: Layout, backing: &Arc<dyn Backing>) -> Result<Self, AllocError> {
+        let header_layout = Layout::new::<[AtomicU8; 1 + MARK_COLORS]>();
+        let (block_layout, obj_offset) = header_layout.extend(obj_layout)?;
+        let block = Block::new(block_layout.pad_to_align(), backing)?;
+        let obj = unsafe { 
+            let obj = block.as_ptr().add(obj_offset);
+            write(Self::mark_of(obj) as *mut AtomicU8, AtomicU8::new(FREE_MARK));
+            write(Self::age_of(obj) as *mut AtomicU8, AtomicU8::new(0));
+            #[cfg(feature = "dual-mark")]
+            write(Self::secondary_mark_of(obj) as *mut AtomicU8, AtomicU8::new(FREE_MARK));
+            obj
         };
 
         let large_block = Self {
             block,
-            mark
+            obj,
+            obj_layout: obj_layout.pad_to_align(),
         };
 
         Ok(large_block)
     }
 
-    pub unsafe fn mark(ptr: *const u8, obj_layout: Layout, mark: NonZero<u8>) -> Result<(), AllocError> {
-        let mark_layout = Layout::new::<AtomicU8>();
-        let (_, mark_offset) = obj_layout.extend(mark_layout)?;
-        let block_mark: *const AtomicU8 = ptr.add(mark_offset) as *const AtomicU8;
+    // Clears the header of a block taken from the pool, so the object starts
+    // out unmarked and young like a new one.
+    pub fn reset(&self) {
+        unsafe {
+            (&*Self::mark_of(self.obj)).store(FREE_MARK, Ordering::Relaxed);
+            (&*Self::age_of(self.obj)).store(0, Ordering::Relaxed);
+        }
+
+        #[cfg(feature = "dual-mark")]
+        self.clear_secondary();
+    }
+
+    // SAFETY: ptr must point to the start of an object allocated in a large block
+    pub unsafe fn mark(ptr: *const u8, mark: NonZero<u8>) {
+        (&*Self::mark_of(ptr)).store(mark.into(), Ordering::Relaxed);
+    }
+
+    // Marks the object, returning whether it already carried `mark`.
+    // SAFETY: ptr must point to the start of an object allocated in a large block
+    pub unsafe fn swap_mark(ptr: *const u8, mark: NonZero<u8>) -> bool {
+        (&*Self::mark_of(ptr)).swap(mark.into(), Ordering::Relaxed) == mark.get()
+    }
+
+    // SAFETY: ptr must point to the start of an object allocated in a large block
+    #[cfg(feature = "dual-mark")]
+    pub unsafe fn mark_secondary(ptr: *const u8, mark: NonZero<u8>) {
+        (&*Self::secondary_mark_of(ptr)).store(mark.into(), Ordering::Relaxed);
+    }
+
+    // Replaces the primary mark with the secondary one, keeping `mark` only if
+    // the second color carries it, and clears the second color.
+    #[cfg(feature = "dual-mark")]
+    pub fn fold_secondary(&self, mark: NonZero<u8>) {
+        let secondary = unsafe { (&*Self::secondary_mark_of(self.obj)).swap(FREE_MARK, Ordering::Relaxed) };
+        let primary = if secondary == mark.get() { secondary } else { FREE_MARK };
 
-        (&*block_mark).store(mark.into(), Ordering::Relaxed);
+        unsafe { (&*Self::mark_of(self.obj)).store(primary, Ordering::Relaxed) }
+    }
+
+    #[cfg(feature = "dual-mark")]
+    pub fn clear_secondary(&self) {
+        unsafe { (&*Self::secondary_mark_of(self.obj)).store(FREE_MARK, Ordering::Relaxed) }
+    }
 
-        Ok(())
+    // SAFETY: ptr must point to the start of an object allocated in a large block
+    pub unsafe fn is_marked_at(ptr: *const u8, mark: NonZero<u8>) -> bool {
+        (&*Self::mark_of(ptr)).load(Ordering::Relaxed) == mark.into()
+    }
+
+    // SAFETY: ptr must point to the start of an object allocated in a large block
+    pub unsafe fn age_at(ptr: *const u8) -> u8 {
+        (&*Self::age_of(ptr)).load(Ordering::Relaxed)
+    }
+
+    pub fn increment_age(&self) {
+        let age = unsafe { &*Self::age_of(self.obj) };
+
+        age.store(age.load(Ordering::Relaxed).saturating_add(1), Ordering::Relaxed);
+    }
+
+    unsafe fn mark_of(obj: *const u8) -> *const AtomicU8 {
+        obj.sub(1) as *const AtomicU8
+    }
+
+    unsafe fn age_of(obj: *const u8) -> *const AtomicU8 {
+        obj.sub(2) as *const AtomicU8
+    }
+
+    #[cfg(feature = "dual-mark")]
+    unsafe fn secondary_mark_of(obj: *const u8) -> *const AtomicU8 {
+        obj.sub(3) as *const AtomicU8
     }
 
     pub fn is_marked(&self, mark: NonZero<u8>) -> bool {
-        unsafe { (&*self.mark).load(Ordering::Relaxed) == mark.into() }
+        unsafe { Self::is_marked_at(self.obj, mark) }
     }
 
     pub fn get_size(&self) -> usize {
         self.block.get_size()
     }
 
+    pub fn obj_layout(&self) -> Layout {
+        self.obj_layout
+    }
+
     pub fn as_ptr(&self) -> *const u8 {
-        self.block.as_ptr()
+        self.obj
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backing;
+    use crate::constants::LARGE_OBJECT_MIN;
     use std::ptr::write;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn new_large_block() {
         let align = 8;
-        let block =  LargeBlock::new(Layout::from_size_align(LARGE_OBJECT_MIN, align).unwrap()).unwrap();
+        let block =  LargeBlock::new(Layout::from_size_align(LARGE_OBJECT_MIN, align).unwrap(), &backing::system()).unwrap();
 
         assert!(block.get_size() > LARGE_OBJECT_MIN);
         assert_eq!(block.as_ptr() as usize % align, 0);
@@ -77,15 +163,31 @@ mod tests {
         let data = [0u8; LARGE_OBJECT_MIN];
         let align = 8;
         let layout = Layout::from_size_align(LARGE_OBJECT_MIN, align).unwrap();
-        let block =  LargeBlock::new(layout).unwrap();
+        let block =  LargeBlock::new(layout, &backing::system()).unwrap();
 
         unsafe { 
             write(block.as_ptr() as *mut [u8; LARGE_OBJECT_MIN], data);
-            LargeBlock::mark(block.as_ptr(), layout, NonZero::new(1).unwrap()).unwrap();
+            LargeBlock::mark(block.as_ptr(), NonZero::new(1).unwrap());
 
             assert_eq!(&*(block.as_ptr() as *mut [u8; LARGE_OBJECT_MIN]), &data);
         }
 
         assert!(block.is_marked(NonZero::new(1).unwrap()));
     }
+
+    #[test]
+    fn mark_over_aligned_large() {
+        let align = 4096;
+        let layout = Layout::from_size_align(LARGE_OBJECT_MIN, align).unwrap();
+        let block = LargeBlock::new(layout, &backing::system()).unwrap();
+        let mark = NonZero::new(3).unwrap();
+
+        assert_eq!(block.as_ptr() as usize % align, 0);
+        assert!(block.get_size() >= LARGE_OBJECT_MIN + align);
+        assert!(!block.is_marked(mark));
+
+        unsafe { LargeBlock::mark(block.as_ptr(), mark) };
+
+        assert!(block.is_marked(mark));
+    }
 }
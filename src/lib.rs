@@ -1,62 +1,1255 @@
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
+
 mod alloc_head;
+mod allocator_cache;
+mod backing;
 mod block;
+mod block_directory;
+mod block_handle;
+mod block_list;
 mod block_meta;
+#[cfg(feature = "block-pool")]
+mod block_pool;
 mod block_store;
 mod bump_block;
+#[cfg(feature = "dual-mark")]
+mod color;
+mod directory;
 mod error;
+mod geometry;
+mod global;
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+mod huge_page_backing;
 mod large_block;
+#[cfg(all(feature = "mmap", unix))]
+mod mmap_backing;
+#[cfg(feature = "nightly")]
+mod nimix_alloc;
+mod observer;
+mod region;
+#[cfg(feature = "side-meta")]
+mod side_meta;
 mod size_class;
+mod weak_handle;
 mod constants;
 
 use alloc_head::AllocHead;
+#[cfg(feature = "card-marks")]
 use block_meta::BlockMeta;
 use block_store::BlockStore;
-use large_block::LargeBlock;
-use size_class::SizeClass;
+use region::Region;
+use constants::{CACHE_LINE_SIZE, LINE_SIZE};
 use std::num::NonZero;
 use std::alloc::Layout;
+use std::ffi::c_void;
 use std::sync::Arc;
+use std::thread::JoinHandle;
 
-pub use error::AllocError;
+pub use alloc_head::FastPathStats;
+pub use allocator_cache::AllocatorCache;
+pub use backing::{Backing, SystemBacking};
+pub use block::BlockId;
+pub use block_handle::BlockHandle;
+pub use block_store::{AllocCounts, HeapConfig, HeapStats, SweepProgress, SweepStats};
+#[cfg(feature = "dual-mark")]
+pub use color::Color;
+pub use constants::{LARGE_OBJECT_MIN, MEDIUM_OBJECT_MAX, SMALL_OBJECT_MAX};
+pub use error::{AllocError, OverlapError};
+pub use global::{alloc, sweep, NimixGlobal};
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+pub use huge_page_backing::HugePageBacking;
+#[cfg(all(feature = "mmap", unix))]
+pub use mmap_backing::MmapBacking;
+#[cfg(feature = "nightly")]
+pub use nimix_alloc::NimixAlloc;
+pub use observer::HeapObserver;
+pub use size_class::SizeClass;
+pub use weak_handle::WeakHandle;
 
+/// A handle to a heap, cloned to get another handle to the same heap.
+///
+/// A handle allocates into blocks it holds on its own, so it can be sent to
+/// another thread but not shared between threads; each thread allocates
+/// through a clone of its own instead.
+///
+/// Objects are bump allocated into blocks of `BLOCK_SIZE` bytes, 16KB unless
+/// the heap is created with [`Heap::new_sized`] or [`Heap::try_new_sized_in`].
+/// Associated functions that don't create a heap, like [`Heap::mark`], work
+/// for objects of heaps of any block size.
 #[derive(Clone)]
-pub struct Heap {
-    head: AllocHead
+pub struct Heap<const BLOCK_SIZE: usize = { constants::BLOCK_SIZE }> {
+    head: AllocHead<BLOCK_SIZE>
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Heap {
     pub fn new() -> Self {
+        Self::new_sized()
+    }
+
+    /// Creates a heap tuned by `config`.
+    pub fn with_config(config: HeapConfig) -> Self {
         let store = Arc::new(BlockStore::new());
 
+        store.set_config(config);
+
+        Self {
+            head: AllocHead::new(store),
+        }
+    }
+
+    /// Creates a heap whose blocks and large objects are allocated from
+    /// `backing` rather than from the system allocator.
+    pub fn with_backing(backing: impl Backing + 'static) -> Self {
+        let store = Arc::new(BlockStore::with_backing(Arc::new(backing)));
+
+        Self {
+            head: AllocHead::new(store),
+        }
+    }
+
+    /// Creates a heap whose blocks are carved out of the `len` bytes starting at
+    /// `base`, rather than requested from the system allocator. Blocks are
+    /// aligned to their size, so some of the region may go unused. Large
+    /// objects are still allocated from the system allocator.
+    ///
+    /// Returns an error if the region cannot hold a single block.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes and must not be used by
+    /// anything else for as long as this heap, or any clone of it, is alive.
+    pub unsafe fn try_new_in(base: *mut u8, len: usize) -> Result<Self, AllocError> {
+        Self::try_new_sized_in(base, len)
+    }
+
+    /// Creates a heap in which every object has the layout of `T`, allowing the
+    /// live objects to be walked with [`Heap::iter_live_as`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is zero sized, or if its size (padded to its alignment) does
+    /// not evenly divide the line size. Objects must tile each line exactly so
+    /// that their positions can be recovered from the line marks alone.
+    ///
+    /// # Safety
+    ///
+    /// Every allocation made through this heap, or any clone of it, must use
+    /// `Layout::new::<T>()` and the allocated object must be initialized as a `T`
+    /// before the heap is next walked.
+    ///
+    /// Liveness is only tracked per line, so a walk also yields dead objects
+    /// that share a line with a live one, as well as slots of a partially used
+    /// line that were never handed out. Those slots hold either a stale `T` or
+    /// zeroed memory, therefore `T` must be valid for the all-zero bit pattern
+    /// and must stay valid to read after the program is done with it (in
+    /// practice `T` should be plain old data with no `Drop` impl).
+    pub unsafe fn new_homogeneous<T>() -> Self {
+        let layout = Layout::new::<T>();
+        let stride = layout.pad_to_align().size();
+
+        assert!(stride != 0, "homogeneous heap objects must not be zero sized");
+
+        let store = Arc::new(BlockStore::homogeneous(layout));
+
+        assert!(store.geometry().line_size() % stride == 0, "homogeneous heap objects must tile a line");
+
         Self {
             head: AllocHead::new(store),
         }
     }
+
+    /// Marks a slab allocated with [`Heap::alloc_slab`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a slab allocated by a heap with the same number of
+    /// lines.
+    pub unsafe fn mark_slab(ptr: *mut u8, lines: usize, mark: NonZero<u8>) -> Result<(), AllocError> {
+        // the lines of a slab within a block are those of the block, a slab of
+        // its own is marked whole whatever its lines are
+        let line_size = block_directory::get(ptr).map_or(LINE_SIZE, |record| record.geometry().line_size());
+
+        Self::mark(ptr, Self::slab_layout(lines, line_size)?, mark)
+    }
+
+    /// Marks an object allocated with [`Heap::alloc_with_header`], given its
+    /// body pointer and the layouts it was allocated with.
+    ///
+    /// # Safety
+    ///
+    /// `body_ptr` must be the body pointer returned when allocating the object
+    /// with the same `header` and `body` layouts.
+    pub unsafe fn mark_with_header(body_ptr: *mut u8, header: Layout, body: Layout, mark: NonZero<u8>) -> Result<(), AllocError> {
+        let (layout, body_offset) = Self::header_layout(header, body)?;
+
+        Self::mark(body_ptr.sub(body_offset), layout, mark)
+    }
+
+    /// Marks the object at `ptr` with `mark`, so the next sweep with `mark`
+    /// retains it. Where the object lies isn't recorded, so its block is
+    /// flagged as holding an untraced object, and [`Heap::sweep_evacuating`]
+    /// leaves a block flagged with the mark it sweeps with in place, see
+    /// [`Heap::mark_if_unmarked`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an object allocated by a heap with the given layout.
+    pub unsafe fn mark(ptr: *mut u8, layout: Layout, mark: NonZero<u8>) -> Result<(), AllocError> {
+        block_store::mark_object(ptr, layout, mark)
+    }
+
+    /// Marks an object like [`Heap::mark`], in the given color. Marking with
+    /// [`Color::Primary`] is the same as [`Heap::mark`], marking with
+    /// [`Color::Secondary`] leaves the primary marks untouched, and is only
+    /// taken into account by [`Heap::sweep_color`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an object allocated by a heap with the given layout.
+    #[cfg(feature = "dual-mark")]
+    pub unsafe fn mark_color(ptr: *mut u8, layout: Layout, color: Color, mark: NonZero<u8>) -> Result<(), AllocError> {
+        block_store::mark_object_color(ptr, layout, color, mark)
+    }
+
+    /// Converts the handle into an opaque pointer, e.g. to pass the heap
+    /// through a C host. The handle's allocation blocks are handed back to the
+    /// heap first. The pointer holds one reference to the heap, keeping it
+    /// alive like a clone would, until it is turned back into a handle with
+    /// [`Heap::from_raw`]. Only heaps with the default block size can be passed
+    /// around this way.
+    pub fn into_raw(self) -> *const c_void {
+        let store = self.head.get_store_arc();
+
+        // the pointer counts as a handle until it is turned back into one
+        store.add_handle();
+        drop(self);
+        Arc::into_raw(store) as *const c_void
+    }
+
+    /// Reclaims a pointer returned by [`Heap::into_raw`], returning a handle
+    /// to the same heap.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from [`Heap::into_raw`], and every pointer returned by
+    /// it must be passed to `from_raw` exactly once.
+    pub unsafe fn from_raw(ptr: *const c_void) -> Self {
+        let store = Arc::from_raw(ptr as *const BlockStore);
+
+        // the handle the pointer stood for becomes this one
+        store.remove_handle();
+
+        Self {
+            head: AllocHead::new(store),
+        }
+    }
+
+    /// Returns a new handle to the heap behind a pointer returned by
+    /// [`Heap::into_raw`], leaving the pointer valid, so a host can hand one
+    /// handle to each thread it allocates from.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from [`Heap::into_raw`] and must not have been passed to
+    /// [`Heap::from_raw`] yet.
+    pub unsafe fn clone_from_raw(ptr: *const c_void) -> Self {
+        Arc::increment_strong_count(ptr as *const BlockStore);
+        (*(ptr as *const BlockStore)).add_handle();
+
+        Self::from_raw(ptr)
+    }
+
+    /// Records a write into the block containing `ptr`, for use by a write
+    /// barrier. The block will be returned by the next call to
+    /// [`Heap::dirty_blocks`]. Only available with the `card-marks` feature,
+    /// which reserves a card byte in every block for this.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point into a small or medium object allocated by a heap.
+    #[cfg(feature = "card-marks")]
+    pub unsafe fn mark_card(ptr: *const u8) {
+        // a pointer outside of any block has no card to mark
+        if let Ok(meta) = BlockMeta::from_ptr(ptr) {
+            meta.mark_card();
+        }
+    }
+}
+
+impl<const BLOCK_SIZE: usize> Heap<BLOCK_SIZE> {
+    /// Creates a heap whose blocks are `BLOCK_SIZE` bytes rather than 16KB,
+    /// e.g. `Heap::<4096>::new_sized()` for a small arena where 16KB blocks
+    /// are too coarse. Smaller blocks also make for a smaller largest medium
+    /// object, see [`Heap::can_allocate`]. A block size that isn't a power of
+    /// two from 4KB up to 64MB fails to compile.
+    pub fn new_sized() -> Self {
+        let store = Arc::new(BlockStore::new_sized());
+
+        Self {
+            head: AllocHead::new(store),
+        }
+    }
+
+    /// Like [`Heap::try_new_in`], for a heap with blocks of `BLOCK_SIZE` bytes,
+    /// see [`Heap::new_sized`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::try_new_in`].
+    pub unsafe fn try_new_sized_in(base: *mut u8, len: usize) -> Result<Self, AllocError> {
+        let region = Region::new(base, len)?;
+        let store = Arc::new(BlockStore::in_region(region));
+
+        Ok(Self {
+            head: AllocHead::new(store),
+        })
+    }
+
+    /// Puts the heap in a mode where blocks are never recycled or reused, every
+    /// block is bump allocated from top to bottom once and then a fresh block is
+    /// requested. Allocation offsets within a block are then reproducible from
+    /// run to run, at the cost of never reclaiming memory.
+    pub fn with_deterministic_allocation(self) -> Self {
+        self.head.get_store().set_deterministic(true);
+        self
+    }
+
+    /// Splits each sweep across `workers` threads once the heap holds at least
+    /// `min_blocks` blocks. Smaller heaps are swept on the calling thread, where
+    /// spawning workers would cost more than it saves.
+    pub fn with_parallel_sweep(self, min_blocks: usize, workers: usize) -> Self {
+        self.head.get_store().set_parallel_sweep(min_blocks, workers);
+        self
+    }
+
+    /// Installs an observer that is notified of allocations, new blocks and
+    /// sweeps. See [`HeapObserver`] for the events reported.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the heap already has an observer.
+    pub fn with_observer(self, observer: Box<dyn HeapObserver>) -> Self {
+        self.head.get_store().set_observer(observer);
+        self
+    }
+
+    /// Makes allocation panic when the heap runs out of memory, rather than
+    /// returning [`AllocError::OOM`]. The panic message includes the requested
+    /// size and the heap's size and usage, and the backtrace points at the
+    /// failing allocation. Meant for debugging, off by default.
+    pub fn set_panic_on_oom(&self, panic_on_oom: bool) {
+        self.head.get_store().set_panic_on_oom(panic_on_oom);
+    }
+
+    /// Sets how many lines following a marked line are treated as in use when
+    /// looking for holes, by default 1. Small objects are only marked in the
+    /// line they start in, so this must cover however far a small object can
+    /// spill past that line. Setting it to 0 packs the heap tightly but is only
+    /// sound if no small object straddles a line boundary.
+    ///
+    /// This applies to blocks the heap creates afterwards, so it should be set
+    /// before allocating.
+    pub fn with_conservative_lines(self, lines: usize) -> Self {
+        self.head.get_store().set_conservative_lines(lines);
+        self
+    }
+
+    /// Marks every object allocated through this handle with `color` as soon
+    /// as it is allocated, so a sweep with that mark keeps all objects born
+    /// since the color was last swept without the tracer visiting them. The
+    /// setting belongs to this handle only, clones start without a birth
+    /// color.
+    pub fn with_birth_color(self, color: NonZero<u8>) -> Self {
+        self.head.set_birth_color(Some(color));
+        self
+    }
+
+    /// Has each sweep leave the surviving blocks in address order, so that the
+    /// following sweep walks their metadata front to back instead of jumping
+    /// around the heap, at the cost of sorting the blocks. The `sweep order`
+    /// benchmark compares the two.
+    pub fn with_ordered_sweep(self, ordered: bool) -> Self {
+        self.head.get_store().set_ordered_sweep(ordered);
+        self
+    }
+
+    /// Keeps a reserve of at least `count` free blocks, allocated right away,
+    /// so a warmed up heap never has to request blocks for allocations the
+    /// reserve can serve. Neither sweeps nor [`Heap::on_memory_pressure`]
+    /// release free blocks below this count.
+    ///
+    /// Returns an error if the reserve can't be allocated.
+    pub fn with_min_block_count(self, count: usize) -> Result<Self, AllocError> {
+        self.head.get_store().set_min_free_blocks(count)?;
+
+        Ok(self)
+    }
+
+    /// Requests up to `count` blocks up front, stopping at the first one that
+    /// can't be allocated rather than failing, and returns how many were
+    /// added. Unlike the reserve of [`Heap::with_min_block_count`], blocks
+    /// beyond the free block limit are released again by the next sweep.
+    pub fn prealloc_best_effort(&self, count: usize) -> usize {
+        self.head.get_store().prealloc_best_effort(count)
+    }
+
+    /// Aligns the blocks the heap requests from the system allocator to `align`
+    /// rather than to their own size, e.g. to line blocks up with huge pages.
+    /// Objects find their block by rounding their address down to the block
+    /// size, so the alignment must be a power of two no smaller than the block
+    /// size, otherwise an error is returned. Blocks of a region backed heap are
+    /// always aligned to their size.
+    pub fn with_block_alignment(self, align: usize) -> Result<Self, AllocError> {
+        self.head.get_store().set_block_alignment(align)?;
+
+        Ok(self)
+    }
+
+    /// Gives the heap's blocks lines of `line_size` bytes rather than 128.
+    /// Smaller lines waste less space around small objects that survive a
+    /// sweep, at the cost of a mark byte for every line, which leaves each
+    /// block fewer bytes for objects. Objects up to a line in size are small
+    /// objects, so the size class boundaries move with the line size.
+    ///
+    /// The line size must be a power of two of at least 32 bytes and smaller
+    /// than a block, and the objects of a homogeneous heap must still tile a
+    /// line. Blocks keep the line size they were made with, so an error is
+    /// also returned once the heap has blocks or has been cloned.
+    pub fn with_line_size(self, line_size: usize) -> Result<Self, AllocError> {
+        self.head.set_line_size(line_size)?;
+
+        Ok(self)
+    }
+
+    /// Sets a soft limit on the bytes in use. Allocation carries on past it, but
+    /// [`Heap::over_soft_limit`] starts reporting true so the embedder knows a
+    /// collection is due.
+    pub fn with_soft_limit(self, bytes: usize) -> Self {
+        self.head.get_store().set_soft_limit(bytes);
+        self
+    }
+
+    /// Returns whether [`Heap::used`] has grown past the soft limit. This is
+    /// cheap enough to poll after every allocation.
+    pub fn over_soft_limit(&self) -> bool {
+        self.used() > self.head.get_store().get_soft_limit()
+    }
+
+    /// # Safety
+    ///
+    /// The returned memory is uninitialized and only remains valid until a
+    /// sweep is performed in which the object was not marked.
     pub unsafe fn alloc(&self, layout: Layout) -> Result<*mut u8, AllocError> {
         let ptr = self.head.alloc(layout)?;
 
         Ok(ptr as *mut u8)
     }
 
-    pub unsafe fn sweep(&self, mark: NonZero<u8>, cb: impl FnOnce()) {
-        self.head.sweep(mark.into(), cb);
+    /// Allocates room for a `T`, with the size and alignment of `T`. Zero sized
+    /// types are rejected as zero sized layouts are by [`Heap::alloc`].
+    ///
+    /// # Safety
+    ///
+    /// The returned memory is uninitialized and only remains valid until a
+    /// sweep is performed in which the object was not marked, with the layout
+    /// of `T`.
+    pub unsafe fn alloc_value<T>(&self) -> Result<*mut T, AllocError> {
+        let ptr = self.alloc(Layout::new::<T>())?;
+
+        Ok(ptr as *mut T)
+    }
+
+    /// Allocates room for `len` consecutive values of `T`, returning a pointer
+    /// to the first. Fails with [`AllocError::LayoutError`] if the array's size
+    /// overflows.
+    ///
+    /// # Safety
+    ///
+    /// The returned memory is uninitialized and only remains valid until a
+    /// sweep is performed in which the slice was not marked, with the layout of
+    /// `[T; len]`.
+    pub unsafe fn alloc_slice<T>(&self, len: usize) -> Result<*mut T, AllocError> {
+        let ptr = self.alloc(Layout::array::<T>(len)?)?;
+
+        Ok(ptr as *mut T)
+    }
+
+    /// Allocates like [`Heap::alloc`], but the returned memory is zeroed.
+    ///
+    /// # Safety
+    ///
+    /// The returned memory only remains valid until a sweep is performed in
+    /// which the object was not marked.
+    pub unsafe fn alloc_zeroed(&self, layout: Layout) -> Result<*mut u8, AllocError> {
+        let ptr = self.head.alloc_zeroed(layout)?;
+
+        Ok(ptr as *mut u8)
+    }
+
+    /// Frees the large object at `ptr` right away, returning its memory to the
+    /// system allocator without waiting for a sweep. Returns
+    /// [`AllocError::UnknownObject`] if `ptr` is not the start of a large object
+    /// of this heap, small and medium objects can only be reclaimed by a sweep.
+    ///
+    /// # Safety
+    ///
+    /// The object must not be used after it is freed.
+    pub unsafe fn free_large(&self, ptr: *const u8) -> Result<(), AllocError> {
+        self.head.get_store().free_large(ptr)
+    }
+
+    /// Checks whether `layout` could ever be allocated, without allocating.
+    /// Returns the size class the allocation would be served from, or the
+    /// error [`Heap::alloc`] would fail with regardless of how much memory is
+    /// available.
+    pub fn can_allocate(&self, layout: Layout) -> Result<SizeClass, AllocError> {
+        self.head.geometry().size_class(layout)
+    }
+
+    /// Allocates `size` bytes with no alignment at all, placing the object
+    /// right where the previous one ended. This packs byte strings and other
+    /// blobs as densely as possible and skips the alignment math of
+    /// [`Heap::alloc`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::alloc`].
+    pub unsafe fn alloc_packed(&self, size: usize) -> Result<*mut u8, AllocError> {
+        let ptr = self.head.alloc_packed(size)?;
+
+        Ok(ptr as *mut u8)
+    }
+
+    /// Allocates a line aligned run of `lines` lines, for embedders that carve
+    /// up memory themselves. The slab is one object as far as the heap is
+    /// concerned, it must be marked with [`Heap::mark_slab`] to survive a sweep
+    /// and is reclaimed as a whole when it isn't.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::alloc`].
+    pub unsafe fn alloc_slab(&self, lines: usize) -> Result<*mut u8, AllocError> {
+        self.alloc(Self::slab_layout(lines, self.head.geometry().line_size())?)
+    }
+
+    fn slab_layout(lines: usize, line_size: usize) -> Result<Layout, AllocError> {
+        let size = lines.checked_mul(line_size).ok_or(AllocError::AllocOverflow)?;
+
+        Ok(Layout::from_size_align(size, line_size)?)
+    }
+
+    /// Moves an object to a new allocation of `new_size` bytes with the same
+    /// alignment, copying over as much of its contents as fits, and returns
+    /// the new address. A medium object that no longer fits in the block
+    /// being allocated into is placed in a recycled block with a large enough
+    /// hole before a fresh block is used. The old object is freed once it is
+    /// copied: its lines are cleared, so the next sweep reclaims them even if
+    /// the object was marked, such as with the birth color. Only lines it
+    /// shares with neighbouring objects keep their marks. An old large object
+    /// is released right away.
+    ///
+    /// An object that shrinks without leaving its size class stays where it
+    /// is and `ptr` is returned. Growing, or moving to another size class,
+    /// always relocates the object.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an object allocated by this heap with `old_layout`,
+    /// and no sweep may run between the allocation of the object and this
+    /// call unless it was marked. Unless `ptr` is returned, the old object must
+    /// not be used afterwards. The returned memory is subject to the same
+    /// rules as [`Heap::alloc`], it must be marked using its new size.
+    pub unsafe fn realloc(&self, ptr: *const u8, old_layout: Layout, new_size: usize) -> Result<*mut u8, AllocError> {
+        let ptr = self.head.realloc(ptr, old_layout, new_size)?;
+
+        Ok(ptr as *mut u8)
+    }
+
+    /// Allocates an object along with an id that is unique for the lifetime of
+    /// the heap, suitable as a key for weak tables. Unlike the address, the id
+    /// is never reused, even once the object dies and its memory is handed out
+    /// again.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::alloc`].
+    pub unsafe fn alloc_with_id(&self, layout: Layout) -> Result<(*mut u8, u64), AllocError> {
+        let ptr = self.alloc(layout)?;
+        let id = self.head.get_store().assign_id(ptr);
+
+        Ok((ptr, id))
+    }
+
+    /// Returns the id of an object allocated with [`Heap::alloc_with_id`], or
+    /// `None` if it has been swept.
+    pub fn object_id(&self, ptr: *const u8) -> Option<u64> {
+        self.head.get_store().get_id(ptr)
+    }
+
+    /// Allocates `size` bytes aligned to a cache line and padded out to a whole
+    /// number of cache lines, so that the object never shares a cache line with
+    /// a neighbouring allocation.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::alloc`].
+    pub unsafe fn alloc_cache_aligned(&self, size: usize) -> Result<*mut u8, AllocError> {
+        let padded_size = size
+            .checked_next_multiple_of(CACHE_LINE_SIZE)
+            .ok_or(AllocError::AllocOverflow)?;
+        let layout = Layout::from_size_align(padded_size, CACHE_LINE_SIZE)?;
+
+        self.alloc(layout)
+    }
+
+    /// Allocates an object made of a `header` followed by `n` elements laid out
+    /// as an array, as used by strings or vectors that store their data inline.
+    /// The elements start at `header.size()` rounded up to the alignment of
+    /// `elem`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::alloc`].
+    pub unsafe fn alloc_with_trailing(&self, header: Layout, elem: Layout, n: usize) -> Result<*mut u8, AllocError> {
+        let array_size = elem
+            .pad_to_align()
+            .size()
+            .checked_mul(n)
+            .ok_or(AllocError::AllocOverflow)?;
+        let array = Layout::from_size_align(array_size, elem.align())?;
+        let (layout, _) = header.extend(array)?;
+
+        self.alloc(layout.pad_to_align())
+    }
+
+    /// Allocates an object preceded by a header, as used by collectors that keep
+    /// a type or size word in front of every object. Returns the header and body
+    /// pointers, the header ends exactly where the body begins. Any padding
+    /// needed to align the body goes in front of the header.
+    ///
+    /// Objects allocated this way must be marked with
+    /// [`Heap::mark_with_header`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::alloc`].
+    pub unsafe fn alloc_with_header(&self, header: Layout, body: Layout) -> Result<(*mut u8, *mut u8), AllocError> {
+        let (layout, body_offset) = Self::header_layout(header, body)?;
+        let base = self.alloc(layout)?;
+        let body_ptr = base.add(body_offset);
+
+        Ok((body_ptr.sub(header.pad_to_align().size()), body_ptr))
+    }
+
+    // The combined layout of a header and body, along with the offset of the
+    // body within it. The body is aligned for both so that the header placed
+    // right before it is aligned as well.
+    fn header_layout(header: Layout, body: Layout) -> Result<(Layout, usize), AllocError> {
+        let align = header.align().max(body.align());
+        let body_offset = header
+            .pad_to_align()
+            .size()
+            .checked_next_multiple_of(align)
+            .ok_or(AllocError::AllocOverflow)?;
+        let size = body_offset
+            .checked_add(body.size())
+            .ok_or(AllocError::AllocOverflow)?;
+
+        Ok((Layout::from_size_align(size, align)?, body_offset))
+    }
+
+    /// Reclaims every object that was not marked with `mark`, returning what
+    /// was reclaimed.
+    ///
+    /// `cb` is called once, after the heap's blocks are locked and before any
+    /// object is reclaimed. Handles on other threads that need a new block wait
+    /// until the sweep is over, which makes it the place for a runtime to flip
+    /// the mark it considers live.
+    ///
+    /// # Safety
+    ///
+    /// Every object that is still in use must have been marked with `mark`, any
+    /// object that was not will be reclaimed.
+    pub unsafe fn sweep(&self, mark: NonZero<u8>, cb: impl FnOnce()) -> SweepStats {
+        self.head.sweep(mark, cb).0
+    }
+
+    /// Sweeps like [`Heap::sweep`] on a thread of its own, returning a handle
+    /// that yields what was reclaimed.
+    ///
+    /// The block lists are only locked while the sweep takes the blocks off
+    /// them and while it hands back those that survived, so clones of this heap
+    /// can keep allocating on other threads in the meantime. Blocks they start
+    /// allocating in during the sweep aren't swept by it, and neither are the
+    /// objects allocated in them. Other sweeps wait for this one to finish, or
+    /// sweep its blocks themselves if they get to the heap before its thread.
+    ///
+    /// # Safety
+    ///
+    /// Every object that was in use when this is called must have been marked
+    /// with `mark`. No object may be marked until the sweep has finished, and
+    /// objects allocated before the call and not marked must no longer be used.
+    pub unsafe fn sweep_in_background(&self, mark: NonZero<u8>) -> JoinHandle<SweepStats> {
+        let store = self.head.get_store_arc();
+
+        // the blocks are taken before returning, so none handed back after
+        // this call is swept
+        store.begin_concurrent_sweep(mark);
+
+        std::thread::spawn(move || store.finish_concurrent_sweep(mark))
+    }
+
+    /// Sweeps like [`Heap::sweep`], but only up to `block_budget` blocks at a
+    /// time, so the pauses stay short on large heaps. The first call starts a
+    /// cycle, which later calls carry on with until [`SweepProgress::Done`] is
+    /// returned. Allocation may go on between the calls, the blocks it takes
+    /// up are left to the next cycle.
+    ///
+    /// Large objects are swept all at once when the cycle starts. Calling
+    /// another sweep, or this one with a different mark, gives up on the cycle
+    /// in progress.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::sweep`], objects allocated while the cycle is in
+    /// progress are the exception.
+    pub unsafe fn sweep_incremental(&self, mark: NonZero<u8>, block_budget: usize) -> SweepProgress {
+        self.head.get_store().sweep_incremental(mark, block_budget)
+    }
+
+    /// Gives up on the cycle started by [`Heap::sweep_incremental`], returning
+    /// whether one was in progress. The blocks the cycle had yet to get to are
+    /// handed back as they were, so allocation carries on as usual, while what
+    /// it already reclaimed stays reclaimed. The next sweep starts afresh.
+    pub fn cancel_sweep(&self) -> bool {
+        self.head.get_store().cancel_incremental()
+    }
+
+    /// Sweeps like [`Heap::sweep`], going by the marks of the `live` color
+    /// alone. The marks of the other color are cleared, as the sweep reclaims
+    /// whatever they kept alive, so that color can be used to mark the next
+    /// cycle.
+    ///
+    /// # Safety
+    ///
+    /// Every object that is still in use must have been marked with `mark` in
+    /// the `live` color.
+    #[cfg(feature = "dual-mark")]
+    pub unsafe fn sweep_color(&self, live: Color, mark: NonZero<u8>, cb: impl FnOnce()) -> SweepStats {
+        self.head.get_store().sweep_color(live, mark, cb).0
+    }
+
+    /// Sweeps like [`Heap::sweep`], after moving the live objects out of
+    /// sparsely marked blocks so the sweep can free those blocks. `relocate` is
+    /// called with the old and new address of every object moved, for the
+    /// embedder to update its references. Ids, finalizers, weak handles and
+    /// immortality follow the object on their own.
+    ///
+    /// Only objects marked with `mark` through [`Heap::mark_if_unmarked`] are
+    /// moved, as only their layouts are known. A block is evacuated when those
+    /// objects account for every line marked in it, nothing in it was marked
+    /// with `mark` any other way, such as with [`Heap::mark`], a birth color or
+    /// [`Heap::scan_conservative`], and no heap handle is allocating in it.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::sweep`]. In addition, every reference to a moved object
+    /// must be updated before it is used again.
+    pub unsafe fn sweep_evacuating(
+        &self,
+        mark: NonZero<u8>,
+        cb: impl FnOnce(),
+        relocate: impl FnMut(*const u8, *const u8),
+    ) -> SweepStats {
+        self.head.get_store().sweep_evacuating(mark, cb, relocate).0
+    }
+
+    /// Sweeps like [`Heap::sweep`], but also retains anything `keep` returns true
+    /// for, whether or not it was marked, for embedders with liveness
+    /// information of their own such as reference counts. `keep` is asked
+    /// about the address of every unmarked large object and the base address
+    /// of every block. A kept block is left exactly as it is, none of its
+    /// lines are reclaimed.
+    ///
+    /// # Safety
+    ///
+    /// Every object that is still in use must have been marked with `mark`, or
+    /// be a large object or lie in a block that `keep` returns true for.
+    pub unsafe fn sweep_with_predicate(
+        &self,
+        mark: NonZero<u8>,
+        cb: impl FnOnce(),
+        keep: impl Fn(*const u8) -> bool,
+    ) -> SweepStats {
+        self.head.sweep_retaining(mark, cb, keep).0
+    }
+
+    /// Sweeps like [`Heap::sweep`], returning the base address and size of
+    /// every block that was moved to the free list. Nothing in those blocks is
+    /// in use, so the embedder may hand their pages back to the OS, e.g. with
+    /// `madvise(MADV_DONTNEED)`, as long as the memory reads back as zeroes.
+    /// Blocks released back to the system allocator are not reported.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::sweep`].
+    pub unsafe fn sweep_reporting_free(&self, mark: NonZero<u8>, cb: impl FnOnce()) -> Vec<(*const u8, usize)> {
+        self.head
+            .sweep(mark, cb)
+            .1
+            .into_iter()
+            .map(|block| (block.as_ptr(), BLOCK_SIZE))
+            .collect()
+    }
+
+    /// Sweeps like [`Heap::sweep`], returning the address and size of every
+    /// object that was allocated through [`Heap::alloc`] or one of its variants
+    /// and is reclaimed by this sweep. A test harness can check these against
+    /// its own idea of what is live to catch objects it forgot to mark. Only
+    /// available with the `track-allocations` feature, which records every
+    /// allocation for this.
+    ///
+    /// An unmarked object is not reported while it shares a marked line with
+    /// another object, since its memory is not reclaimed until the line is.
+    /// Neither are objects in blocks held by heap handles for allocation, this
+    /// one's included, as the sweep doesn't visit those blocks. They are
+    /// reported by the first sweep after their block is handed back.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::sweep`].
+    #[cfg(feature = "track-allocations")]
+    pub unsafe fn sweep_reporting_unmarked(&self, mark: NonZero<u8>, cb: impl FnOnce()) -> Vec<(*const u8, usize)> {
+        self.head.sweep(mark, cb);
+        self.head.get_store().take_unmarked()
+    }
+
+    /// Returns the mark the next collection should use. Each sweep advances it
+    /// to the mark following the one that was swept with, wrapping around and
+    /// skipping over the mark reserved for free lines.
+    pub fn current_mark(&self) -> NonZero<u8> {
+        self.head.get_store().current_mark()
+    }
+
+    /// Overrides the mark returned by [`Heap::current_mark`].
+    pub fn set_live_mark(&self, mark: NonZero<u8>) {
+        self.head.get_store().set_live_mark(mark);
     }
 
     pub fn size(&self) -> usize {
         self.head.get_size()
     }
 
-    pub unsafe fn mark(ptr: *mut u8, layout: Layout, mark: NonZero<u8>) -> Result<(), AllocError> {
-        let size_class =  SizeClass::get_for_size(layout.size())?;
+    /// Reports how many blocks the heap owns and where they are, along with
+    /// the number and size of its large objects. Blocks held by heap handles
+    /// for allocation count towards `block_count` but not towards any list.
+    pub fn stats(&self) -> HeapStats {
+        self.head.get_store().stats()
+    }
 
-        if size_class != SizeClass::Large {
-            let meta = BlockMeta::from_ptr(ptr);
+    /// Reports how often this handle's small and medium allocations were served
+    /// from the block it already held, versus having to fetch another block.
+    /// A high share of refreshes points at fragmented blocks. The counts are
+    /// kept per handle, clones of the heap start from zero.
+    pub fn fast_path_stats(&self) -> FastPathStats {
+        self.head.get_stats()
+    }
+
+    /// Returns the bytes found live by the last sweep: the marked lines of the
+    /// swept blocks along with the surviving large objects. Unlike
+    /// [`Heap::used`] this doesn't grow with allocations made since, so it
+    /// reflects what the last collection kept. It is zero until the first sweep.
+    pub fn live_bytes(&self) -> usize {
+        self.head.get_store().get_live_bytes()
+    }
 
-            meta.mark(ptr, layout.size() as u32, size_class, mark.into());
+    /// Returns how fragmented the last sweep left the heap, between 0 and 1:
+    /// the share of the free lines it found that are scattered among blocks
+    /// still holding live objects, rather than making up blocks it freed
+    /// entirely. A high value suggests an evacuating sweep would pay off. It
+    /// is zero until the first sweep.
+    pub fn fragmentation(&self) -> f32 {
+        self.head.get_store().fragmentation()
+    }
+
+    /// Reports how many small, medium and large objects have been allocated in
+    /// the heap, through any of its handles. Unlike the other statistics these
+    /// are never reset, sweeps leave them alone.
+    pub fn alloc_counts(&self) -> AllocCounts {
+        self.head.get_store().alloc_counts()
+    }
+
+    /// Returns the bytes reserved by the heap, including blocks that are
+    /// currently free and dead large objects kept for reuse. This is the same
+    /// value as [`Heap::size`].
+    pub fn capacity(&self) -> usize {
+        self.head.get_size()
+    }
+
+    /// Returns the bytes handed out by the heap. Between sweeps this is the sum
+    /// of the requested sizes, after a sweep it is recomputed from the surviving
+    /// lines and large objects, so it is only as precise as a line.
+    ///
+    /// Allocations made through other clones of this heap are only counted
+    /// once that clone hands its current block back to the heap.
+    pub fn used(&self) -> usize {
+        self.head.get_used()
+    }
+
+    /// Marks an object like [`Heap::mark`], returning `true` if this call marked
+    /// it and `false` if it was already marked with `mark`, so a tracer can
+    /// skip objects it has already visited this cycle. Only marks made through
+    /// this method are taken into account for small and medium objects, since
+    /// a marked line doesn't say which of the objects in it were marked.
+    ///
+    /// The objects are recorded for [`Heap::sweep_evacuating`], in tables picked
+    /// by the block an object lies in, so threads tracing different blocks
+    /// seldom wait on each other. Objects marked with [`Heap::mark`] instead
+    /// are not recorded, and flag their block so that it isn't evacuated,
+    /// even when the recorded objects cover every marked line in it. A tracer
+    /// should stick to this method when it means to evacuate.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an object allocated by this heap with the given
+    /// layout.
+    pub unsafe fn mark_if_unmarked(&self, ptr: *mut u8, layout: Layout, mark: NonZero<u8>) -> Result<bool, AllocError> {
+        self.head.get_store().mark_if_unmarked(ptr, layout, mark)
+    }
+
+    /// Makes an object permanent: every following sweep retains it, whatever
+    /// mark is swept with, so it never needs to be marked again. Useful for
+    /// interned symbols and other objects that live as long as the runtime.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an object allocated by this heap with the given
+    /// layout, which must not have been swept yet.
+    pub unsafe fn immortalize(&self, ptr: *const u8, layout: Layout) -> Result<(), AllocError> {
+        self.head.get_store().immortalize(ptr, layout)
+    }
+
+    /// Registers `finalizer` to be called, with `ptr`, by the first sweep that
+    /// finds the object unmarked. It runs before the object's memory can be
+    /// reused and only ever once, a sweep that retains the object keeps the
+    /// finalizer for a later sweep. Registering another finalizer for the same
+    /// object replaces the first. Freeing a large object with
+    /// [`Heap::free_large`] drops its finalizer without running it.
+    ///
+    /// Finalizers run once the sweep has unlocked the heap's blocks, so they
+    /// may allocate from this heap, through a handle of their own, but must
+    /// not sweep it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to the start of an object allocated by this heap.
+    pub unsafe fn register_finalizer(&self, ptr: *const u8, finalizer: Box<dyn FnOnce(*const u8) + Send>) {
+        self.head.get_store().register_finalizer(ptr, finalizer);
+    }
 
-            Ok(())
-        } else {
-            LargeBlock::mark(ptr, layout, mark.into())
+    /// Returns a weak handle to the object at `ptr`, which is cleared by the
+    /// first sweep that finds the object unmarked, without keeping it alive.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to the start of an object allocated by this heap.
+    pub unsafe fn register_weak(&self, ptr: *const u8) -> WeakHandle {
+        self.head.get_store().register_weak(ptr)
+    }
+
+    /// Pins the object at `ptr`, so evacuating sweeps leave it, and the rest
+    /// of its block, where it is. Useful for objects whose address was handed
+    /// to foreign code. Pinning doesn't keep the object alive, the pin is
+    /// dropped once the object is swept.
+    ///
+    /// Returns [`AllocError::UnknownObject`] if `ptr` is not in this heap.
+    pub fn pin(&self, ptr: *const u8) -> Result<(), AllocError> {
+        self.head.get_store().pin(ptr)
+    }
+
+    /// Undoes [`Heap::pin`], returning [`AllocError::UnknownObject`] if the
+    /// object isn't pinned.
+    pub fn unpin(&self, ptr: *const u8) -> Result<(), AllocError> {
+        self.head.get_store().unpin(ptr)
+    }
+
+    /// Returns whether `ptr` points into the data region of one of this heap's
+    /// blocks. Large objects are not considered.
+    pub fn owns(&self, ptr: *const u8) -> bool {
+        self.head.get_store().find_block(ptr as usize).is_some()
+    }
+
+    /// Returns whether `ptr` points into one of this heap's blocks or large
+    /// objects, for conservative scanning. Interior pointers count.
+    ///
+    /// The answer reflects the heap at the time of the call, and may be stale
+    /// by the time it is returned if other handles allocate or sweep
+    /// concurrently. Blocks stay in the heap while free, so a pointer into
+    /// one is reported as contained even when nothing lives there, whereas
+    /// the memory of a swept large object no longer is.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let store = self.head.get_store();
+        let addr = ptr as usize;
+
+        store.find_block(addr).is_some() || store.find_large(addr).is_some()
+    }
+
+    /// Returns the size of the allocation `ptr` points into, for runtimes that
+    /// don't record the length of their objects.
+    ///
+    /// Large objects are reported with their exact size. Small and medium
+    /// objects are only known by their line marks, so the object must be
+    /// marked, and the size reported is that of the run of lines around `ptr`
+    /// marked with the same mark. That is the object's line span, rounded out
+    /// to whole lines, and takes in marked neighbours as well. `None` is
+    /// returned for unmarked objects and pointers outside of the heap.
+    pub fn allocation_size(&self, ptr: *const u8) -> Option<usize> {
+        self.head.get_store().allocation_size(ptr as usize)
+    }
+
+    /// Calls `f` with the start and length of every live part of the heap,
+    /// for debuggers and heap dumps. Small and medium objects are only known
+    /// to be live by their line marks, so each run of consecutive lines marked
+    /// with `mark` is reported as one range. A range may hold several objects,
+    /// or cover part of an object that spilled over from an unmarked line,
+    /// and is always a whole number of lines long. Large objects marked with
+    /// `mark` are reported one by one, with their exact size.
+    ///
+    /// This handle's allocation blocks are returned before walking the heap,
+    /// blocks held by other clones of the heap are not visited. `f` is called
+    /// after the walk, so it is free to allocate from the heap.
+    pub fn for_each_live(&self, mark: NonZero<u8>, mut f: impl FnMut(*const u8, usize)) {
+        self.head.flush();
+
+        for (ptr, len) in self.head.get_store().live_ranges(mark) {
+            f(ptr, len);
+        }
+    }
+
+    /// Checks that no two live objects overlap, returning the first two found
+    /// to on failure. This walks every live object and is meant for tests and
+    /// debug builds.
+    ///
+    /// Objects whose extent the heap knows are checked one by one, so objects
+    /// placed on top of one another within a marked line are caught as well.
+    /// Those are the objects marked with `mark` through
+    /// [`Heap::mark_if_unmarked`], immortal objects and large objects marked
+    /// with `mark`, and with the `track-allocations` feature every allocation
+    /// whose line is marked with `mark`. Small and medium objects marked with
+    /// [`Heap::mark`] are only known by their line marks, so each run of lines
+    /// marked with `mark` is checked as one range instead, against the large
+    /// objects and the objects whose extent is known. Two such objects sharing
+    /// a run of lines are not told apart.
+    ///
+    /// This handle's allocation blocks are returned before checking, blocks
+    /// held by other clones of the heap are not inspected.
+    pub fn verify_no_overlap(&self, mark: NonZero<u8>) -> Result<(), OverlapError> {
+        self.head.flush();
+        self.head.get_store().verify_no_overlap(mark)
+    }
+
+    /// Returns how many sweeps the object at `ptr` has survived, or `None` if
+    /// `ptr` doesn't point into this heap. Small and medium objects share the
+    /// age of their block, which counts the sweeps in which anything in the
+    /// block was marked, so it can be older than the object itself. Large
+    /// objects have an age of their own. Ages saturate at `u8::MAX`.
+    pub fn block_age_of(&self, ptr: *const u8) -> Option<u8> {
+        self.head.get_store().age_of(ptr as usize)
+    }
+
+    /// Returns a snapshot of the metadata of the block holding `ptr`, or `None`
+    /// if `ptr` doesn't point into the data of one of this heap's blocks, as
+    /// is the case for large objects.
+    pub fn block_for(&self, ptr: *const u8) -> Option<BlockHandle> {
+        let block = self.head.get_store().find_block(ptr as usize)?;
+
+        unsafe { BlockHandle::new(block as *const u8) }.ok()
+    }
+
+    /// Treats each word as a potential pointer into the heap, as a conservative
+    /// root scanner would. Any word pointing into the data of a block or large
+    /// object owned by this heap marks the object it points into, all other
+    /// words are ignored. So are words pointing into a free block, as nothing
+    /// in it is allocated.
+    ///
+    /// A word pointing into a medium object, interior pointers included, keeps
+    /// every line of the object alive. A word pointing into a small object
+    /// keeps its line alive along with the one before it, which the object may
+    /// start in.
+    ///
+    /// The heap's block lists stay locked for the whole scan, so no block is
+    /// released while it runs. Handles that need another block to allocate
+    /// into, as well as sweeps and [`Heap::on_memory_pressure`], wait for it.
+    pub fn scan_conservative(&self, words: &[usize], mark: NonZero<u8>) {
+        self.head.get_store().scan_conservative(words, mark);
+    }
+
+    // identifies the heap shared by every clone of this handle
+    pub(crate) fn store_id(&self) -> usize {
+        self.head.get_store() as *const BlockStore<BLOCK_SIZE> as usize
+    }
+
+    /// Tears the heap down, reclaiming every object as [`Heap::reset`] does
+    /// and releasing every block and large object the heap holds, reserve
+    /// included. This only succeeds when this is the last handle allocating
+    /// from the heap, otherwise blocks later handed back by the remaining
+    /// handles would go to a heap nobody sweeps anymore.
+    ///
+    /// Clones of the heap, [`AllocatorCache`] entries, `NimixAlloc`s and
+    /// pointers made by [`Heap::into_raw`] all count as handles. This thread's
+    /// [`AllocatorCache`] entry for the heap is dropped first, handing its
+    /// blocks back. A sweep started by [`Heap::sweep_in_background`] doesn't
+    /// count; it is given up on, or waited for if its thread is already
+    /// sweeping, and reclaims nothing.
+    ///
+    /// Returns the number of other handles still alive if there are any, in
+    /// which case this handle is dropped but the heap lives on untouched.
+    pub fn shutdown(self) -> Result<(), usize> {
+        AllocatorCache::forget(self.store_id());
+
+        let others = self.head.handle_count() - 1;
+
+        if others > 0 {
+            return Err(others);
         }
+
+        self.head.flush();
+        self.head.get_store().shutdown();
+
+        Ok(())
+    }
+
+    /// Sheds cached capacity in response to memory pressure, returning the
+    /// number of bytes released. Every free block beyond the reserve set by
+    /// [`Heap::with_min_block_count`] is released, along with any block that
+    /// has been handed back without anything being allocated in it and the
+    /// dead large objects kept for reuse by later allocations of their size.
+    /// No sweep is performed and nothing is allocated while doing so.
+    pub fn on_memory_pressure(&self) -> usize {
+        self.head.get_store().on_memory_pressure()
+    }
+
+    /// Reclaims every object in the heap at once, as a sweep in which nothing
+    /// was marked would, without going through the marks. Finalizers are run,
+    /// weak handles are cleared and large objects are released. The blocks are
+    /// kept for reuse, up to the number of free blocks a sweep keeps, and the
+    /// rest are released.
+    ///
+    /// This handle's blocks are reset too, while those held by other handles to
+    /// the heap are left alone, as with [`Heap::sweep`].
+    ///
+    /// # Safety
+    ///
+    /// No object allocated in the heap may be used afterwards, outside of the
+    /// blocks held by other handles.
+    pub unsafe fn reset(&self) {
+        self.head.flush();
+        self.head.get_store().reset();
+    }
+
+    /// Releases the free blocks kept around for later allocations, returning
+    /// the number of bytes released. Only blocks that are already free are
+    /// touched, no marks are looked at, and the reserve set by
+    /// [`Heap::with_min_block_count`] is kept.
+    pub fn trim(&self) -> usize {
+        self.head.get_store().trim()
+    }
+
+    /// Reports the fraction of lines marked with `mark` for every block that
+    /// isn't free, sorted so that the least utilized blocks, the best
+    /// candidates for evacuation, come first. Blocks currently held by a heap
+    /// handle for allocation are not included.
+    pub fn utilization(&self, mark: NonZero<u8>) -> Vec<(BlockId, f32)> {
+        self.head.get_store().utilization(mark)
+    }
+
+    /// Yields the base address of every block whose card was marked since the
+    /// last call, cleaning the cards as they are collected. Blocks currently
+    /// held by a heap handle for allocation are skipped, their cards remain
+    /// dirty until the block is handed back.
+    #[cfg(feature = "card-marks")]
+    pub fn dirty_blocks(&self) -> impl Iterator<Item = *const u8> {
+        self.head.get_store().dirty_blocks().into_iter()
+    }
+
+    /// Yields a reference to every object of a homogeneous heap that lies within
+    /// a line marked with `mark`.
+    ///
+    /// Because liveness is tracked per line this also yields dead objects, and
+    /// never allocated zeroed slots, that share a line with a live object. See
+    /// [`Heap::new_homogeneous`] for the requirements this places on `T`.
+    ///
+    /// Only blocks that have been handed back to the heap are walked. This
+    /// handle's own allocation blocks are returned before walking, but blocks
+    /// held by other clones of the heap are not visited.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the heap was not created by `Heap::new_homogeneous::<T>`.
+    pub fn iter_live_as<'a, T: 'a>(&'a self, mark: NonZero<u8>) -> impl Iterator<Item = &'a T> {
+        let store = self.head.get_store();
+        let mut slots = vec![];
+
+        assert_eq!(
+            store.get_homogeneous_layout(),
+            Some(Layout::new::<T>()),
+            "heap is not homogeneous over this type"
+        );
+
+        self.head.flush();
+        store.for_each_live_slot(mark, |slot| slots.push(slot as *const T));
+
+        slots.into_iter().map(|slot| unsafe { &*slot })
+    }
+
+    /// Consumes a homogeneous heap, yielding the address and size of every
+    /// object that lies within a line marked with `mark`, so they can be copied
+    /// into a fresh heap. The heap, and with it every block, is released once
+    /// the iterator is dropped, unless other clones of it are still alive.
+    ///
+    /// As with [`Heap::iter_live_as`], dead objects and never allocated slots
+    /// sharing a line with a live object are yielded too, and blocks held by
+    /// other clones of the heap are not visited.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the heap was not created by [`Heap::new_homogeneous`].
+    pub fn into_live_iter(self, mark: NonZero<u8>) -> IntoLiveIter<BLOCK_SIZE> {
+        let store = self.head.get_store();
+        let layout = store
+            .get_homogeneous_layout()
+            .expect("heap is not homogeneous");
+        let mut slots = vec![];
+
+        self.head.flush();
+        store.for_each_live_slot(mark, |slot| slots.push(slot));
+
+        IntoLiveIter {
+            slots: slots.into_iter(),
+            size: layout.size(),
+            _heap: self,
+        }
+    }
+}
+
+/// The iterator returned by [`Heap::into_live_iter`].
+pub struct IntoLiveIter<const BLOCK_SIZE: usize = { constants::BLOCK_SIZE }> {
+    slots: std::vec::IntoIter<*const u8>,
+    size: usize,
+    // keeps the objects alive until the iterator is dropped
+    _heap: Heap<BLOCK_SIZE>,
+}
+
+impl<const BLOCK_SIZE: usize> Iterator for IntoLiveIter<BLOCK_SIZE> {
+    type Item = (*const u8, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.slots.next().map(|slot| (slot, self.size))
     }
 }
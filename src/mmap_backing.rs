@@ -0,0 +1,75 @@
+use super::backing::Backing;
+use std::alloc::Layout;
+use std::ptr;
+
+/// A [`Backing`] that maps every block and large object straight from the OS,
+/// and hands the pages back as soon as the heap releases them, instead of
+/// leaving them cached by the system allocator. Keeps the resident size of a
+/// process close to what its heaps hold after a spike.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MmapBacking;
+
+impl MmapBacking {
+    fn page_size() -> usize {
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+    }
+
+    // The length actually mapped for `layout`, a whole number of pages.
+    fn mapped_len(layout: Layout) -> usize {
+        layout.size().next_multiple_of(Self::page_size())
+    }
+}
+
+impl Backing for MmapBacking {
+    // Maps enough to align the start, then unmaps the slack on either side.
+    fn alloc(&self, layout: Layout) -> *mut u8 {
+        let len = Self::mapped_len(layout);
+        let align = layout.align().max(Self::page_size());
+        let Some(padded) = len.checked_add(align - Self::page_size()) else {
+            return ptr::null_mut();
+        };
+
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                padded,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+
+        if base == libc::MAP_FAILED {
+            return ptr::null_mut();
+        }
+
+        let start = (base as usize).next_multiple_of(align);
+        let front = start - base as usize;
+        let back = padded - front - len;
+
+        unsafe {
+            if front > 0 {
+                libc::munmap(base, front);
+            }
+
+            if back > 0 {
+                libc::munmap((start + len) as *mut libc::c_void, back);
+            }
+        }
+
+        start as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let len = Self::mapped_len(layout);
+
+        libc::madvise(ptr as *mut libc::c_void, len, libc::MADV_DONTNEED);
+        libc::munmap(ptr as *mut libc::c_void, len);
+    }
+
+    // fresh mappings are always zeroed
+    fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc(layout)
+    }
+}
@@ -0,0 +1,73 @@
+use super::alloc_head::AllocHead;
+use super::size_class::SizeClass;
+use super::Heap;
+use std::alloc::{AllocError, Allocator, Layout};
+use std::ptr::{self, NonNull};
+
+/// An [`Allocator`] backed by a heap, so standard collections such as `Vec`
+/// and `Box` can be allocated from it.
+///
+/// Like a [`Heap`] clone, each `NimixAlloc` allocates through blocks of its
+/// own. Deallocating does nothing, the memory is reclaimed by a sweep of the
+/// heap in which the allocation was not marked. To keep a collection alive
+/// its buffer must be marked with [`Heap::mark`], using the layout it was
+/// allocated with, before every sweep.
+pub struct NimixAlloc {
+    head: AllocHead,
+}
+
+impl NimixAlloc {
+    pub fn new(heap: &Heap) -> Self {
+        Self {
+            head: AllocHead::new(heap.head.get_store_arc()),
+        }
+    }
+
+    // Moves the allocation, since a bump allocated object can't be resized
+    // in place.
+    unsafe fn move_to(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = self.allocate(new_layout)?;
+        let count = old_layout.size().min(new_layout.size());
+
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, count);
+
+        Ok(new_ptr)
+    }
+}
+
+unsafe impl Allocator for NimixAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            // any non null pointer aligned to the layout will do
+            let dangling = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+
+            return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+        }
+
+        let ptr = self.head.alloc(layout).map_err(|_| AllocError)?;
+        let ptr = NonNull::new(ptr as *mut u8).ok_or(AllocError)?;
+
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.move_to(ptr, old_layout, new_layout)
+    }
+
+    // Shrinking keeps the allocation in place when it stays in the same size
+    // class, as it must then be marked the same way, and is still aligned.
+    unsafe fn shrink(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let same_class = match (SizeClass::get_for_layout(old_layout), SizeClass::get_for_layout(new_layout)) {
+            (Ok(old), Ok(new)) => old == new,
+            _ => false,
+        };
+
+        if same_class && ptr.as_ptr() as usize % new_layout.align() == 0 {
+            return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+        }
+
+        self.move_to(ptr, old_layout, new_layout)
+    }
+}
@@ -0,0 +1,27 @@
+use super::block::BlockId;
+use std::alloc::Layout;
+use std::num::NonZero;
+
+/// Receives notifications of what a heap is doing, for instrumentation. Every
+/// method does nothing by default, so an observer only needs to implement the
+/// events it cares about.
+///
+/// Observers are called from whichever thread triggered the event, possibly
+/// while the heap holds internal locks, so they must not call back into the
+/// heap.
+pub trait HeapObserver: Send + Sync {
+    /// Called after each successful allocation.
+    fn on_alloc(&self, _ptr: *const u8, _layout: Layout) {}
+
+    /// Called when the heap requests a new block, rather than reusing one.
+    fn on_new_block(&self, _block: BlockId) {}
+
+    /// Called before a sweep with `mark` begins.
+    fn on_sweep_start(&self, _mark: NonZero<u8>) {}
+
+    /// Called when a sweep finds nothing live in a block.
+    fn on_block_freed(&self, _block: BlockId) {}
+
+    /// Called once a sweep with `mark` has finished.
+    fn on_sweep_end(&self, _mark: NonZero<u8>) {}
+}
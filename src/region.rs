@@ -0,0 +1,81 @@
+use super::block::Block;
+use super::constants;
+use super::error::AllocError;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// A caller provided span of memory that blocks are carved out of, front to back.
+pub struct Region<const BLOCK_SIZE: usize = { constants::BLOCK_SIZE }> {
+    cursor: AtomicUsize,
+    end: usize,
+}
+
+impl<const BLOCK_SIZE: usize> Region<BLOCK_SIZE> {
+    // SAFETY: base must be valid for reads and writes of len bytes for as long
+    // as the region and any block taken from it are alive
+    pub unsafe fn new(base: *mut u8, len: usize) -> Result<Self, AllocError> {
+        let start = (base as usize)
+            .checked_next_multiple_of(BLOCK_SIZE)
+            .ok_or(AllocError::OOM)?;
+        let end = (base as usize)
+            .checked_add(len)
+            .ok_or(AllocError::AllocOverflow)?;
+
+        if start.checked_add(BLOCK_SIZE).map_or(true, |block_end| block_end > end) {
+            return Err(AllocError::OOM);
+        }
+
+        Ok(Region {
+            cursor: AtomicUsize::new(start),
+            end,
+        })
+    }
+
+    pub fn take_block(&self) -> Result<Block<BLOCK_SIZE>, AllocError> {
+        let start = self
+            .cursor
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |cursor| {
+                if cursor + BLOCK_SIZE <= self.end {
+                    Some(cursor + BLOCK_SIZE)
+                } else {
+                    None
+                }
+            })
+            .map_err(|_| AllocError::OOM)?;
+
+        let ptr = NonNull::new(start as *mut u8).ok_or(AllocError::OOM)?;
+
+        Ok(unsafe { Block::borrowed(ptr) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::BLOCK_SIZE;
+
+    #[test]
+    fn region_too_small() {
+        let mut memory = vec![0u8; BLOCK_SIZE];
+        let region = unsafe { Region::<BLOCK_SIZE>::new(memory.as_mut_ptr(), BLOCK_SIZE - 1) };
+
+        assert!(region.is_err());
+    }
+
+    #[test]
+    fn take_aligned_blocks() {
+        let mut memory = vec![0u8; BLOCK_SIZE * 3];
+        let region = unsafe { Region::<BLOCK_SIZE>::new(memory.as_mut_ptr(), memory.len()).unwrap() };
+        let a = region.take_block().unwrap();
+        let b = region.take_block().unwrap();
+
+        assert_eq!(a.as_ptr() as usize % BLOCK_SIZE, 0);
+        assert_eq!(b.as_ptr() as usize - a.as_ptr() as usize, BLOCK_SIZE);
+        assert!(!a.is_owned());
+
+        // a third aligned block can only fit if the vec happened to be aligned
+        if memory.as_ptr() as usize % BLOCK_SIZE != 0 {
+            assert!(region.take_block().is_err());
+        }
+    }
+}
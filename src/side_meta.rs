@@ -0,0 +1,119 @@
+use super::directory::Directory;
+use super::error::AllocError;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::mem::size_of;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+// Metadata is found from the block's address without taking a lock, the slot
+// of each block points at its metadata, which goes with the block. The size
+// of the metadata depends on the geometry of the block, so each allocation
+// starts with its size, followed by the metadata itself.
+static DIRECTORY: Directory<AtomicPtr<usize>> = Directory::new();
+
+const HEADER: usize = size_of::<usize>();
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(HEADER + size, HEADER).unwrap()
+}
+
+// Returns the `size` bytes of metadata of the block at `block`, allocating
+// them if the block has none yet, or none of that size.
+pub fn insert(block: *const u8, size: usize) -> Result<*const u8, AllocError> {
+    let slot = DIRECTORY.get_or_insert(block)?;
+    let meta = slot.load(Ordering::Acquire);
+
+    if !meta.is_null() {
+        if unsafe { *meta } == size {
+            return Ok(unsafe { meta.add(1) } as *const u8);
+        }
+
+        // a block of another geometry was here before
+        remove(block);
+    }
+
+    // only the owner of the block inserts its metadata, so nothing races this.
+    // Like the directory's chunks, it comes from the system allocator.
+    let meta = unsafe { System.alloc_zeroed(layout(size)) } as *mut usize;
+
+    if meta.is_null() {
+        return Err(AllocError::OOM);
+    }
+
+    unsafe { meta.write(size) };
+    slot.store(meta, Ordering::Release);
+
+    Ok(unsafe { meta.add(1) } as *const u8)
+}
+
+pub fn get(block: *const u8) -> Result<*const u8, AllocError> {
+    let meta = DIRECTORY.get(block).map_or(ptr::null_mut(), |slot| slot.load(Ordering::Acquire));
+
+    if meta.is_null() {
+        return Err(AllocError::UnknownObject);
+    }
+
+    Ok(unsafe { meta.add(1) } as *const u8)
+}
+
+pub fn remove(block: *const u8) {
+    let Some(slot) = DIRECTORY.get(block) else {
+        return;
+    };
+    let meta = slot.swap(ptr::null_mut(), Ordering::AcqRel);
+
+    if !meta.is_null() {
+        unsafe { System.dealloc(meta as *mut u8, layout(*meta)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::block_meta::BlockMeta;
+    use crate::constants::BLOCK_SIZE;
+    use crate::geometry::Geometry;
+    use std::alloc::Layout;
+    use std::ptr::NonNull;
+
+    #[test]
+    fn blocks_without_metadata_are_reported() {
+        let block = Block::default().unwrap();
+
+        assert!(matches!(get(block.as_ptr()), Err(AllocError::UnknownObject)));
+
+        let meta = insert(block.as_ptr(), Geometry::DEFAULT.meta_size()).unwrap();
+
+        assert_eq!(get(block.as_ptr()).unwrap(), meta);
+        assert_eq!(insert(block.as_ptr(), Geometry::DEFAULT.meta_size()).unwrap(), meta);
+    }
+
+    #[test]
+    fn borrowed_blocks_drop_their_metadata() {
+        let layout = Layout::from_size_align(BLOCK_SIZE, BLOCK_SIZE).unwrap();
+        let memory = NonNull::new(unsafe { std::alloc::alloc(layout) }).unwrap();
+        let block = unsafe { Block::<BLOCK_SIZE>::borrowed(memory) };
+
+        BlockMeta::new(&block, Geometry::DEFAULT).unwrap();
+        assert!(get(memory.as_ptr()).is_ok());
+
+        // the memory is still ours, so no other block can have taken its place
+        drop(block);
+        assert!(matches!(get(memory.as_ptr()), Err(AllocError::UnknownObject)));
+
+        unsafe { std::alloc::dealloc(memory.as_ptr(), layout) };
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn addresses_past_the_directory_are_rejected() {
+        let block = (usize::MAX & !(BLOCK_SIZE - 1)) as *const u8;
+
+        assert!(matches!(
+            insert(block, Geometry::DEFAULT.meta_size()),
+            Err(AllocError::OOM)
+        ));
+        assert!(matches!(get(block), Err(AllocError::UnknownObject)));
+    }
+}
@@ -1,6 +1,9 @@
-use super::constants;
 use super::error::AllocError;
+use super::geometry::Geometry;
+use std::alloc::Layout;
 
+/// How an allocation is served: small objects fit within a line, medium
+/// objects within a block, and large objects get an allocation of their own.
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum SizeClass {
@@ -10,12 +13,23 @@ pub enum SizeClass {
 }
 
 impl SizeClass {
+    /// Classifies an object by its size alone, as a heap with the default
+    /// block and line sizes would, failing with [`AllocError::AllocOverflow`]
+    /// for sizes the heap can't allocate.
     pub fn get_for_size(object_size: usize) -> Result<SizeClass, AllocError> {
-        match object_size {
-            constants::SMALL_OBJECT_MIN..=constants::SMALL_OBJECT_MAX => Ok(SizeClass::Small),
-            constants::MEDIUM_OBJECT_MIN..=constants::MEDIUM_OBJECT_MAX => Ok(SizeClass::Medium),
-            constants::LARGE_OBJECT_MIN..=constants::LARGE_OBJECT_MAX => Ok(SizeClass::Large),
-            _ => Err(AllocError::AllocOverflow),
-        }
+        Geometry::DEFAULT.size_class_of(object_size)
+    }
+
+    // how a heap with the default block geometry allocates the layout
+    pub fn get_for_layout(layout: Layout) -> Result<SizeClass, AllocError> {
+        Geometry::DEFAULT.size_class(layout)
+    }
+
+    /// Classifies a layout the way the heap would allocate it, without
+    /// allocating, see [`Heap::can_allocate`].
+    ///
+    /// [`Heap::can_allocate`]: crate::Heap::can_allocate
+    pub fn of_layout(layout: &Layout) -> Result<SizeClass, AllocError> {
+        Self::get_for_layout(*layout)
     }
 }
@@ -0,0 +1,43 @@
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+
+/// A reference to an object that doesn't keep it alive, as returned by
+/// [`Heap::register_weak`].
+///
+/// The first sweep that finds the object unmarked clears the handle, from then
+/// on [`WeakHandle::get`] returns `None`. Clones share the same slot.
+///
+/// [`Heap::register_weak`]: crate::Heap::register_weak
+#[derive(Debug, Clone)]
+pub struct WeakHandle {
+    slot: Arc<AtomicPtr<u8>>,
+}
+
+impl WeakHandle {
+    pub(crate) fn new(ptr: *const u8) -> Self {
+        Self {
+            slot: Arc::new(AtomicPtr::new(ptr as *mut u8)),
+        }
+    }
+
+    /// The object the handle refers to, or `None` once it has been swept.
+    pub fn get(&self) -> Option<*const u8> {
+        let ptr = self.slot.load(Ordering::Acquire);
+
+        if ptr.is_null() {
+            None
+        } else {
+            Some(ptr as *const u8)
+        }
+    }
+
+    pub(crate) fn slot(&self) -> Arc<AtomicPtr<u8>> {
+        self.slot.clone()
+    }
+
+    // Clears a slot whose object was swept.
+    pub(crate) fn clear(slot: &AtomicPtr<u8>) {
+        slot.store(ptr::null_mut(), Ordering::Release);
+    }
+}
@@ -0,0 +1,84 @@
+use nimix::NimixGlobal;
+use std::collections::HashMap;
+use std::thread;
+
+#[global_allocator]
+static GLOBAL: NimixGlobal = NimixGlobal::new();
+
+#[test]
+fn std_collections_allocate_from_the_heap() {
+    let mut map = HashMap::new();
+
+    for i in 0..10_000 {
+        map.insert(i, format!("value {}", i));
+    }
+
+    let big: Vec<u64> = (0..100_000).collect();
+
+    assert_eq!(map[&1234], "value 1234");
+    assert_eq!(big.iter().sum::<u64>(), 99_999 * 100_000 / 2);
+}
+
+#[test]
+fn threads_allocate_through_their_own_handles() {
+    let workers: Vec<_> = (0..4)
+        .map(|n| {
+            thread::spawn(move || {
+                let strings: Vec<String> = (0..1000).map(|i| (n * i).to_string()).collect();
+
+                strings.iter().map(|s| s.len()).sum::<usize>()
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        assert!(worker.join().unwrap() > 0);
+    }
+}
+
+#[test]
+fn heap_objects_freed_from_heap_code_stay_with_the_heap() {
+    use std::alloc::Layout;
+    use std::num::NonZero;
+
+    let layout = Layout::new::<u64>();
+    let mark = NonZero::new(1).unwrap();
+    let ptr = unsafe { nimix::alloc(layout).unwrap() } as *mut u64;
+
+    unsafe {
+        ptr.write(7);
+        nimix::Heap::mark(ptr as *mut u8, layout, mark).unwrap();
+
+        // the callback runs as heap code, where the global allocator can't
+        // take the heap's locks, yet the object must not reach the system
+        // allocator
+        nimix::sweep(mark, || std::alloc::dealloc(ptr as *mut u8, layout));
+
+        assert_eq!(ptr.read(), 7);
+    }
+}
+
+#[test]
+fn other_heaps_release_their_blocks_to_the_system() {
+    use nimix::Heap;
+    use std::alloc::Layout;
+
+    let layout = Layout::new::<[u64; 16]>();
+    let before = GLOBAL.size();
+    let heap = Heap::new();
+
+    // far more than the other tests allocate from the global heap meanwhile
+    for _ in 0..(1 << 19) {
+        unsafe { heap.alloc(layout).unwrap() };
+    }
+
+    let size = heap.size();
+
+    assert!(size >= 64 << 20);
+
+    // the blocks never were objects of the global heap, so dropping the heap
+    // hands them back to the system rather than leaving them to its sweeps
+    drop(heap);
+
+    assert!(GLOBAL.size() - before < size / 2);
+}
@@ -0,0 +1,2909 @@
+use nimix::Heap;
+use std::alloc::Layout;
+use std::num::NonZero;
+
+const BLOCK_SIZE: usize = 1024 * 16;
+const LINE_SIZE: usize = 128;
+const MARK_COLORS: usize = if cfg!(feature = "dual-mark") { 2 } else { 1 };
+const CARD_MARK_BYTES: usize = if cfg!(feature = "card-marks") { 1 } else { 0 };
+const LINE_COUNT: usize = line_count(BLOCK_SIZE, LINE_SIZE);
+
+// lines of data per block, unless they are kept on the side the line marks
+// take up the rest along with a few bytes of block metadata
+const fn line_count(block_size: usize, line_size: usize) -> usize {
+    if cfg!(feature = "side-meta") {
+        block_size / line_size
+    } else {
+        (block_size - 3 - CARD_MARK_BYTES - MARK_COLORS) / (line_size + MARK_COLORS)
+    }
+}
+
+// too big to fit in a block
+type Large = [u64; BLOCK_SIZE / 4];
+
+// an object that takes up a line of its own
+fn line_layout() -> Layout {
+    Layout::from_size_align(LINE_SIZE, 8).unwrap()
+}
+
+#[derive(Clone, Copy)]
+struct Point {
+    x: u64,
+    y: u64,
+}
+
+#[test]
+fn heap_handles_can_be_sent_between_threads() {
+    fn assert_send<T: Send>() {}
+
+    assert_send::<Heap>();
+}
+
+#[test]
+fn iter_live_as_sums_marked_objects() {
+    let heap = unsafe { Heap::new_homogeneous::<Point>() };
+    let layout = Layout::new::<Point>();
+    let mark = NonZero::new(1).unwrap();
+    let mut expect = 0;
+
+    for i in 0..5000 {
+        unsafe {
+            let ptr = heap.alloc(layout).unwrap();
+
+            (ptr as *mut Point).write(Point { x: i, y: 1 });
+            Heap::mark(ptr, layout, mark).unwrap();
+        }
+
+        expect += i;
+    }
+
+    let got: u64 = heap.iter_live_as::<Point>(mark).map(|p| p.x).sum();
+    let count: u64 = heap.iter_live_as::<Point>(mark).map(|p| p.y).sum();
+
+    assert_eq!(got, expect);
+    assert_eq!(count, 5000);
+}
+
+#[test]
+fn used_is_bounded_by_capacity() {
+    let heap = Heap::new();
+    let layout = Layout::new::<[u64; 2]>();
+    let mark = NonZero::new(1).unwrap();
+    let alloc_heap = heap.clone();
+    let mut ptrs = vec![];
+
+    for _ in 0..100 {
+        ptrs.push(unsafe { alloc_heap.alloc(layout).unwrap() });
+    }
+
+    drop(alloc_heap);
+
+    let used = heap.used();
+
+    assert_eq!(used, 1600);
+    assert!(used <= heap.capacity());
+
+    unsafe {
+        Heap::mark(ptrs[0], layout, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    assert!(heap.used() > 0);
+    assert!(heap.used() < used);
+}
+
+#[test]
+fn deterministic_allocation_offsets_match() {
+    fn offsets() -> Vec<usize> {
+        let heap = Heap::new().with_deterministic_allocation();
+        let mark = NonZero::new(1).unwrap();
+        let mut offsets = vec![];
+
+        for round in 0..3 {
+            for i in 1..300 {
+                let layout = Layout::from_size_align(i * 7 % 500 + 1, 8).unwrap();
+                let ptr = unsafe { heap.alloc(layout).unwrap() };
+
+                if i % 3 == round {
+                    unsafe { Heap::mark(ptr, layout, mark).unwrap() };
+                }
+
+                offsets.push(ptr as usize % BLOCK_SIZE);
+            }
+
+            unsafe { heap.sweep(mark, || {}) };
+        }
+
+        offsets
+    }
+
+    assert_eq!(offsets(), offsets());
+}
+
+#[test]
+fn cache_aligned_allocations_do_not_share_lines() {
+    let heap = Heap::new();
+
+    unsafe {
+        let a = heap.alloc_cache_aligned(24).unwrap() as usize;
+        let b = heap.alloc_cache_aligned(24).unwrap() as usize;
+
+        assert_eq!(a % 64, 0);
+        assert_eq!(b % 64, 0);
+        assert!(a.abs_diff(b) >= 64);
+    }
+}
+
+#[test]
+fn try_new_in_rejects_small_region() {
+    let mut region = vec![0u8; BLOCK_SIZE];
+    let heap = unsafe { Heap::try_new_in(region.as_mut_ptr(), BLOCK_SIZE - 1) };
+
+    assert!(heap.is_err());
+}
+
+#[test]
+fn try_new_in_allocates_from_region() {
+    let mut region = vec![0u8; BLOCK_SIZE * 4];
+    let start = region.as_ptr() as usize;
+    let end = start + region.len();
+    let heap = unsafe { Heap::try_new_in(region.as_mut_ptr(), region.len()).unwrap() };
+    let layout = Layout::new::<u64>();
+
+    for _ in 0..1000 {
+        let ptr = unsafe { heap.alloc(layout).unwrap() } as usize;
+
+        assert!(start <= ptr && ptr < end);
+    }
+
+    drop(heap);
+}
+
+#[test]
+fn prealloc_best_effort_stops_when_region_is_full() {
+    let region_layout = Layout::from_size_align(BLOCK_SIZE * 4, BLOCK_SIZE).unwrap();
+    let region = unsafe { std::alloc::alloc(region_layout) };
+    let heap = unsafe { Heap::try_new_in(region, region_layout.size()).unwrap() };
+
+    assert_eq!(heap.prealloc_best_effort(10), 4);
+    assert_eq!(heap.prealloc_best_effort(1), 0);
+    assert_eq!(heap.size(), BLOCK_SIZE * 4);
+
+    drop(heap);
+    unsafe { std::alloc::dealloc(region, region_layout) };
+}
+
+#[test]
+fn alloc_with_trailing_array() {
+    let heap = Heap::new();
+    let header = Layout::from_size_align(16, 8).unwrap();
+    let elem = Layout::new::<u32>();
+
+    unsafe {
+        let ptr = heap.alloc_with_trailing(header, elem, 100).unwrap();
+        let data = ptr.add(16) as *mut u32;
+
+        (ptr as *mut [u64; 2]).write([7, 100]);
+
+        for i in 0..100 {
+            data.add(i).write(i as u32 * 3);
+        }
+
+        assert_eq!(*(ptr as *const [u64; 2]), [7, 100]);
+
+        for i in 0..100 {
+            assert_eq!(*data.add(i), i as u32 * 3);
+        }
+
+        assert!(heap.alloc_with_trailing(header, elem, usize::MAX).is_err());
+    }
+}
+
+#[test]
+fn owns_small_objects_only() {
+    let heap = Heap::new();
+    let value = 0u64;
+
+    unsafe {
+        let small = heap.alloc(Layout::new::<[u8; 64]>()).unwrap();
+        let large = heap.alloc(Layout::new::<Large>()).unwrap();
+
+        assert!(heap.owns(small.add(63)));
+        assert!(!heap.owns(large));
+        assert!(!heap.owns(&value as *const u64 as *const u8));
+    }
+}
+
+#[test]
+fn mark_over_aligned_large_object() {
+    let heap = Heap::new();
+    let layout = Layout::from_size_align(BLOCK_SIZE + 1024 * 4, 4096).unwrap();
+    let mark = NonZero::new(1).unwrap();
+
+    unsafe {
+        let ptr = heap.alloc(layout).unwrap();
+
+        assert_eq!(ptr as usize % 4096, 0);
+
+        let size = heap.size();
+
+        Heap::mark(ptr, layout, mark).unwrap();
+        heap.sweep(mark, || {});
+
+        assert_eq!(heap.size(), size);
+
+        heap.sweep(NonZero::new(2).unwrap(), || {});
+
+        // the dead object's block is kept for reuse until memory runs short
+        assert_eq!(heap.size(), size);
+
+        heap.on_memory_pressure();
+
+        assert_eq!(heap.size(), 0);
+    }
+}
+
+#[test]
+fn ids_are_not_reused_with_memory() {
+    let heap = Heap::new();
+    let layout = Layout::new::<[u64; 3]>();
+    let mark = NonZero::new(1).unwrap();
+
+    let alloc_heap = heap.clone();
+    let (first, first_id) = unsafe { alloc_heap.alloc_with_id(layout).unwrap() };
+    drop(alloc_heap);
+
+    assert_eq!(heap.object_id(first), Some(first_id));
+
+    unsafe { heap.sweep(mark, || {}) };
+
+    assert_eq!(heap.object_id(first), None);
+
+    let alloc_heap = heap.clone();
+    let (second, second_id) = unsafe { alloc_heap.alloc_with_id(layout).unwrap() };
+
+    assert_eq!(first, second);
+    assert_ne!(first_id, second_id);
+    assert_eq!(heap.object_id(second), Some(second_id));
+}
+
+#[test]
+fn soft_limit_does_not_fail_allocation() {
+    let heap = Heap::new().with_soft_limit(BLOCK_SIZE * 4);
+    let layout = Layout::from_size_align(BLOCK_SIZE + 4 * 1024, 8).unwrap();
+    let mark = NonZero::new(1).unwrap();
+
+    for _ in 0..5 {
+        assert!(unsafe { heap.alloc(layout) }.is_ok());
+    }
+
+    assert!(heap.over_soft_limit());
+
+    unsafe { heap.sweep(mark, || {}) };
+
+    assert!(!heap.over_soft_limit());
+}
+
+#[test]
+fn shutdown_reports_outstanding_handles() {
+    let heap = Heap::new();
+    let other = heap.clone();
+    let layout = Layout::new::<u64>();
+
+    unsafe { other.alloc(layout).unwrap() };
+
+    assert_eq!(heap.clone().shutdown(), Err(2));
+    assert_eq!(heap.shutdown(), Err(1));
+    assert_eq!(other.shutdown(), Ok(()));
+}
+
+#[test]
+fn shutdown_counts_allocating_handles_only() {
+    use nimix::AllocatorCache;
+
+    let heap = Heap::new();
+    let raw = heap.clone().into_raw();
+
+    assert_eq!(heap.clone().shutdown(), Err(2));
+
+    let other = unsafe { Heap::from_raw(raw) };
+
+    assert_eq!(heap.clone().shutdown(), Err(2));
+    drop(other);
+
+    // this thread's cached handle is handed back by the shutdown itself
+    AllocatorCache::with(&heap, |cached| unsafe { cached.alloc(Layout::new::<u64>()).unwrap() });
+
+    assert_eq!(heap.shutdown(), Ok(()));
+}
+
+#[test]
+fn shutdown_releases_blocks_during_background_sweep() {
+    use counting::{Counting, Counts};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    let counts = Arc::new(Counts::default());
+    let heap = Heap::with_backing(Counting(counts.clone())).with_min_block_count(2).unwrap();
+    let layout = line_layout();
+    let mark = NonZero::new(1).unwrap();
+
+    unsafe {
+        for _ in 0..(3 * LINE_COUNT) {
+            let ptr = heap.alloc(layout).unwrap();
+
+            Heap::mark(ptr, layout, mark).unwrap();
+        }
+
+        heap.alloc(Layout::new::<Large>()).unwrap();
+    }
+
+    let sweep = unsafe { heap.sweep_in_background(mark) };
+
+    // the sweep's thread isn't a handle, and is left nothing to sweep
+    assert_eq!(heap.shutdown(), Ok(()));
+    assert_eq!(counts.deallocs.load(Ordering::Relaxed), counts.allocs.load(Ordering::Relaxed));
+    assert_eq!(sweep.join().unwrap().blocks_freed, 0);
+}
+
+#[test]
+fn small_bursts_take_free_blocks_first() {
+    use nimix::HeapConfig;
+
+    let layout = line_layout();
+    let mark = NonZero::new(1).unwrap();
+
+    for small_burst_blocks in [usize::MAX, 1] {
+        let heap = Heap::with_config(HeapConfig {
+            small_burst_blocks,
+            ..HeapConfig::default()
+        });
+        let filler = heap.clone();
+        let objects: Vec<usize> = (0..(4 * LINE_COUNT))
+            .map(|_| unsafe { filler.alloc(layout).unwrap() } as usize)
+            .collect();
+
+        drop(filler);
+
+        // the first two blocks keep a live object, the other two are freed
+        let recycled = [objects[0] / BLOCK_SIZE, objects[LINE_COUNT] / BLOCK_SIZE];
+
+        for obj in [objects[0], objects[LINE_COUNT]] {
+            unsafe { Heap::mark(obj as *mut u8, layout, mark).unwrap() };
+        }
+
+        unsafe { heap.sweep(mark, || {}) };
+
+        // the first two blocks a burst of small allocations goes through
+        let burst = heap.clone();
+        let mut blocks = vec![];
+
+        while blocks.len() < 2 {
+            let block = unsafe { burst.alloc(layout).unwrap() } as usize / BLOCK_SIZE;
+
+            if blocks.last() != Some(&block) {
+                blocks.push(block);
+            }
+        }
+
+        // it starts out in a recycled block, and is only given a free one once
+        // it has gone on long enough
+        assert!(recycled.contains(&blocks[0]));
+        assert_eq!(recycled.contains(&blocks[1]), small_burst_blocks == usize::MAX);
+    }
+}
+
+#[test]
+fn observer_sees_events_in_order() {
+    use nimix::{BlockId, HeapObserver};
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<&'static str>>>);
+
+    impl HeapObserver for Recorder {
+        fn on_alloc(&self, _: *const u8, _: Layout) {
+            self.0.lock().unwrap().push("alloc");
+        }
+
+        fn on_new_block(&self, _: BlockId) {
+            self.0.lock().unwrap().push("new_block");
+        }
+
+        fn on_sweep_start(&self, _: NonZero<u8>) {
+            self.0.lock().unwrap().push("sweep_start");
+        }
+
+        fn on_block_freed(&self, _: BlockId) {
+            self.0.lock().unwrap().push("block_freed");
+        }
+
+        fn on_sweep_end(&self, _: NonZero<u8>) {
+            self.0.lock().unwrap().push("sweep_end");
+        }
+    }
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let heap = Heap::new().with_observer(Box::new(Recorder(events.clone())));
+    let alloc_heap = heap.clone();
+
+    unsafe { alloc_heap.alloc(Layout::new::<u64>()).unwrap() };
+    drop(alloc_heap);
+    unsafe { heap.sweep(NonZero::new(1).unwrap(), || {}) };
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        ["new_block", "alloc", "sweep_start", "block_freed", "sweep_end"]
+    );
+}
+
+#[test]
+fn can_allocate_classifies_layouts() {
+    use nimix::{AllocError, SizeClass};
+
+    let heap = Heap::new();
+    let small = Layout::new::<u64>();
+    let too_big = Layout::from_size_align(u32::MAX as usize + 1, 8).unwrap();
+    let over_aligned = Layout::from_size_align(8, BLOCK_SIZE * 2).unwrap();
+
+    assert_eq!(heap.can_allocate(small).unwrap(), SizeClass::Small);
+    assert!(matches!(heap.can_allocate(too_big), Err(AllocError::AllocOverflow)));
+    assert_eq!(heap.can_allocate(over_aligned).unwrap(), SizeClass::Large);
+    assert_eq!(heap.size(), 0);
+}
+
+#[test]
+fn over_aligned_small_object_gets_large_block() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let layout = Layout::from_size_align(64, BLOCK_SIZE * 4).unwrap();
+    let ptr = unsafe { heap.alloc(layout).unwrap() };
+
+    assert_eq!(ptr as usize % layout.align(), 0);
+    assert_eq!(heap.stats().large_object_count, 1);
+
+    unsafe {
+        ptr.write_bytes(1, layout.size());
+        Heap::mark(ptr, layout, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    assert_eq!(heap.stats().large_object_count, 1);
+}
+
+#[test]
+fn header_is_contiguous_with_body() {
+    let heap = Heap::new();
+    let header = Layout::new::<[u32; 3]>();
+    let body = Layout::new::<[u64; 4]>();
+    let mark = NonZero::new(1).unwrap();
+
+    let (header_ptr, body_ptr) = unsafe { heap.alloc_with_header(header, body).unwrap() };
+
+    assert_eq!(header_ptr as usize + header.size(), body_ptr as usize);
+    assert_eq!(body_ptr as usize % body.align(), 0);
+
+    unsafe {
+        (header_ptr as *mut [u32; 3]).write([1, 2, 3]);
+        (body_ptr as *mut [u64; 4]).write([4, 5, 6, 7]);
+
+        assert_eq!(*(header_ptr as *const [u32; 3]), [1, 2, 3]);
+        assert_eq!(*(body_ptr as *const [u64; 4]), [4, 5, 6, 7]);
+
+        Heap::mark_with_header(body_ptr, header, body, mark).unwrap();
+    }
+}
+
+#[test]
+fn sweep_reports_freed_blocks() {
+    let heap = Heap::new();
+    let layout = line_layout();
+    let mark = NonZero::new(1).unwrap();
+    let alloc_heap = heap.clone();
+    let mut objects = vec![];
+
+    // four full blocks, only the first keeps a live object
+    for _ in 0..(4 * LINE_COUNT) {
+        objects.push(unsafe { alloc_heap.alloc(layout).unwrap() } as usize);
+    }
+
+    drop(alloc_heap);
+    unsafe { Heap::mark(objects[0] as *mut u8, layout, mark).unwrap() };
+
+    let mut freed: Vec<usize> = unsafe { heap.sweep_reporting_free(mark, || {}) }
+        .into_iter()
+        .map(|(base, size)| {
+            assert_eq!(size, BLOCK_SIZE);
+            base as usize
+        })
+        .collect();
+    let mut dead: Vec<usize> = objects[LINE_COUNT..]
+        .iter()
+        .map(|obj| obj - obj % BLOCK_SIZE)
+        .collect();
+
+    freed.sort();
+    dead.sort();
+    dead.dedup();
+
+    assert_eq!(freed.len(), 3);
+    assert_eq!(freed, dead);
+}
+
+#[test]
+fn filling_one_block_refreshes_once() {
+    const BLOCK_CAPACITY: usize = LINE_COUNT * LINE_SIZE;
+
+    let heap = Heap::new();
+    let layout = Layout::new::<u8>();
+
+    for _ in 0..BLOCK_CAPACITY {
+        unsafe { heap.alloc(layout).unwrap() };
+    }
+
+    let stats = heap.fast_path_stats();
+
+    assert_eq!(stats.refreshes, 1);
+    assert_eq!(stats.hits, BLOCK_CAPACITY - 1);
+}
+
+#[test]
+fn into_live_iter_copies_live_objects() {
+    use nimix::HeapObserver;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // dropped along with the old heap's block store
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl HeapObserver for DropFlag {}
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // fills a line, so marking one object never keeps a neighbour alive
+    #[derive(Clone, Copy)]
+    struct Wide {
+        x: u64,
+        y: u64,
+        _pad: [u64; LINE_SIZE / 8 - 2],
+    }
+
+    let freed = Arc::new(AtomicBool::new(false));
+    let old = unsafe { Heap::new_homogeneous::<Wide>() }
+        .with_observer(Box::new(DropFlag(freed.clone())));
+    let new = unsafe { Heap::new_homogeneous::<Wide>() };
+    let layout = Layout::new::<Wide>();
+    let mark = NonZero::new(1).unwrap();
+
+    for i in 0..8u64 {
+        let ptr = unsafe { old.alloc(layout).unwrap() } as *mut Wide;
+
+        unsafe { ptr.write(Wide { x: i, y: i * 10, _pad: [0; LINE_SIZE / 8 - 2] }) };
+
+        if i % 2 == 0 {
+            unsafe { Heap::mark(ptr as *mut u8, layout, mark).unwrap() };
+        }
+    }
+
+    let mut copies = vec![];
+
+    for (ptr, size) in old.into_live_iter(mark) {
+        assert_eq!(size, layout.size());
+        assert!(!freed.load(Ordering::Relaxed));
+
+        let copy = unsafe { new.alloc(layout).unwrap() };
+
+        unsafe { std::ptr::copy_nonoverlapping(ptr, copy, size) };
+        copies.push(copy as *const Wide);
+    }
+
+    assert!(freed.load(Ordering::Relaxed));
+
+    let mut xs: Vec<u64> = copies.iter().map(|copy| unsafe { (**copy).x }).collect();
+
+    xs.sort();
+
+    for copy in copies {
+        assert_eq!(unsafe { (*copy).y }, unsafe { (*copy).x } * 10);
+    }
+
+    assert_eq!(xs, [0, 2, 4, 6]);
+}
+
+#[test]
+#[should_panic(expected = "out of memory allocating 64 bytes")]
+fn panic_on_oom_reports_requested_size() {
+    let mut region = vec![0u8; BLOCK_SIZE * 4];
+    let heap = unsafe { Heap::try_new_in(region.as_mut_ptr(), region.len()).unwrap() };
+    let layout = Layout::new::<[u64; 8]>();
+
+    heap.set_panic_on_oom(true);
+
+    // the region holds at most four blocks
+    loop {
+        unsafe { heap.alloc(layout).unwrap() };
+    }
+}
+
+#[test]
+fn immortal_objects_survive_sweeps() {
+    let heap = Heap::new();
+    let small = Layout::new::<[u64; 4]>();
+    let large = Layout::new::<Large>();
+    let alloc_heap = heap.clone();
+
+    let (small_ptr, large_ptr) = unsafe {
+        let small_ptr = alloc_heap.alloc(small).unwrap() as *mut [u64; 4];
+        let large_ptr = alloc_heap.alloc(large).unwrap() as *mut Large;
+
+        small_ptr.write([1, 2, 3, 4]);
+        large_ptr.write([7; BLOCK_SIZE / 4]);
+        heap.immortalize(small_ptr as *const u8, small).unwrap();
+        heap.immortalize(large_ptr as *const u8, large).unwrap();
+
+        (small_ptr, large_ptr)
+    };
+
+    drop(alloc_heap);
+
+    for mark in 1..4 {
+        unsafe { heap.sweep(NonZero::new(mark).unwrap(), || {}) };
+
+        // reuse whatever space the sweep freed up
+        let alloc_heap = heap.clone();
+
+        for _ in 0..1000 {
+            let ptr = unsafe { alloc_heap.alloc(small).unwrap() } as *mut [u64; 4];
+
+            unsafe { ptr.write([0; 4]) };
+        }
+    }
+
+    unsafe {
+        assert_eq!(*small_ptr, [1, 2, 3, 4]);
+        assert!((*large_ptr).iter().all(|word| *word == 7));
+    }
+}
+
+#[test]
+fn block_alignment_keeps_interior_pointers_resolvable() {
+    const ALIGN: usize = BLOCK_SIZE * 4;
+
+    assert!(Heap::new().with_block_alignment(BLOCK_SIZE / 4).is_err());
+
+    let heap = Heap::new().with_block_alignment(ALIGN).unwrap();
+    let layout = Layout::new::<[u64; 4]>();
+    let mark = NonZero::new(1).unwrap();
+    let alloc_heap = heap.clone();
+    let mut objects = vec![];
+
+    for i in 0..2000u64 {
+        let ptr = unsafe { alloc_heap.alloc(layout).unwrap() } as *mut [u64; 4];
+
+        unsafe { ptr.write([i; 4]) };
+        objects.push(ptr);
+    }
+
+    drop(alloc_heap);
+
+    for ptr in objects.iter() {
+        let block = *ptr as usize - *ptr as usize % BLOCK_SIZE;
+
+        assert_eq!(block % ALIGN, 0);
+    }
+
+    // interior pointers resolve to their block's line marks
+    let interior: Vec<usize> = objects
+        .iter()
+        .step_by(3)
+        .map(|ptr| *ptr as usize + 8)
+        .collect();
+
+    heap.scan_conservative(&interior, mark);
+    unsafe { heap.sweep(mark, || {}) };
+
+    let alloc_heap = heap.clone();
+
+    for _ in 0..2000 {
+        let ptr = unsafe { alloc_heap.alloc(layout).unwrap() } as *mut [u64; 4];
+
+        unsafe { ptr.write([u64::MAX; 4]) };
+    }
+
+    for (i, ptr) in objects.iter().enumerate().step_by(3) {
+        assert_eq!(unsafe { **ptr }, [i as u64; 4]);
+    }
+}
+
+#[test]
+fn age_grows_with_each_survived_sweep() {
+    let heap = Heap::new();
+    let small = Layout::new::<[u64; 2]>();
+    let large = Layout::new::<Large>();
+    let alloc_heap = heap.clone();
+    let small_ptr = unsafe { alloc_heap.alloc(small).unwrap() };
+    let large_ptr = unsafe { alloc_heap.alloc(large).unwrap() };
+    let value = 0u64;
+
+    drop(alloc_heap);
+
+    assert_eq!(heap.block_age_of(small_ptr), Some(0));
+    assert_eq!(heap.block_age_of(large_ptr), Some(0));
+    assert_eq!(heap.block_age_of(&value as *const u64 as *const u8), None);
+
+    for age in 1..=3 {
+        let mark = NonZero::new(age).unwrap();
+
+        unsafe {
+            Heap::mark(small_ptr, small, mark).unwrap();
+            Heap::mark(large_ptr, large, mark).unwrap();
+            heap.sweep(mark, || {});
+        }
+
+        assert_eq!(heap.block_age_of(small_ptr), Some(age));
+        assert_eq!(heap.block_age_of(large_ptr), Some(age));
+    }
+}
+
+#[test]
+fn packed_bytes_fill_a_block_exactly() {
+    const BLOCK_CAPACITY: usize = LINE_COUNT * LINE_SIZE;
+
+    let heap = Heap::new();
+
+    for _ in 0..BLOCK_CAPACITY {
+        unsafe { heap.alloc_packed(1).unwrap() };
+    }
+
+    assert_eq!(heap.fast_path_stats().refreshes, 1);
+    assert_eq!(heap.size(), BLOCK_SIZE);
+
+    unsafe { heap.alloc_packed(1).unwrap() };
+
+    assert_eq!(heap.fast_path_stats().refreshes, 2);
+}
+
+#[test]
+fn allocator_cache_serves_several_heaps() {
+    use nimix::AllocatorCache;
+
+    let heaps = [Heap::new(), Heap::new()];
+    let layout = Layout::new::<u64>();
+    let mark = NonZero::new(1).unwrap();
+    let mut objects = vec![];
+
+    for i in 0..1000u64 {
+        let heap = &heaps[i as usize % 2];
+        let ptr = AllocatorCache::with(heap, |handle| unsafe { handle.alloc(layout).unwrap() });
+
+        unsafe { (ptr as *mut u64).write(i) };
+
+        if i % 4 < 2 {
+            unsafe { Heap::mark(ptr, layout, mark).unwrap() };
+            objects.push((ptr, i));
+        }
+    }
+
+    // each heap was served by one cached handle holding one block
+    assert!(heaps.iter().all(|heap| heap.size() == BLOCK_SIZE));
+
+    AllocatorCache::flush();
+
+    for heap in heaps.iter() {
+        unsafe { heap.sweep(mark, || {}) };
+    }
+
+    for i in 0..1000u64 {
+        let heap = &heaps[i as usize % 2];
+        let ptr = AllocatorCache::with(heap, |handle| unsafe { handle.alloc(layout).unwrap() });
+
+        unsafe { (ptr as *mut u64).write(u64::MAX) };
+    }
+
+    for (ptr, i) in objects {
+        assert_eq!(unsafe { *(ptr as *const u64) }, i);
+    }
+}
+
+#[test]
+fn slabs_are_line_aligned_and_reclaimed_whole() {
+    let heap = Heap::new();
+    let small = Layout::new::<u64>();
+    let alloc_heap = heap.clone();
+    let obj = unsafe { alloc_heap.alloc(small).unwrap() };
+    let slab = unsafe { alloc_heap.alloc_slab(4).unwrap() };
+
+    drop(alloc_heap);
+
+    assert_eq!(slab as usize % LINE_SIZE, 0);
+    unsafe { slab.write_bytes(0xab, 4 * LINE_SIZE) };
+
+    let mark = NonZero::new(1).unwrap();
+
+    unsafe {
+        Heap::mark(obj, small, mark).unwrap();
+        Heap::mark_slab(slab, 4, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    assert_eq!(heap.used(), 5 * LINE_SIZE);
+
+    let mark = NonZero::new(2).unwrap();
+
+    unsafe {
+        Heap::mark(obj, small, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    assert_eq!(heap.used(), LINE_SIZE);
+}
+
+#[test]
+fn predicate_keeps_unmarked_large_object() {
+    let heap = Heap::new();
+    let large = Layout::new::<Large>();
+    let small = Layout::new::<u64>();
+    let mark = NonZero::new(1).unwrap();
+    let alloc_heap = heap.clone();
+
+    let (kept, dropped, obj) = unsafe {
+        let kept = alloc_heap.alloc(large).unwrap() as *const u8;
+        let dropped = alloc_heap.alloc(large).unwrap() as *const u8;
+        let obj = alloc_heap.alloc(small).unwrap() as *const u8;
+
+        (kept as *mut Large).write([3; BLOCK_SIZE / 4]);
+
+        (kept, dropped, obj)
+    };
+
+    drop(alloc_heap);
+
+    unsafe { heap.sweep_with_predicate(mark, || {}, |ptr| ptr == kept) };
+
+    assert!(heap.block_age_of(kept).is_some());
+    assert!(heap.block_age_of(dropped).is_none());
+    assert_eq!(
+        heap.size() - heap.stats().pooled_large_bytes,
+        BLOCK_SIZE + large.size() + 8
+    );
+    assert!(unsafe { (*(kept as *const Large)).iter().all(|word| *word == 3) });
+
+    // the block holding the small object was freed, not kept
+    let alloc_heap = heap.clone();
+
+    assert_eq!(unsafe { alloc_heap.alloc(small).unwrap() } as *const u8, obj);
+}
+
+#[test]
+fn mark_if_unmarked_reports_revisits() {
+    let heap = Heap::new();
+    let small = Layout::new::<u64>();
+    let large = Layout::new::<Large>();
+    let mark = NonZero::new(1).unwrap();
+
+    unsafe {
+        let a = heap.alloc(small).unwrap();
+        let b = heap.alloc(small).unwrap();
+        let big = heap.alloc(large).unwrap();
+
+        assert!(heap.mark_if_unmarked(a, small, mark).unwrap());
+        assert!(!heap.mark_if_unmarked(a, small, mark).unwrap());
+
+        // b shares a's line but has not been visited yet
+        assert!(heap.mark_if_unmarked(b, small, mark).unwrap());
+
+        assert!(heap.mark_if_unmarked(big, large, mark).unwrap());
+        assert!(!heap.mark_if_unmarked(big, large, mark).unwrap());
+
+        // a new cycle starts over
+        let mark = NonZero::new(2).unwrap();
+
+        assert!(heap.mark_if_unmarked(a, small, mark).unwrap());
+        assert!(heap.mark_if_unmarked(big, large, mark).unwrap());
+    }
+}
+
+#[test]
+fn min_block_count_survives_memory_pressure() {
+    let heap = Heap::new().with_min_block_count(5).unwrap();
+    let layout = line_layout();
+    let mark = NonZero::new(1).unwrap();
+
+    assert_eq!(heap.size(), 5 * BLOCK_SIZE);
+
+    let alloc_heap = heap.clone();
+
+    // ten blocks worth, all dead after the sweep
+    for _ in 0..(10 * LINE_COUNT) {
+        unsafe { alloc_heap.alloc(layout).unwrap() };
+    }
+
+    drop(alloc_heap);
+    unsafe { heap.sweep(mark, || {}) };
+
+    assert_eq!(heap.size(), 10 * BLOCK_SIZE);
+    assert_eq!(heap.on_memory_pressure(), 5 * BLOCK_SIZE);
+    assert_eq!(heap.size(), 5 * BLOCK_SIZE);
+}
+
+#[test]
+fn realloc_moves_medium_object_to_another_block() {
+    let heap = Heap::new();
+    let old_layout = Layout::from_size_align(BLOCK_SIZE / 2, 8).unwrap();
+    let new_size = BLOCK_SIZE / 4 * 3;
+    let mark = NonZero::new(1).unwrap();
+    let alloc_heap = heap.clone();
+
+    let (old, new) = unsafe {
+        // fill most of the block, so the grown object can't stay in it
+        let old = alloc_heap.alloc(old_layout).unwrap();
+
+        for i in 0..old_layout.size() {
+            old.add(i).write(i as u8);
+        }
+
+        let new = alloc_heap.realloc(old, old_layout, new_size).unwrap();
+
+        (old, new)
+    };
+
+    drop(alloc_heap);
+
+    let old_block = old as usize - old as usize % BLOCK_SIZE;
+
+    assert_ne!(new as usize - new as usize % BLOCK_SIZE, old_block);
+
+    for i in 0..old_layout.size() {
+        assert_eq!(unsafe { *new.add(i) }, i as u8);
+    }
+
+    let new_layout = Layout::from_size_align(new_size, 8).unwrap();
+
+    unsafe { Heap::mark(new, new_layout, mark).unwrap() };
+
+    let freed = unsafe { heap.sweep_reporting_free(mark, || {}) };
+
+    assert_eq!(freed, [(old_block as *const u8, BLOCK_SIZE)]);
+}
+
+#[test]
+fn realloc_frees_old_object_carrying_birth_color() {
+    let mark = NonZero::new(1).unwrap();
+    let heap = Heap::new();
+    let alloc_heap = heap.clone().with_birth_color(mark);
+    // takes up whole lines, so none of them is shared with another object
+    let old_layout = Layout::from_size_align(LINE_SIZE * (LINE_COUNT / 2), LINE_SIZE).unwrap();
+    let new_size = LINE_SIZE * (LINE_COUNT / 4 * 3);
+
+    let (old, new) = unsafe {
+        let old = alloc_heap.alloc(old_layout).unwrap();
+        let new = alloc_heap.realloc(old, old_layout, new_size).unwrap();
+
+        (old, new)
+    };
+
+    drop(alloc_heap);
+
+    let old_block = old as usize - old as usize % BLOCK_SIZE;
+
+    assert_ne!(new as usize - new as usize % BLOCK_SIZE, old_block);
+
+    // the birth color marked the old object too, yet its lines are reclaimed
+    unsafe { heap.sweep(mark, || {}) };
+
+    let block = heap.block_for(old).unwrap();
+
+    assert_eq!(block.occupied_lines(), 0);
+    assert_eq!(block.largest_hole(), LINE_SIZE * LINE_COUNT);
+}
+
+#[test]
+fn realloc_of_unknown_object_allocates_nothing() {
+    let heap = Heap::new();
+    let local = [0u64; 8];
+    let layout = Layout::new::<[u64; 8]>();
+
+    let result = unsafe { heap.realloc(local.as_ptr() as *const u8, layout, std::mem::size_of::<Large>()) };
+
+    assert!(matches!(result, Err(nimix::AllocError::UnknownObject)));
+    assert_eq!(heap.stats().large_object_count, 0);
+}
+
+#[test]
+fn realloc_fills_recycled_hole() {
+    let heap = Heap::new();
+    let small = Layout::new::<u64>();
+    let old_layout = Layout::from_size_align(BLOCK_SIZE / 4, 8).unwrap();
+    let mark = NonZero::new(1).unwrap();
+
+    // leave a recycled block whose only live object sits at the top
+    let alloc_heap = heap.clone();
+    let live = unsafe { alloc_heap.alloc(small).unwrap() };
+
+    drop(alloc_heap);
+    unsafe {
+        Heap::mark(live, small, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    let alloc_heap = heap.clone();
+
+    unsafe {
+        // the first medium allocation takes a fresh block
+        let old = alloc_heap.alloc(old_layout).unwrap();
+        let big = Layout::from_size_align(BLOCK_SIZE / 16 * 11, 8).unwrap();
+        let filler = alloc_heap.alloc(big).unwrap();
+        let new = alloc_heap.realloc(old, old_layout, BLOCK_SIZE / 2).unwrap();
+
+        assert_eq!(filler as usize / BLOCK_SIZE, old as usize / BLOCK_SIZE);
+        assert_eq!(new as usize / BLOCK_SIZE, live as usize / BLOCK_SIZE);
+    }
+}
+
+#[test]
+fn realloc_shrinks_in_place_within_size_class() {
+    let heap = Heap::new();
+    let old_layout = Layout::from_size_align(BLOCK_SIZE / 2, 8).unwrap();
+
+    unsafe {
+        let old = heap.alloc(old_layout).unwrap();
+        let new = heap.realloc(old, old_layout, BLOCK_SIZE / 16).unwrap();
+
+        assert_eq!(new, old);
+
+        // medium to small relocates, even though it shrinks
+        let small = heap.realloc(old, old_layout, LINE_SIZE / 2).unwrap();
+
+        assert_ne!(small, old);
+    }
+}
+
+#[test]
+fn realloc_grows_within_size_class() {
+    let heap = Heap::new();
+    let old_layout = Layout::new::<[u8; 16]>();
+
+    unsafe {
+        let old = heap.alloc(old_layout).unwrap();
+
+        old.copy_from(b"sixteen bytes!!!".as_ptr(), 16);
+
+        let new = heap.realloc(old, old_layout, 64).unwrap();
+
+        assert_ne!(new, old);
+        assert_eq!(std::slice::from_raw_parts(new, 16), b"sixteen bytes!!!");
+    }
+}
+
+#[test]
+fn realloc_promotes_medium_object_to_large() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let old_layout = Layout::from_size_align(BLOCK_SIZE / 2, 8).unwrap();
+    let new_size = BLOCK_SIZE * 4;
+
+    unsafe {
+        let old = heap.alloc(old_layout).unwrap();
+
+        for i in 0..old_layout.size() {
+            old.add(i).write(i as u8);
+        }
+
+        let new = heap.realloc(old, old_layout, new_size).unwrap();
+
+        assert!(heap.block_for(old).is_some());
+        assert!(heap.block_for(new).is_none());
+
+        Heap::mark(new, Layout::from_size_align(new_size, 8).unwrap(), mark).unwrap();
+        heap.sweep(mark, || {});
+
+        for i in 0..old_layout.size() {
+            assert_eq!(*new.add(i), i as u8);
+        }
+    }
+}
+
+#[test]
+fn raw_round_trip_keeps_the_heap() {
+    let heap = Heap::new();
+    let observer = heap.clone();
+    let layout = Layout::new::<[u64; 4]>();
+
+    unsafe { heap.alloc(layout).unwrap() };
+
+    let raw = heap.into_raw();
+    let thread_handle = unsafe { Heap::clone_from_raw(raw) };
+    let heap = unsafe { Heap::from_raw(raw) };
+
+    unsafe { heap.alloc(Layout::new::<Large>()).unwrap() };
+    unsafe { thread_handle.alloc(layout).unwrap() };
+
+    // the second handle picked up the block the first one handed back
+    assert_eq!(observer.size(), heap.size());
+    assert_eq!(observer.size(), BLOCK_SIZE + std::mem::size_of::<Large>() + 8);
+
+    drop(thread_handle);
+    drop(heap);
+
+    assert_eq!(observer.used(), 2 * layout.size() + std::mem::size_of::<Large>() + 8);
+
+    assert_eq!(observer.shutdown(), Ok(()));
+}
+
+#[test]
+fn birth_color_keeps_new_objects_alive() {
+    let young = NonZero::new(1).unwrap();
+    let heap = Heap::new();
+    // hands its blocks back when dropped, so they are swept
+    let born = heap.clone().with_birth_color(young);
+    let layouts = [
+        Layout::new::<u64>(),
+        Layout::new::<[u64; 64]>(),
+        Layout::new::<Large>(),
+    ];
+    let mut objects = vec![];
+
+    for (i, layout) in layouts.iter().cycle().take(30).enumerate() {
+        let ptr = unsafe { born.alloc(*layout).unwrap() };
+
+        unsafe { std::ptr::write_bytes(ptr, i as u8, layout.size()) };
+        objects.push((ptr, *layout, i as u8));
+    }
+
+    drop(born);
+
+    let size = heap.size();
+
+    // nothing was marked explicitly, yet every object survives
+    unsafe { heap.sweep(young, || {}) };
+
+    assert_eq!(heap.size(), size);
+    assert_eq!(heap.stats().free_block_count, 0);
+    assert_eq!(heap.stats().large_object_count, 10);
+
+    let other = heap.clone();
+
+    for _ in 0..1000 {
+        let ptr = unsafe { other.alloc(layouts[1]).unwrap() };
+
+        unsafe { std::ptr::write_bytes(ptr, 0xFF, layouts[1].size()) };
+    }
+
+    for (ptr, layout, byte) in objects {
+        let bytes = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+
+        assert!(bytes.iter().all(|b| *b == byte));
+    }
+}
+
+#[cfg(feature = "track-allocations")]
+#[test]
+fn unmarked_objects_are_reported() {
+    use std::collections::HashSet;
+
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    // the sweeping handle's own block isn't swept, nor reported
+    let held = unsafe { heap.alloc(layout).unwrap() };
+    let filler = heap.clone();
+    let mut live = HashSet::new();
+    let mut dead = HashSet::new();
+
+    for i in 0..20 {
+        let ptr = unsafe { filler.alloc(layout).unwrap() };
+
+        if i % 2 == 0 {
+            live.insert(ptr as usize);
+        } else {
+            dead.insert(ptr as usize);
+        }
+    }
+
+    let large = unsafe { filler.alloc(Layout::new::<Large>()).unwrap() };
+
+    dead.insert(large as usize);
+
+    // hands the blocks back so the sweep visits them
+    drop(filler);
+
+    let reported = unsafe {
+        heap.sweep_reporting_unmarked(mark, || {
+            for ptr in live.iter() {
+                Heap::mark(*ptr as *mut u8, layout, mark).unwrap();
+            }
+        })
+    };
+
+    let reported: HashSet<usize> = reported.into_iter().map(|(ptr, _)| ptr as usize).collect();
+
+    assert!(reported.is_disjoint(&live));
+    assert!(!reported.contains(&(held as usize)));
+    assert_eq!(reported, dead);
+
+    // survivors are only reported once they go unmarked
+    let mark = NonZero::new(2).unwrap();
+    let reported = unsafe { heap.sweep_reporting_unmarked(mark, || {}) };
+    let reported: HashSet<usize> = reported.into_iter().map(|(ptr, _)| ptr as usize).collect();
+
+    assert_eq!(reported, live);
+}
+
+#[test]
+fn global_collect_reclaims_unmarked_objects() {
+    use nimix::NimixGlobal;
+    use std::alloc::GlobalAlloc;
+
+    let global = NimixGlobal::new();
+    let mark = NonZero::new(1).unwrap();
+    let layout = line_layout();
+
+    unsafe {
+        let live = global.alloc(layout);
+        let dead = global.alloc(layout);
+
+        assert!(!live.is_null() && !dead.is_null());
+
+        std::ptr::write_bytes(live, 7, layout.size());
+        Heap::mark(live, layout, mark).unwrap();
+        global.collect(mark);
+
+        // the unmarked object's line is handed out again
+        let reused = (0..200).any(|_| global.alloc(layout) == dead);
+
+        assert!(reused);
+        assert!(std::slice::from_raw_parts(live, layout.size()).iter().all(|b| *b == 7));
+
+        // freeing heap objects is left to collect
+        global.dealloc(live, layout);
+        assert_eq!(*live, 7);
+    }
+}
+
+#[test]
+fn block_handle_reports_marked_lines() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let objects: Vec<*mut u8> = (0..10).map(|_| unsafe { heap.alloc(layout).unwrap() }).collect();
+
+    for obj in objects.iter() {
+        unsafe { Heap::mark(*obj, layout, mark).unwrap() };
+    }
+
+    // an interior pointer finds the same block
+    let handle = heap.block_for(unsafe { objects[3].add(100) }).unwrap();
+
+    assert_eq!(handle.id(), heap.block_for(objects[0]).unwrap().id());
+    assert_eq!(handle.mark(), 1);
+    assert_eq!(handle.age(), 0);
+    assert_eq!(handle.occupied_lines(), 10);
+    assert_eq!(handle.line_marks().iter().filter(|mark| **mark == 1).count(), 10);
+    // the objects were bumped down from the top of the block
+    assert_eq!(handle.largest_hole(), (handle.line_marks().len() - 10) * LINE_SIZE);
+
+    let large = unsafe { heap.alloc(Layout::new::<Large>()).unwrap() };
+    let local = 0u8;
+
+    assert!(heap.block_for(large).is_none());
+    assert!(heap.block_for(&local).is_none());
+}
+
+#[test]
+fn alloc_zeroed_clears_recycled_holes() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let filler = heap.clone();
+    let mut garbage = vec![];
+
+    for i in 0..LINE_COUNT {
+        let ptr = unsafe { filler.alloc(layout).unwrap() };
+
+        unsafe { std::ptr::write_bytes(ptr, 0xAA, layout.size()) };
+
+        if i % 8 == 0 {
+            unsafe { Heap::mark(ptr, layout, mark).unwrap() };
+        } else {
+            garbage.push(ptr);
+        }
+    }
+
+    drop(filler);
+    unsafe { heap.sweep(mark, || {}) };
+
+    let large = Layout::new::<Large>();
+    let mut reused = false;
+
+    for _ in 0..40 {
+        let ptr = unsafe { heap.alloc_zeroed(layout).unwrap() };
+        let bytes = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+
+        reused |= garbage.contains(&ptr);
+        assert!(bytes.iter().all(|b| *b == 0));
+    }
+
+    let ptr = unsafe { heap.alloc_zeroed(large).unwrap() };
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, large.size()) };
+
+    assert!(reused);
+    assert!(bytes.iter().all(|b| *b == 0));
+}
+
+#[test]
+fn free_large_releases_one_object() {
+    let heap = Heap::new();
+    let layout = Layout::new::<Large>();
+    let small = unsafe { heap.alloc(Layout::new::<u64>()).unwrap() };
+    let first = unsafe { heap.alloc(layout).unwrap() };
+    let second = unsafe { heap.alloc(layout).unwrap() };
+    let size = heap.size();
+
+    unsafe {
+        second.write(9);
+        heap.free_large(first).unwrap();
+    }
+
+    // the object plus its header, padded to the object's alignment
+    assert_eq!(size - heap.size(), std::mem::size_of::<Large>() + 8);
+    assert_eq!(unsafe { *second }, 9);
+    assert!(unsafe { heap.free_large(first) }.is_err());
+    assert!(unsafe { heap.free_large(small) }.is_err());
+}
+
+#[test]
+fn stats_track_block_lists() {
+    use nimix::HeapStats;
+
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let large = Layout::new::<Large>();
+    let filler = heap.clone();
+    let mut objects = vec![];
+
+    assert_eq!(heap.stats(), HeapStats::default());
+
+    for _ in 0..(3 * LINE_COUNT) {
+        objects.push(unsafe { filler.alloc(layout).unwrap() });
+    }
+
+    unsafe { filler.alloc(large).unwrap() };
+    drop(filler);
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.block_count, 3);
+    assert_eq!(stats.rest_block_count + stats.recycle_block_count, 3);
+    assert_eq!(stats.large_object_count, 1);
+    assert_eq!(stats.large_bytes, std::mem::size_of::<Large>() + 8);
+
+    // keep one object in the first block, leaving a hole above and below it
+    unsafe {
+        Heap::mark(objects[LINE_COUNT / 2], layout, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.block_count, 3);
+    assert_eq!(stats.free_block_count, 2);
+    assert_eq!(stats.recycle_block_count, 1);
+    assert_eq!(stats.rest_block_count, 0);
+    assert_eq!(stats.large_object_count, 0);
+    assert_eq!(stats.large_bytes, 0);
+}
+
+#[test]
+fn sweep_stats_count_dead_blocks() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let large = Layout::new::<Large>();
+    let filler = heap.clone();
+    let mut objects = vec![];
+
+    for _ in 0..(4 * LINE_COUNT) {
+        objects.push(unsafe { filler.alloc(layout).unwrap() });
+    }
+
+    let live_large = unsafe { filler.alloc(large).unwrap() };
+
+    unsafe { filler.alloc(large).unwrap() };
+    drop(filler);
+
+    // half the objects are live, those filling the first and third block
+    for block in objects.chunks(LINE_COUNT).step_by(2) {
+        for obj in block.iter() {
+            unsafe { Heap::mark(*obj, layout, mark).unwrap() };
+        }
+    }
+
+    let stats = unsafe {
+        Heap::mark(live_large, large, mark).unwrap();
+        heap.sweep(mark, || {})
+    };
+
+    assert_eq!(stats.blocks_freed, 2);
+    assert_eq!(stats.blocks_recycled, 0);
+    assert_eq!(stats.large_freed, 1);
+    assert_eq!(stats.large_bytes_freed, std::mem::size_of::<Large>() + 8);
+}
+
+#[test]
+fn for_each_live_reports_marked_line_runs() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let large = Layout::new::<Large>();
+    let objects: Vec<*mut u8> = (0..10).map(|_| unsafe { heap.alloc(layout).unwrap() }).collect();
+    let large_obj = unsafe { heap.alloc(large).unwrap() };
+
+    unsafe {
+        // two neighbours make up one run, the other object a run of its own
+        for i in [2, 3, 7] {
+            Heap::mark(objects[i], layout, mark).unwrap();
+        }
+
+        Heap::mark(large_obj, large, mark).unwrap();
+    }
+
+    let mut ranges = vec![];
+
+    heap.for_each_live(mark, |ptr, len| ranges.push((ptr as usize, len)));
+    ranges.sort();
+
+    // objects are bumped down from the top of the block
+    let mut expected = vec![
+        (objects[7] as usize, LINE_SIZE),
+        (objects[3] as usize, 2 * LINE_SIZE),
+        (large_obj as usize, large.size()),
+    ];
+
+    expected.sort();
+    assert_eq!(ranges, expected);
+}
+
+#[cfg(feature = "dual-mark")]
+#[test]
+fn sweep_color_goes_by_one_color() {
+    use nimix::Color;
+
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let large = Layout::new::<Large>();
+    let filler = heap.clone();
+    let mut objects = vec![];
+
+    for _ in 0..(4 * LINE_COUNT) {
+        objects.push(unsafe { filler.alloc(layout).unwrap() });
+    }
+
+    let large_objects = [
+        unsafe { filler.alloc(large).unwrap() },
+        unsafe { filler.alloc(large).unwrap() },
+    ];
+
+    drop(filler);
+
+    unsafe {
+        // the primary color keeps everything alive, the second color only the
+        // first block and large object
+        for obj in objects.iter() {
+            Heap::mark(*obj, layout, mark).unwrap();
+        }
+
+        for obj in large_objects {
+            Heap::mark(obj, large, mark).unwrap();
+        }
+
+        for obj in objects[..LINE_COUNT].iter() {
+            Heap::mark_color(*obj, layout, Color::Secondary, mark).unwrap();
+        }
+
+        Heap::mark_color(large_objects[0], large, Color::Secondary, mark).unwrap();
+    }
+
+    let stats = unsafe { heap.sweep_color(Color::Secondary, mark, || {}) };
+
+    assert_eq!(stats.blocks_freed, 3);
+    assert_eq!(stats.large_freed, 1);
+    // sweeping by the primary color ignores the second one
+    let next = NonZero::new(2).unwrap();
+
+    unsafe {
+        Heap::mark(objects[0], layout, next).unwrap();
+        Heap::mark_color(large_objects[0], large, Color::Secondary, next).unwrap();
+
+        let stats = heap.sweep_color(Color::Primary, next, || {});
+
+        assert_eq!(stats.blocks_freed, 0);
+        assert_eq!(stats.large_freed, 1);
+    }
+}
+
+#[test]
+fn finalizers_run_once_for_dead_objects() {
+    use std::sync::{Arc, Mutex};
+
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let large = Layout::new::<Large>();
+    let filler = heap.clone();
+    let mut objects: Vec<(*mut u8, Layout)> = (0..4)
+        .map(|_| (unsafe { filler.alloc(layout).unwrap() }, layout))
+        .collect();
+
+    objects.push((unsafe { filler.alloc(large).unwrap() }, large));
+    drop(filler);
+
+    let fired = Arc::new(Mutex::new(vec![]));
+
+    for (obj, _) in objects.iter() {
+        let fired = fired.clone();
+
+        unsafe { heap.register_finalizer(*obj, Box::new(move |ptr| fired.lock().unwrap().push(ptr as usize))) };
+    }
+
+    // the block holding the marked object is recycled, not freed
+    let (live, _) = objects[1];
+
+    unsafe {
+        Heap::mark(live, layout, mark).unwrap();
+        assert_eq!(heap.sweep(mark, || {}).blocks_recycled, 1);
+    }
+
+    let mut expected: Vec<usize> = objects
+        .iter()
+        .map(|(obj, _)| *obj as usize)
+        .filter(|obj| *obj != live as usize)
+        .collect();
+    let mut actual = fired.lock().unwrap().clone();
+
+    expected.sort();
+    actual.sort();
+    assert_eq!(actual, expected);
+
+    // the live object's finalizer is kept, the others don't run again
+    unsafe { heap.sweep(NonZero::new(2).unwrap(), || {}) };
+
+    assert_eq!(fired.lock().unwrap().len(), objects.len());
+    assert_eq!(fired.lock().unwrap().last(), Some(&(live as usize)));
+}
+
+#[test]
+fn finalizers_may_allocate_and_read_their_object() {
+    use std::sync::{Arc, Mutex};
+
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let layout = Layout::new::<u64>();
+    let filler = heap.clone();
+    let live = unsafe { filler.alloc(layout).unwrap() };
+    let dead = unsafe { filler.alloc(line_layout()).unwrap() } as *mut u64;
+    let dead_large = unsafe { filler.alloc(Layout::new::<Large>()).unwrap() } as *mut u64;
+
+    drop(filler);
+
+    let seen = Arc::new(Mutex::new(vec![]));
+
+    for (obj, value) in [(dead, 7), (dead_large, 9)] {
+        let seen = seen.clone();
+        let alloc_heap = heap.clone();
+
+        unsafe {
+            obj.write(value);
+            heap.register_finalizer(
+                obj as *const u8,
+                Box::new(move |ptr| {
+                    // the heap's blocks are unlocked, yet the object is intact
+                    let new = alloc_heap.alloc(layout).unwrap();
+
+                    seen.lock().unwrap().push((*(ptr as *const u64), new as usize));
+                }),
+            );
+        }
+    }
+
+    unsafe {
+        Heap::mark(live, layout, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    let mut seen = seen.lock().unwrap().clone();
+
+    seen.sort();
+    assert_eq!(seen.iter().map(|(value, _)| *value).collect::<Vec<_>>(), [7, 9]);
+    assert!(seen.iter().all(|(_, new)| *new != dead as usize && *new != dead_large as usize));
+}
+
+#[test]
+fn weak_handles_are_cleared_by_sweep() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let layout = line_layout();
+    let large = Layout::new::<Large>();
+    let filler = heap.clone();
+    let live = unsafe { filler.alloc(layout).unwrap() };
+    let dead = unsafe { filler.alloc(layout).unwrap() };
+    let dead_large = unsafe { filler.alloc(large).unwrap() };
+
+    drop(filler);
+
+    let (live_weak, dead_weak, large_weak) = unsafe {
+        (heap.register_weak(live), heap.register_weak(dead), heap.register_weak(dead_large))
+    };
+    let live_clone = live_weak.clone();
+
+    assert_eq!(dead_weak.get(), Some(dead as *const u8));
+
+    unsafe {
+        Heap::mark(live, layout, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    assert_eq!(live_weak.get(), Some(live as *const u8));
+    assert_eq!(live_clone.get(), Some(live as *const u8));
+    assert_eq!(dead_weak.get(), None);
+    assert_eq!(large_weak.get(), None);
+
+    unsafe { heap.sweep(NonZero::new(2).unwrap(), || {}) };
+
+    assert_eq!(live_clone.get(), None);
+}
+
+#[test]
+fn evacuating_sweep_compacts_sparse_blocks() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let filler = heap.clone();
+    let mut objects = vec![];
+
+    for i in 0..(4 * LINE_COUNT) {
+        let obj = unsafe { filler.alloc(layout).unwrap() };
+
+        unsafe { (obj as *mut u64).write(i as u64) };
+        objects.push(obj);
+    }
+
+    drop(filler);
+
+    let live: Vec<*mut u8> = objects.iter().copied().step_by(8).collect();
+    let before = heap.stats();
+    let mut moved = vec![];
+
+    let stats = unsafe {
+        heap.sweep_evacuating(
+            mark,
+            || {
+                for obj in live.iter() {
+                    heap.mark_if_unmarked(*obj, layout, mark).unwrap();
+                }
+            },
+            |old, new| moved.push((old as usize, new as usize)),
+        )
+    };
+    let after = heap.stats();
+
+    assert_eq!(before.rest_block_count + before.recycle_block_count, 4);
+    assert_eq!(after.rest_block_count + after.recycle_block_count, 1);
+    assert_eq!(stats.blocks_freed, 4);
+    assert_eq!(moved.len(), live.len());
+
+    for (old, new) in moved {
+        let index = objects.iter().position(|obj| *obj as usize == old).unwrap();
+
+        assert_eq!(unsafe { *(new as *const u64) }, index as u64);
+        assert!(heap.owns(new as *const u8));
+    }
+}
+
+#[test]
+fn evacuation_forwards_ids_weak_handles_and_finalizers() {
+    use std::sync::{Arc, Mutex};
+
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let layout = line_layout();
+    let filler = heap.clone();
+    let objects: Vec<(*mut u8, u64)> = (0..(4 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc_with_id(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    let live: Vec<(*mut u8, u64)> = objects.iter().copied().step_by(8).collect();
+    let weak: Vec<_> = live.iter().map(|(obj, _)| unsafe { heap.register_weak(*obj) }).collect();
+    let finalized = Arc::new(Mutex::new(vec![]));
+
+    for (obj, _) in live.iter() {
+        let finalized = finalized.clone();
+
+        unsafe { heap.register_finalizer(*obj, Box::new(move |ptr| finalized.lock().unwrap().push(ptr as usize))) };
+    }
+
+    let mut moved = vec![];
+
+    unsafe {
+        heap.sweep_evacuating(
+            mark,
+            || {
+                for (obj, _) in live.iter() {
+                    heap.mark_if_unmarked(*obj, layout, mark).unwrap();
+                }
+            },
+            |old, new| moved.push((old as usize, new as usize)),
+        )
+    };
+
+    assert_eq!(moved.len(), live.len());
+    assert!(finalized.lock().unwrap().is_empty());
+
+    for ((old, id), weak) in live.iter().zip(weak.iter()) {
+        let (_, new) = moved.iter().find(|(moved_from, _)| *moved_from == *old as usize).unwrap();
+
+        assert_eq!(heap.object_id(*new as *const u8), Some(*id));
+        assert_eq!(heap.object_id(*old), None);
+        assert_eq!(weak.get(), Some(*new as *const u8));
+    }
+
+    // the finalizers now run for the objects where they were moved to
+    unsafe { heap.sweep(NonZero::new(2).unwrap(), || {}) };
+
+    let mut finalized = finalized.lock().unwrap().clone();
+    let mut expected: Vec<usize> = moved.iter().map(|(_, new)| *new).collect();
+
+    finalized.sort();
+    expected.sort();
+    assert_eq!(finalized, expected);
+}
+
+#[test]
+fn evacuated_immortal_objects_stay_immortal() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(4 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    let live: Vec<*mut u8> = objects.iter().copied().step_by(8).collect();
+    let immortal = live[0];
+    let mut moved = None;
+
+    unsafe {
+        (immortal as *mut u64).write(42);
+        heap.immortalize(immortal, layout).unwrap();
+        heap.sweep_evacuating(
+            mark,
+            || {
+                for obj in live.iter() {
+                    heap.mark_if_unmarked(*obj, layout, mark).unwrap();
+                }
+            },
+            |old, new| {
+                if std::ptr::eq(old, immortal) {
+                    moved = Some(new);
+                }
+            },
+        );
+    }
+
+    let new = moved.expect("the immortal object was evacuated");
+
+    // nothing is marked, the immortal object is kept where it was moved to
+    unsafe { heap.sweep(NonZero::new(2).unwrap(), || {}) };
+
+    assert_eq!(heap.allocation_size(new), Some(LINE_SIZE));
+    assert_eq!(unsafe { *(new as *const u64) }, 42);
+}
+
+#[test]
+fn pinned_objects_are_not_evacuated() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(4 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    let live: Vec<*mut u8> = objects.iter().copied().step_by(8).collect();
+    let pinned = live[0];
+    let mut moved = vec![];
+
+    heap.pin(pinned).unwrap();
+
+    let stats = unsafe {
+        heap.sweep_evacuating(
+            mark,
+            || {
+                for obj in live.iter() {
+                    heap.mark_if_unmarked(*obj, layout, mark).unwrap();
+                }
+            },
+            |old, _| moved.push(old as usize),
+        )
+    };
+
+    // the pinned object's block stays, the others are evacuated
+    assert_eq!(stats.blocks_freed, 3);
+    assert!(!moved.contains(&(pinned as usize)));
+    let block = heap.block_for(pinned).unwrap();
+
+    assert_eq!(block.line_marks().iter().filter(|line| **line == mark.get()).count(), LINE_COUNT.div_ceil(8));
+
+    heap.unpin(pinned).unwrap();
+    assert!(heap.unpin(pinned).is_err());
+}
+
+#[test]
+fn untraced_marks_keep_their_block_in_place() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(4 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    let live: Vec<*mut u8> = objects.iter().copied().step_by(8).collect();
+    let untraced = live[0];
+    let mut moved = vec![];
+
+    let stats = unsafe {
+        heap.sweep_evacuating(
+            mark,
+            || {
+                Heap::mark(untraced, layout, mark).unwrap();
+
+                for obj in live[1..].iter() {
+                    heap.mark_if_unmarked(*obj, layout, mark).unwrap();
+                }
+            },
+            |old, _| moved.push(old as usize),
+        )
+    };
+
+    // nothing in the block with the untraced object is known to be all that
+    // is live in it, so none of it moves
+    let block = untraced as usize / BLOCK_SIZE;
+
+    assert_eq!(stats.blocks_freed, 3);
+    assert!(moved.iter().all(|old| old / BLOCK_SIZE != block));
+    assert!(heap.block_for(untraced).is_some());
+}
+
+#[test]
+fn untraced_object_sharing_a_traced_line_is_not_evacuated() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // two objects to a line
+    let layout = Layout::from_size_align(LINE_SIZE / 2, LINE_SIZE / 2).unwrap();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(8 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    let (traced, untraced) = (objects[0], objects[1]);
+    let mut moved = vec![];
+
+    assert_eq!(traced as usize / LINE_SIZE, untraced as usize / LINE_SIZE);
+
+    let stats = unsafe {
+        (untraced as *mut u64).write(0xbeef);
+        heap.sweep_evacuating(
+            mark,
+            || {
+                heap.mark_if_unmarked(traced, layout, mark).unwrap();
+                Heap::mark(untraced, layout, mark).unwrap();
+            },
+            |old, _| moved.push(old as usize),
+        )
+    };
+
+    assert_eq!(stats.blocks_freed, 3);
+    assert!(moved.is_empty());
+
+    // reuse every block the sweep freed
+    for _ in 0..(8 * LINE_COUNT) {
+        unsafe { heap.alloc_zeroed(layout).unwrap() };
+    }
+
+    assert_eq!(unsafe { *(untraced as *const u64) }, 0xbeef);
+}
+
+#[test]
+fn no_free_blocks_are_kept_when_configured() {
+    use nimix::HeapConfig;
+
+    let heap = Heap::with_config(HeapConfig {
+        max_free_blocks: 0,
+        ..HeapConfig::default()
+    });
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(8 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    assert_eq!(heap.stats().block_count, 8);
+
+    unsafe {
+        // only the first block holds anything live
+        Heap::mark(objects[0], layout, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.block_count, 1);
+    assert_eq!(stats.free_block_count, 0);
+}
+
+// Sweeps two blocks in which every `stride`th line is marked, returning how
+// many blocks were recycled and how many were left to rest.
+fn sweep_striped_blocks(heap: &Heap, stride: usize) -> (usize, usize) {
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(2 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    unsafe {
+        for obj in objects.iter().step_by(stride) {
+            Heap::mark(*obj, layout, mark).unwrap();
+        }
+
+        heap.sweep(mark, || {});
+    }
+
+    let stats = heap.stats();
+
+    (stats.recycle_block_count, stats.rest_block_count)
+}
+
+#[test]
+fn recycle_hole_min_of_zero_recycles_any_hole() {
+    use nimix::HeapConfig;
+
+    // the holes between marked lines are too small to be recycled by default
+    assert_eq!(sweep_striped_blocks(&Heap::new(), 4), (0, 2));
+
+    let heap = Heap::with_config(HeapConfig {
+        recycle_hole_min: 0,
+        ..HeapConfig::default()
+    });
+
+    assert_eq!(sweep_striped_blocks(&heap, 4), (2, 0));
+}
+
+#[test]
+fn recycle_hole_min_above_capacity_never_recycles() {
+    use nimix::HeapConfig;
+
+    // a single marked line leaves plenty of room
+    assert_eq!(sweep_striped_blocks(&Heap::new(), LINE_COUNT), (2, 0));
+
+    let heap = Heap::with_config(HeapConfig {
+        recycle_hole_min: BLOCK_SIZE + 1,
+        ..HeapConfig::default()
+    });
+
+    assert_eq!(sweep_striped_blocks(&heap, LINE_COUNT), (0, 2));
+}
+
+// Counts what a heap requests from its backing.
+mod counting {
+    use nimix::{Backing, SystemBacking};
+    use std::alloc::Layout;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    pub struct Counts {
+        pub allocs: AtomicUsize,
+        pub deallocs: AtomicUsize,
+    }
+
+    pub struct Counting(pub Arc<Counts>);
+
+    impl Backing for Counting {
+        fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.0.allocs.fetch_add(1, Ordering::Relaxed);
+            SystemBacking.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.0.deallocs.fetch_add(1, Ordering::Relaxed);
+            SystemBacking.dealloc(ptr, layout)
+        }
+    }
+}
+
+#[test]
+fn backing_serves_blocks_and_large_objects() {
+    use counting::{Counting, Counts};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    let counts = Arc::new(Counts::default());
+    let heap = Heap::with_backing(Counting(counts.clone()));
+    let layout = line_layout();
+    let large = Layout::new::<Large>();
+
+    // with the block pool, the blocks of a dropped heap are up for grabs, but
+    // not by a heap with a backing of its own
+    let other = Heap::new();
+
+    for _ in 0..(3 * LINE_COUNT) {
+        unsafe { other.alloc(layout).unwrap() };
+    }
+
+    drop(other);
+
+    unsafe {
+        for _ in 0..(3 * LINE_COUNT) {
+            heap.alloc(layout).unwrap();
+        }
+
+        heap.alloc(large).unwrap();
+        heap.alloc(large).unwrap();
+    }
+
+    let stats = heap.stats();
+
+    assert_eq!(counts.allocs.load(Ordering::Relaxed), stats.block_count + stats.large_object_count);
+    assert_eq!(stats.large_object_count, 2);
+
+    drop(heap);
+
+    assert_eq!(counts.deallocs.load(Ordering::Relaxed), counts.allocs.load(Ordering::Relaxed));
+}
+
+#[test]
+fn preallocated_blocks_serve_first_allocations() {
+    use counting::{Counting, Counts};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    let counts = Arc::new(Counts::default());
+    let heap = Heap::with_backing(Counting(counts.clone()));
+    // each object takes up a line of its own
+    let layout = line_layout();
+
+    assert_eq!(heap.prealloc_best_effort(4), 4);
+    assert_eq!(heap.size(), 4 * BLOCK_SIZE);
+    assert_eq!(counts.allocs.load(Ordering::Relaxed), 4);
+
+    for _ in 0..(4 * LINE_COUNT) {
+        unsafe { heap.alloc(layout).unwrap() };
+    }
+
+    // every allocation was served from the blocks requested up front
+    assert_eq!(counts.allocs.load(Ordering::Relaxed), 4);
+    assert_eq!(heap.size(), 4 * BLOCK_SIZE);
+}
+
+#[test]
+fn allocation_goes_on_during_background_sweep() {
+    let heap = Heap::new();
+    let layout = Layout::new::<[u64; 4]>();
+    let mark = NonZero::new(1).unwrap();
+    let filler = heap.clone();
+    let mut live = vec![];
+
+    for i in 0..(200 * LINE_COUNT) {
+        let ptr = unsafe { filler.alloc(layout).unwrap() };
+
+        if i % 9 == 0 {
+            unsafe {
+                (ptr as *mut u64).write(i as u64);
+                Heap::mark(ptr, layout, mark).unwrap();
+            }
+
+            live.push((ptr as usize, i as u64));
+        }
+    }
+
+    drop(filler);
+
+    let sweep = unsafe { heap.sweep_in_background(mark) };
+    let threads: Vec<_> = (0..4u64)
+        .map(|t| {
+            let heap = heap.clone();
+
+            std::thread::spawn(move || {
+                (0..20_000u64)
+                    .map(|i| {
+                        let ptr = unsafe { heap.alloc(layout).unwrap() };
+                        let value = (t << 32) | i;
+
+                        unsafe { (ptr as *mut u64).write(value) };
+
+                        (ptr as usize, value)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let allocated: Vec<(usize, u64)> = threads
+        .into_iter()
+        .flat_map(|thread| thread.join().unwrap())
+        .collect();
+
+    sweep.join().unwrap();
+
+    // nothing allocated during the sweep was handed out twice, or over a
+    // survivor of the sweep
+    let mut addrs: Vec<usize> = allocated.iter().chain(live.iter()).map(|&(addr, _)| addr).collect();
+
+    addrs.sort_unstable();
+    addrs.dedup();
+    assert_eq!(addrs.len(), allocated.len() + live.len());
+
+    for &(addr, value) in allocated.iter().chain(live.iter()) {
+        assert_eq!(unsafe { (addr as *const u64).read() }, value);
+    }
+}
+
+#[test]
+fn sweep_callback_runs_once_before_reclaiming() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let layout = line_layout();
+    let filler = heap.clone();
+    let dead = unsafe { filler.alloc(layout).unwrap() };
+
+    drop(filler);
+
+    let weak = unsafe { heap.register_weak(dead) };
+    let mut calls = 0;
+
+    unsafe {
+        heap.sweep(mark, || {
+            calls += 1;
+
+            // the sweep has started, but nothing was reclaimed yet
+            assert_eq!(weak.get(), Some(dead as *const u8));
+        })
+    };
+
+    assert_eq!(calls, 1);
+    assert_eq!(weak.get(), None);
+}
+
+// records the start and end of every sweep of the heap it observes
+struct SweepRecorder(std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>);
+
+impl nimix::HeapObserver for SweepRecorder {
+    fn on_sweep_start(&self, _: NonZero<u8>) {
+        self.0.lock().unwrap().push("sweep_start");
+    }
+
+    fn on_sweep_end(&self, _: NonZero<u8>) {
+        self.0.lock().unwrap().push("sweep_end");
+    }
+}
+
+// Checks that `sweep` calls the callback it's given once, after the sweep has
+// started and before anything is reclaimed.
+fn assert_callback_runs_mid_sweep(sweep: impl FnOnce(&Heap, NonZero<u8>, &dyn Fn())) {
+    let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let heap = Heap::new().with_observer(Box::new(SweepRecorder(events.clone())));
+    let mark = NonZero::new(1).unwrap();
+    let filler = heap.clone();
+    let dead = unsafe { filler.alloc(line_layout()).unwrap() };
+
+    drop(filler);
+
+    let weak = unsafe { heap.register_weak(dead) };
+
+    sweep(&heap, mark, &|| {
+        events.lock().unwrap().push("callback");
+
+        // the blocks are locked, but nothing was reclaimed yet
+        assert_eq!(weak.get(), Some(dead as *const u8));
+    });
+
+    assert_eq!(*events.lock().unwrap(), ["sweep_start", "callback", "sweep_end"]);
+    assert_eq!(weak.get(), None);
+}
+
+#[test]
+fn evacuating_sweep_callback_runs_mid_sweep() {
+    assert_callback_runs_mid_sweep(|heap, mark, cb| unsafe {
+        heap.sweep_evacuating(mark, cb, |_, _| {});
+    });
+}
+
+#[cfg(feature = "dual-mark")]
+#[test]
+fn color_sweep_callback_runs_mid_sweep() {
+    use nimix::Color;
+
+    for live in [Color::Primary, Color::Secondary] {
+        assert_callback_runs_mid_sweep(|heap, mark, cb| unsafe {
+            heap.sweep_color(live, mark, cb);
+        });
+    }
+}
+
+#[test]
+fn upward_allocation_hands_out_increasing_addresses() {
+    use nimix::HeapConfig;
+
+    let heap = Heap::with_config(HeapConfig {
+        upward_allocation: true,
+        ..HeapConfig::default()
+    });
+    let mark = NonZero::new(1).unwrap();
+    let layout = Layout::new::<[u64; 4]>();
+    let block_of = |ptr: *mut u8| ptr as usize & !(BLOCK_SIZE - 1);
+    let assert_increasing = |objects: &[*mut u8]| {
+        for pair in objects.windows(2) {
+            if block_of(pair[0]) == block_of(pair[1]) {
+                assert!(pair[0] < pair[1]);
+            }
+        }
+    };
+
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(4 * LINE_COUNT * 4))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+    assert_increasing(&objects);
+
+    // the holes left between survivors are walked from the bottom up as well
+    for obj in objects.iter().step_by(40) {
+        unsafe { Heap::mark(*obj, layout, mark).unwrap() };
+    }
+
+    unsafe { heap.sweep(mark, || {}) };
+
+    let refill: Vec<*mut u8> = (0..(4 * LINE_COUNT * 4))
+        .map(|_| unsafe { heap.alloc(layout).unwrap() })
+        .collect();
+
+    assert_increasing(&refill);
+    assert!(refill.iter().any(|obj| objects.contains(obj)));
+}
+
+#[test]
+fn medium_object_keeps_its_last_line() {
+    // without conservative lines only the marks keep the object's last line
+    // from being handed out again
+    let heap = Heap::new().with_conservative_lines(0);
+    let mark = NonZero::new(1).unwrap();
+    let layout = Layout::from_size_align(2 * LINE_SIZE + LINE_SIZE / 4, 8).unwrap();
+    let filler = heap.clone();
+    let obj = unsafe { filler.alloc(layout).unwrap() };
+
+    drop(filler);
+
+    unsafe {
+        obj.write_bytes(7, layout.size());
+        Heap::mark(obj, layout, mark).unwrap();
+    }
+
+    let block = heap.block_for(obj).unwrap();
+    let first = (obj as usize % BLOCK_SIZE) / LINE_SIZE;
+    let marked: Vec<usize> = (0..LINE_COUNT)
+        .filter(|&line| block.line_marks()[line] == mark.get())
+        .collect();
+
+    // the object spans three lines, and is marked in all of them
+    assert_eq!(marked, vec![first, first + 1, first + 2]);
+    assert_ne!(obj as usize % LINE_SIZE, 0);
+
+    let stats = unsafe { heap.sweep(mark, || {}) };
+
+    assert_eq!(stats.blocks_freed, 0);
+
+    let small = Layout::new::<u64>();
+    let range = obj as usize..obj as usize + layout.size();
+
+    for _ in 0..(2 * LINE_COUNT * 16) {
+        let ptr = unsafe { heap.alloc(small).unwrap() };
+
+        assert!(!range.contains(&(ptr as usize)));
+        unsafe { ptr.write_bytes(0, small.size()) };
+    }
+
+    assert!((0..layout.size()).all(|i| unsafe { *obj.add(i) } == 7));
+}
+
+#[test]
+fn verify_no_overlap_catches_misplaced_objects() {
+    // without a conservative line, the tail of a small object that straddles
+    // a line boundary is reclaimed as if nothing were in it
+    let heap = Heap::new().with_conservative_lines(0);
+    let mark = NonZero::new(1).unwrap();
+    let layout = Layout::new::<[u64; 3]>();
+    let count = LINE_COUNT * LINE_SIZE / layout.size();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..count)
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    // the lowest one, so the hole above it is large enough to be recycled
+    let straddler = *objects
+        .iter()
+        .rev()
+        .find(|obj| **obj as usize % LINE_SIZE + layout.size() > LINE_SIZE)
+        .unwrap();
+
+    unsafe { heap.mark_if_unmarked(straddler, layout, mark).unwrap() };
+
+    assert!(heap.verify_no_overlap(mark).is_ok());
+
+    unsafe { heap.sweep(mark, || {}) };
+
+    // objects of another size, so they don't line up with the swept ones
+    let small = Layout::new::<u64>();
+    let range = straddler as usize..straddler as usize + layout.size();
+    let misplaced = (0..(BLOCK_SIZE / small.size()))
+        .map(|_| unsafe { heap.alloc(small).unwrap() })
+        .find(|obj| range.contains(&(*obj as usize)))
+        .expect("an allocation was placed over the straddling object");
+
+    // the next cycle finds both objects live
+    let mark = NonZero::new(2).unwrap();
+
+    unsafe {
+        heap.mark_if_unmarked(straddler, layout, mark).unwrap();
+        heap.mark_if_unmarked(misplaced, small, mark).unwrap();
+    }
+
+    let err = heap.verify_no_overlap(mark).unwrap_err();
+
+    assert_eq!(err.first, (straddler as *const u8, layout.size()));
+    assert_eq!(err.second, (misplaced as *const u8, small.size()));
+}
+
+#[test]
+fn swept_large_objects_are_reused() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let layout = Layout::from_size_align(BLOCK_SIZE * 4, 16).unwrap();
+    let first = unsafe { heap.alloc(layout).unwrap() };
+    let size = heap.size();
+
+    unsafe { heap.sweep(mark, || {}) };
+
+    // the swept object's block is kept by the heap, if no longer in use
+    let stats = heap.stats();
+
+    assert_eq!(heap.size(), size);
+    assert_eq!(stats.large_bytes, 0);
+    assert_eq!(stats.pooled_large_bytes, size);
+
+    // and is handed out again rather than a new one
+    let second = unsafe { heap.alloc(layout).unwrap() };
+
+    assert_eq!(second, first);
+    assert_eq!(heap.size(), size);
+    assert_eq!(heap.stats().pooled_large_bytes, 0);
+}
+
+#[test]
+fn cancelled_sweep_leaves_the_heap_usable() {
+    use nimix::SweepProgress;
+
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let layout = line_layout();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(4 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    // the first two blocks are live, the other two are not
+    for obj in [objects[0], objects[LINE_COUNT]] {
+        unsafe { Heap::mark(obj, layout, mark).unwrap() };
+    }
+
+    assert!(!heap.cancel_sweep());
+    assert_eq!(unsafe { heap.sweep_incremental(mark, 1) }, SweepProgress::InProgress);
+    assert!(heap.cancel_sweep());
+
+    let alloc_heap = heap.clone();
+    let obj = unsafe { alloc_heap.alloc(layout).unwrap() };
+
+    unsafe {
+        (obj as *mut u64).write(42);
+        Heap::mark(obj, layout, mark).unwrap();
+    }
+
+    drop(alloc_heap);
+
+    // the sweep that follows starts over, and sorts out every block
+    let stats = unsafe { heap.sweep(mark, || {}) };
+
+    assert_eq!(stats.blocks_recycled, 3);
+    assert_eq!(heap.stats().recycle_block_count, 3);
+    assert_eq!(heap.stats().rest_block_count, 0);
+    assert_eq!(unsafe { *(obj as *const u64) }, 42);
+    assert!(heap.block_for(objects[0]).is_some());
+}
+
+#[test]
+fn trim_releases_only_free_blocks() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..(6 * LINE_COUNT))
+        .map(|_| unsafe { filler.alloc(layout).unwrap() })
+        .collect();
+
+    drop(filler);
+
+    // keep something alive in two of the blocks
+    for obj in [objects[0], objects[5 * LINE_COUNT]] {
+        unsafe { Heap::mark(obj, layout, mark).unwrap() };
+    }
+
+    unsafe { heap.sweep(mark, || {}) };
+
+    let before = heap.stats();
+
+    assert_eq!(before.free_block_count, 4);
+    assert_eq!(heap.trim(), 4 * BLOCK_SIZE);
+
+    let after = heap.stats();
+
+    assert_eq!(after.free_block_count, 0);
+    assert_eq!(after.block_count, after.rest_block_count + after.recycle_block_count);
+    assert_eq!(after.block_count, 2);
+    assert_eq!(heap.size(), 2 * BLOCK_SIZE);
+    assert_eq!(heap.trim(), 0);
+
+    // the reserve isn't trimmed
+    let reserved = Heap::new().with_min_block_count(2).unwrap();
+
+    assert_eq!(reserved.trim(), 0);
+    assert_eq!(reserved.stats().free_block_count, 2);
+}
+
+#[test]
+fn reset_reclaims_everything_and_keeps_blocks() {
+    use nimix::HeapConfig;
+
+    let heap = Heap::with_config(HeapConfig {
+        max_free_blocks: 8,
+        ..HeapConfig::default()
+    });
+    // each object takes up a line of its own
+    let layout = line_layout();
+    let large = Layout::from_size_align(BLOCK_SIZE * 2, 8).unwrap();
+    let mark = NonZero::new(1).unwrap();
+
+    let mut objects = vec![];
+
+    for _ in 0..(12 * LINE_COUNT) {
+        let obj = unsafe { heap.alloc(layout).unwrap() };
+
+        // marks don't keep anything alive through a reset
+        unsafe { Heap::mark(obj, layout, mark).unwrap() };
+        objects.push(obj);
+    }
+
+    let large_obj = unsafe { heap.alloc(large).unwrap() };
+    let weak = unsafe { heap.register_weak(large_obj) };
+
+    assert_eq!(heap.stats().large_object_count, 1);
+
+    unsafe { heap.reset() };
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.large_object_count, 0);
+    assert_eq!(stats.large_bytes, 0);
+    assert_eq!(stats.free_block_count, 8);
+    assert_eq!(stats.block_count, 8);
+    assert_eq!(heap.used(), 0);
+    assert_eq!(weak.get(), None);
+
+    // the kept blocks are empty, and serve new allocations right away
+    for _ in 0..(8 * LINE_COUNT) {
+        let obj = unsafe { heap.alloc(layout).unwrap() };
+
+        assert!(objects.contains(&obj));
+    }
+
+    assert_eq!(heap.stats().block_count, 8);
+}
+
+#[test]
+fn alloc_counts_split_allocations_by_size_class() {
+    let heap = Heap::new();
+    let small = Layout::new::<Point>();
+    let medium = Layout::from_size_align(512, 8).unwrap();
+    let large = Layout::from_size_align(BLOCK_SIZE * 2, 8).unwrap();
+
+    for _ in 0..300 {
+        unsafe { heap.alloc(small).unwrap() };
+    }
+    for _ in 0..70 {
+        unsafe { heap.alloc(medium).unwrap() };
+    }
+    for _ in 0..5 {
+        unsafe { heap.alloc(large).unwrap() };
+    }
+
+    let counts = heap.alloc_counts();
+
+    assert_eq!(counts.small, 300);
+    assert_eq!(counts.medium, 70);
+    assert_eq!(counts.large, 5);
+
+    // allocations through clones are counted by the heap they share
+    unsafe { heap.clone().alloc(small).unwrap() };
+
+    assert_eq!(heap.alloc_counts().small, 301);
+}
+
+#[test]
+fn live_bytes_counts_what_the_last_sweep_kept() {
+    let heap = Heap::new();
+    // line aligned, so each object takes up whole lines and nothing more
+    let small = Layout::from_size_align(LINE_SIZE, LINE_SIZE).unwrap();
+    let medium = Layout::from_size_align(LINE_SIZE * 2, LINE_SIZE).unwrap();
+    let mark = NonZero::new(1).unwrap();
+
+    assert_eq!(heap.live_bytes(), 0);
+
+    // the blocks a handle holds aren't swept, so allocate through a clone
+    // that hands them back once dropped
+    let allocator = heap.clone();
+    let mut live = 0;
+
+    for i in 0..600 {
+        let layout = if i % 2 == 0 { small } else { medium };
+        let obj = unsafe { allocator.alloc(layout).unwrap() };
+
+        if i % 3 == 0 {
+            unsafe { Heap::mark(obj, layout, mark).unwrap() };
+            live += layout.size();
+        }
+    }
+
+    drop(allocator);
+    unsafe { heap.sweep(mark, || {}) };
+
+    assert_eq!(heap.live_bytes(), live);
+
+    // allocating doesn't change what the last sweep found
+    unsafe { heap.alloc(small).unwrap() };
+
+    assert_eq!(heap.live_bytes(), live);
+}
+
+#[test]
+fn fragmentation_tells_scattered_from_compact_survivors() {
+    let layout = Layout::from_size_align(LINE_SIZE, LINE_SIZE).unwrap();
+    let mark = NonZero::new(1).unwrap();
+    let blocks = 20;
+    // fills `blocks` blocks, one object per line, marking those `live` picks
+    let fill = |heap: &Heap, live: &dyn Fn(usize) -> bool| {
+        let allocator = heap.clone();
+
+        for i in 0..(blocks * LINE_COUNT) {
+            let obj = unsafe { allocator.alloc(layout).unwrap() };
+
+            if live(i) {
+                unsafe { Heap::mark(obj, layout, mark).unwrap() };
+            }
+        }
+    };
+
+    let scattered = Heap::new();
+
+    assert_eq!(scattered.fragmentation(), 0.0);
+
+    fill(&scattered, &|i| i % 2 == 0);
+    unsafe { scattered.sweep(mark, || {}) };
+
+    assert!(scattered.fragmentation() > 0.9);
+
+    // as many survivors, packed into the first half of the blocks
+    let compact = Heap::new();
+
+    fill(&compact, &|i| i < blocks * LINE_COUNT / 2);
+    unsafe { compact.sweep(mark, || {}) };
+
+    assert!(compact.fragmentation() < 0.1);
+}
+
+#[test]
+fn contains_accepts_interior_pointers_only() {
+    static OUTSIDE: u64 = 0;
+
+    let heap = Heap::new();
+    let medium = Layout::from_size_align(256, 8).unwrap();
+    let large = Layout::from_size_align(BLOCK_SIZE * 2, 8).unwrap();
+    let on_stack = 0u64;
+
+    for layout in [Layout::new::<Point>(), medium, large] {
+        let obj = unsafe { heap.alloc(layout).unwrap() };
+
+        assert!(heap.contains(obj));
+        assert!(heap.contains(obj.wrapping_add(layout.size() - 1)));
+    }
+
+    let large_obj = unsafe { heap.alloc(large).unwrap() };
+
+    assert!(!heap.contains(large_obj.wrapping_add(large.size())));
+    assert!(!heap.contains(&on_stack as *const u64 as *const u8));
+    assert!(!heap.contains(&OUTSIDE as *const u64 as *const u8));
+
+    // other heaps' objects belong to them alone
+    let other = Heap::new();
+    let obj = unsafe { other.alloc(Layout::new::<Point>()).unwrap() };
+
+    assert!(!heap.contains(obj));
+}
+
+#[test]
+fn allocation_size_spans_the_marked_lines() {
+    let heap = Heap::new();
+    let medium = Layout::from_size_align(300, 8).unwrap();
+    let large = Layout::from_size_align(BLOCK_SIZE * 2, 8).unwrap();
+    let mark = NonZero::new(1).unwrap();
+
+    let objects: Vec<*mut u8> = (0..3).map(|_| unsafe { heap.alloc(medium).unwrap() }).collect();
+    let obj = objects[1];
+
+    assert_eq!(heap.allocation_size(obj), None);
+
+    unsafe { Heap::mark(obj, medium, mark).unwrap() };
+
+    let start = obj as usize;
+    let end = start + medium.size();
+    let span = (end.div_ceil(LINE_SIZE) - start / LINE_SIZE) * LINE_SIZE;
+
+    assert_eq!(heap.allocation_size(obj), Some(span));
+    assert_eq!(heap.allocation_size(obj.wrapping_add(medium.size() - 1)), Some(span));
+
+    let large_obj = unsafe { heap.alloc(large).unwrap() };
+
+    assert_eq!(heap.allocation_size(large_obj.wrapping_add(100)), Some(large.size()));
+
+    let on_stack = 0u64;
+
+    assert_eq!(heap.allocation_size(&on_stack as *const u64 as *const u8), None);
+}
+
+#[test]
+fn typed_allocations_are_sized_and_aligned_for_their_type() {
+    let heap = Heap::new();
+
+    unsafe {
+        let value = heap.alloc_value::<u64>().unwrap();
+
+        assert_eq!(value as usize % std::mem::align_of::<u64>(), 0);
+
+        value.write(0xdead_beef);
+
+        let slice = heap.alloc_slice::<u32>(100).unwrap();
+
+        assert_eq!(slice as usize % std::mem::align_of::<u32>(), 0);
+
+        for i in 0..100 {
+            slice.add(i).write(i as u32 * 3);
+        }
+
+        assert_eq!(value.read(), 0xdead_beef);
+        assert!((0..100).all(|i| slice.add(i).read() == i as u32 * 3));
+
+        assert!(matches!(heap.alloc_slice::<u64>(usize::MAX), Err(nimix::AllocError::LayoutError)));
+    }
+}
+
+#[test]
+fn size_classes_change_at_their_boundaries() {
+    use nimix::{SizeClass, LARGE_OBJECT_MIN, MEDIUM_OBJECT_MAX, SMALL_OBJECT_MAX};
+
+    let block_capacity = LINE_COUNT * LINE_SIZE;
+
+    assert_eq!(SMALL_OBJECT_MAX, LINE_SIZE);
+    assert_eq!(MEDIUM_OBJECT_MAX, block_capacity);
+    assert_eq!(LARGE_OBJECT_MIN, block_capacity + 1);
+
+    assert_eq!(SizeClass::get_for_size(LINE_SIZE).unwrap(), SizeClass::Small);
+    assert_eq!(SizeClass::get_for_size(LINE_SIZE + 1).unwrap(), SizeClass::Medium);
+    assert_eq!(SizeClass::get_for_size(block_capacity).unwrap(), SizeClass::Medium);
+    assert_eq!(SizeClass::get_for_size(block_capacity + 1).unwrap(), SizeClass::Large);
+
+    let layout = Layout::from_size_align(block_capacity + 1, 8).unwrap();
+
+    assert_eq!(SizeClass::of_layout(&layout).unwrap(), SizeClass::Large);
+    assert_eq!(SizeClass::of_layout(&Layout::new::<u64>()).unwrap(), SizeClass::Small);
+}
+
+#[test]
+fn smaller_lines_are_used_by_every_block() {
+    use nimix::SizeClass;
+    use std::collections::HashSet;
+
+    let mark = NonZero::new(1).unwrap();
+
+    for line_size in [64, 32] {
+        let heap = Heap::new().with_line_size(line_size).unwrap().with_conservative_lines(0);
+        let layout = Layout::from_size_align(line_size, 8).unwrap();
+        let line_count = line_count(BLOCK_SIZE, line_size);
+
+        assert_eq!(heap.can_allocate(layout).unwrap(), SizeClass::Small);
+        assert_eq!(
+            heap.can_allocate(Layout::from_size_align(line_size + 1, 8).unwrap()).unwrap(),
+            SizeClass::Medium
+        );
+
+        let filler = heap.clone();
+        let objects: Vec<*mut u8> = (0..line_count * 3).map(|_| unsafe { filler.alloc(layout).unwrap() }).collect();
+
+        drop(filler);
+
+        for obj in objects.iter() {
+            assert_eq!(heap.block_for(*obj).unwrap().line_marks().len(), line_count);
+        }
+
+        // blocks are filled from the top down, every other run of 32 lines
+        // from the top of each block is kept, leaving holes of 32 lines
+        let (live, dead): (Vec<_>, Vec<_>) =
+            objects.iter().enumerate().partition(|(i, _)| (i % line_count / 32) % 2 == 0);
+
+        for (_, obj) in live.iter() {
+            unsafe { Heap::mark(**obj, layout, mark).unwrap() };
+        }
+
+        unsafe { heap.sweep(mark, || {}) };
+
+        for (_, obj) in live.iter() {
+            let block = heap.block_for(**obj).unwrap();
+            let line = (**obj as usize - block.id().as_ptr() as usize) / line_size;
+
+            assert_eq!(block.line_marks()[line], mark.get());
+        }
+
+        // the freed lines are found again, one object to each
+        let dead: HashSet<usize> = dead.iter().map(|(_, obj)| **obj as usize).collect();
+
+        for _ in 0..dead.len() {
+            let obj = unsafe { heap.alloc(layout).unwrap() };
+
+            assert!(dead.contains(&(obj as usize)));
+        }
+    }
+}
+
+#[test]
+fn line_sizes_are_validated() {
+    for line_size in [48, 16, 0, BLOCK_SIZE] {
+        assert!(matches!(Heap::new().with_line_size(line_size), Err(nimix::AllocError::LayoutError)));
+    }
+
+    // blocks keep the line size they were made with
+    let heap = Heap::new();
+
+    unsafe { heap.alloc(line_layout()).unwrap() };
+
+    assert!(heap.with_line_size(64).is_err());
+
+    let heap = Heap::new();
+    let _clone = heap.clone();
+
+    assert!(heap.with_line_size(64).is_err());
+
+    // 128 byte objects no longer tile a line of 64 bytes
+    let heap = unsafe { Heap::new_homogeneous::<[u64; 16]>() };
+
+    assert!(heap.with_line_size(64).is_err());
+    assert!(unsafe { Heap::new_homogeneous::<[u64; 4]>() }.with_line_size(32).is_ok());
+}
+
+#[test]
+fn small_blocks_are_found_from_their_objects() {
+    use nimix::SizeClass;
+    use std::collections::HashSet;
+
+    const SMALL_BLOCK: usize = 4096;
+
+    let heap = Heap::<SMALL_BLOCK>::new_sized().with_conservative_lines(0);
+    let line_count = line_count(SMALL_BLOCK, LINE_SIZE);
+    let capacity = line_count * LINE_SIZE;
+    let mark = NonZero::new(1).unwrap();
+
+    // medium objects are bounded by the smaller block
+    let past_capacity = Layout::from_size_align(capacity + 1, 8).unwrap();
+
+    assert_eq!(heap.can_allocate(past_capacity).unwrap(), SizeClass::Large);
+    assert_eq!(Heap::new().can_allocate(past_capacity).unwrap(), SizeClass::Medium);
+
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..line_count * 4).map(|_| unsafe { filler.alloc(line_layout()).unwrap() }).collect();
+
+    drop(filler);
+
+    let mut blocks = HashSet::new();
+
+    for obj in objects.iter() {
+        let block = heap.block_for(*obj).unwrap();
+        let base = block.id().as_ptr() as usize;
+
+        // the block is found at its own size, not rounded down to 16KB
+        assert_eq!(base % SMALL_BLOCK, 0);
+        assert!((base..base + capacity).contains(&(*obj as usize)));
+        assert_eq!(block.line_marks().len(), line_count);
+
+        blocks.insert(base);
+    }
+
+    assert_eq!(blocks.len(), 4);
+
+    let (live, dead): (Vec<_>, Vec<_>) = objects.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+
+    for (_, obj) in live.iter() {
+        unsafe { Heap::mark(**obj, line_layout(), mark).unwrap() };
+    }
+
+    unsafe { heap.sweep(mark, || {}) };
+
+    for (_, obj) in live.iter() {
+        let block = heap.block_for(**obj).unwrap();
+        let line = (**obj as usize - block.id().as_ptr() as usize) / LINE_SIZE;
+
+        assert_eq!(block.line_marks()[line], mark.get());
+    }
+
+    for (_, obj) in dead.iter() {
+        let block = heap.block_for(**obj).unwrap();
+        let line = (**obj as usize - block.id().as_ptr() as usize) / LINE_SIZE;
+
+        assert_ne!(block.line_marks()[line], mark.get());
+    }
+}
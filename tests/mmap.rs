@@ -0,0 +1,84 @@
+#![cfg(all(feature = "mmap", target_os = "linux"))]
+
+use nimix::{Heap, MmapBacking};
+use std::alloc::Layout;
+use std::num::NonZero;
+
+// The resident set size of the process, in bytes.
+fn rss() -> usize {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+    let pages: usize = statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+
+    pages * unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[test]
+fn swept_blocks_return_their_pages() {
+    const TRANSIENT: usize = 64 * 1024 * 1024;
+
+    let heap = Heap::with_backing(MmapBacking);
+    let layout = Layout::new::<[u64; 16]>();
+    let large = Layout::new::<[u64; 1024 * 16]>();
+    let baseline = rss();
+
+    let filler = heap.clone();
+
+    for _ in 0..(TRANSIENT / 2 / layout.size()) {
+        unsafe { filler.alloc(layout).unwrap().write_bytes(1, layout.size()) };
+    }
+
+    for _ in 0..(TRANSIENT / 2 / large.size()) {
+        unsafe { filler.alloc(large).unwrap().write_bytes(1, large.size()) };
+    }
+
+    drop(filler);
+
+    assert!(rss() > baseline + TRANSIENT / 2);
+
+    unsafe { heap.sweep(NonZero::new(1).unwrap(), || {}) };
+
+    // only the free blocks kept for reuse, and the pooled large objects,
+    // stay resident
+    assert!(rss() < baseline + TRANSIENT / 8);
+}
+
+#[test]
+fn huge_page_chunks_hold_many_blocks() {
+    use nimix::HugePageBacking;
+
+    const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+    let heap = Heap::with_backing(HugePageBacking::new());
+    let mark = NonZero::new(1).unwrap();
+    let layout = Layout::new::<[u64; 16]>();
+    let filler = heap.clone();
+    let objects: Vec<*mut u8> = (0..2000)
+        .map(|i| unsafe {
+            let obj = filler.alloc(layout).unwrap();
+
+            (obj as *mut u64).write(i);
+            obj
+        })
+        .collect();
+
+    drop(filler);
+
+    // the blocks were carved out of a single chunk
+    let chunk = objects[0] as usize / HUGE_PAGE_SIZE;
+
+    assert!(heap.stats().block_count > 1);
+    assert!(objects.iter().all(|obj| *obj as usize / HUGE_PAGE_SIZE == chunk));
+
+    unsafe {
+        for obj in objects.iter().step_by(2) {
+            Heap::mark(*obj, layout, mark).unwrap();
+        }
+
+        heap.sweep(mark, || {});
+    }
+
+    for (i, obj) in objects.iter().enumerate().step_by(2) {
+        assert_eq!(unsafe { *(*obj as *const u64) }, i as u64);
+        assert!(heap.block_for(*obj).is_some());
+    }
+}
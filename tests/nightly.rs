@@ -0,0 +1,51 @@
+#![cfg(feature = "nightly")]
+#![feature(allocator_api)]
+
+use nimix::{Heap, NimixAlloc};
+use std::alloc::Layout;
+use std::num::NonZero;
+
+#[test]
+fn marked_vec_survives_sweep() {
+    let heap = Heap::new();
+    let mark = NonZero::new(1).unwrap();
+    let mut vec: Vec<u32, NimixAlloc> = Vec::new_in(NimixAlloc::new(&heap));
+
+    for i in 0..10_000 {
+        vec.push(i);
+    }
+
+    let layout = Layout::array::<u32>(vec.capacity()).unwrap();
+
+    unsafe {
+        Heap::mark(vec.as_mut_ptr() as *mut u8, layout, mark).unwrap();
+        heap.sweep(mark, || {});
+    }
+
+    // allocate over anything the sweep reclaimed
+    let mut other: Vec<Vec<u8, NimixAlloc>> = vec![];
+
+    for _ in 0..100 {
+        let mut bytes = Vec::with_capacity_in(1000, NimixAlloc::new(&heap));
+
+        bytes.resize(1000, 0xFF);
+        other.push(bytes);
+    }
+
+    assert!(vec.iter().copied().eq(0..10_000));
+}
+
+#[test]
+fn boxes_and_small_vecs_allocate() {
+    let heap = Heap::new();
+    let alloc = NimixAlloc::new(&heap);
+    let boxed = Box::new_in([7u64; 4], &alloc);
+    let mut small: Vec<u8, _> = Vec::new_in(&alloc);
+
+    small.extend_from_slice(b"nimix");
+    small.shrink_to_fit();
+
+    assert_eq!(*boxed, [7; 4]);
+    assert_eq!(small, b"nimix");
+    assert_eq!(heap.size(), 16 * 1024);
+}
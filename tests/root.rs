@@ -0,0 +1,61 @@
+use nimix::Heap;
+use std::alloc::Layout;
+use std::num::NonZero;
+use std::thread;
+
+// The crate root functions share one heap across the process, so everything
+// using them lives in this one test.
+#[test]
+fn root_heap_is_shared_by_threads() {
+    let layout = Layout::new::<u64>();
+    let mark = NonZero::new(1).unwrap();
+
+    let workers: Vec<_> = (0..4u64)
+        .map(|n| {
+            thread::spawn(move || {
+                (0..5000u64)
+                    .map(|i| {
+                        let ptr = unsafe { nimix::alloc(layout).unwrap() } as *mut u64;
+                        let value = (n << 32) | i;
+
+                        unsafe { ptr.write(value) };
+
+                        (ptr as usize, value)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let objects: Vec<(usize, u64)> = workers
+        .into_iter()
+        .flat_map(|worker| worker.join().unwrap())
+        .collect();
+
+    let mut addrs: Vec<usize> = objects.iter().map(|&(addr, _)| addr).collect();
+
+    addrs.sort_unstable();
+    addrs.dedup();
+    assert_eq!(addrs.len(), objects.len());
+
+    let live: Vec<(usize, u64)> = objects.iter().copied().filter(|&(_, value)| value % 2 == 0).collect();
+
+    for &(addr, _) in live.iter() {
+        unsafe { Heap::mark(addr as *mut u8, layout, mark).unwrap() };
+    }
+
+    let mut called = false;
+
+    unsafe { nimix::sweep(mark, || called = true) };
+
+    assert!(called);
+
+    // the dead objects are handed out again, without touching the live ones
+    for _ in 0..objects.len() {
+        unsafe { (nimix::alloc(layout).unwrap() as *mut u64).write(u64::MAX) };
+    }
+
+    for &(addr, value) in live.iter() {
+        assert_eq!(unsafe { (addr as *const u64).read() }, value);
+    }
+}